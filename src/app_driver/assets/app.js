@@ -0,0 +1,426 @@
+// The web client of Brume: a file browser, the uploads, the login and the
+// group administration. It only uses the `/_api.json/{service}` and
+// `/_file` endpoints, the user token is kept in its HttpOnly cookie. The
+// session is the user returned by the server at the login, it ends with the
+// page or at the first `401`.
+"use strict";
+
+const LEVELS = ["None", "SeeData", "EditData", "Admin", "SuperAdmin"];
+
+/* API */
+
+// The errors are JSON, with a stable code and the invalid fields.
+class ApiError extends Error {
+  constructor(status, text) {
+    let error = null;
+    try {
+      error = JSON.parse(text);
+    } catch {
+      // A network error or a proxy page.
+    }
+    const details = (error?.details || []).map((detail) =>
+      detail.field ? `${detail.field}: ${detail.message}` : detail.message,
+    );
+    super([error?.message || text.trim() || `HTTP ${status}`, ...details].join(". "));
+    this.status = status;
+    this.code = error?.code;
+  }
+}
+
+async function api(service, data) {
+  const response = await fetch(`/_api.json/${service}`, {
+    method: "POST",
+    headers: { "Content-Type": "application/json", Accept: "application/json" },
+    body: data === undefined ? "" : JSON.stringify(data),
+  });
+  const text = await response.text();
+  if (!response.ok) {
+    throw new ApiError(response.status, text);
+  }
+  return text ? JSON.parse(text) : null;
+}
+
+// Upload one file in the folder, `progress` receives the sent fraction.
+function upload(file, parent, group, progress) {
+  const query = new URLSearchParams({ parent, name: file.name });
+  if (group) {
+    query.set("group", group);
+  }
+  return new Promise((resolve, reject) => {
+    const xhr = new XMLHttpRequest();
+    xhr.open("POST", `/_file?${query}`);
+    xhr.setRequestHeader("Accept", "application/json");
+    xhr.upload.onprogress = (event) => {
+      if (event.lengthComputable) {
+        progress(event.loaded / event.total);
+      }
+    };
+    xhr.onload = () =>
+      xhr.status === 200
+        ? resolve(JSON.parse(xhr.responseText))
+        : reject(new ApiError(xhr.status, xhr.responseText));
+    xhr.onerror = () => reject(new ApiError(0, "Network error"));
+    xhr.send(file);
+  });
+}
+
+/* SESSION */
+
+let session = null;
+
+function currentUser() {
+  return session;
+}
+
+function setUser(user) {
+  session = user;
+  renderSession();
+}
+
+function renderSession() {
+  const session = document.getElementById("session");
+  const user = currentUser();
+  if (!user) {
+    session.replaceChildren(el("a", { href: "#/login" }, "Log in"));
+    return;
+  }
+  const logout = el("button", {}, "Log out");
+  logout.onclick = async () => {
+    await api("auth.logout").catch(() => {});
+    setUser(null);
+    location.hash = "#/login";
+  };
+  session.replaceChildren(`${user.name} `, logout);
+}
+
+/* DOM */
+
+// Create an element, the children are nodes or texts.
+function el(tag, attributes, ...children) {
+  const element = document.createElement(tag);
+  for (const [name, value] of Object.entries(attributes)) {
+    element.setAttribute(name, value);
+  }
+  element.append(...children);
+  return element;
+}
+
+function button(text, onclick, className) {
+  const element = el("button", className ? { class: className } : {}, text);
+  element.type = "button";
+  element.onclick = () => onclick().catch(showError);
+  return element;
+}
+
+function levelSelect(selected, max) {
+  const select = el("select", {});
+  for (const level of LEVELS.slice(1, LEVELS.indexOf(max) + 1)) {
+    select.append(el("option", { value: level }, level));
+  }
+  select.value = selected;
+  return select;
+}
+
+function message(text, isError) {
+  const element = document.getElementById("message");
+  element.textContent = text;
+  element.className = isError ? "error" : "";
+  element.hidden = !text;
+}
+
+function showError(err) {
+  if (err instanceof ApiError && err.status === 401) {
+    setUser(null);
+  }
+  message(err.message, true);
+}
+
+function size(bytes) {
+  const units = ["B", "KiB", "MiB", "GiB", "TiB"];
+  let i = 0;
+  while (bytes >= 1024 && i < units.length - 1) {
+    bytes /= 1024;
+    i++;
+  }
+  return `${i ? bytes.toFixed(1) : bytes} ${units[i]}`;
+}
+
+function date(secs) {
+  return new Date(secs * 1000).toLocaleString();
+}
+
+/* VIEWS */
+
+function viewLogin(view) {
+  const login = el("input", { name: "login", placeholder: "Login", autocomplete: "username", required: "" });
+  const password = el("input", { name: "password", type: "password", placeholder: "Password", autocomplete: "current-password", required: "" });
+  const form = el("form", { class: "login" }, login, password, el("button", {}, "Log in"));
+  form.onsubmit = async (event) => {
+    event.preventDefault();
+    try {
+      setUser(await api("auth.login", { login: login.value, password: password.value }));
+      location.hash = "#/files/0";
+    } catch (err) {
+      showError(err);
+    }
+  };
+  view.replaceChildren(el("h1", {}, "Log in"), form);
+  login.focus();
+}
+
+// The groups of the user, with the level known from the server.
+function userGroups() {
+  return Object.entries(currentUser()?.groups || {}).map(([id, level]) => ({ id: Number(id), level }));
+}
+
+// The editable groups, for the root folders.
+function editableGroups() {
+  return userGroups().filter((group) => LEVELS.indexOf(group.level) >= LEVELS.indexOf("EditData"));
+}
+
+async function viewFiles(view, id) {
+  const path = [];
+  for (let parent = id; parent !== 0; ) {
+    const node = await api("node.get", { id: parent });
+    path.unshift(node);
+    parent = node.parent;
+  }
+  const children = await api("folder.list", { id });
+  children.sort((a, b) => (a.kind === b.kind ? a.name.localeCompare(b.name) : a.kind === "folder" ? -1 : 1));
+  const groups = id === 0 ? editableGroups() : [];
+  const refresh = () => viewFiles(view, id).catch(showError);
+
+  const breadcrumb = el("p", {}, el("a", { href: "#/files/0" }, "Files"));
+  for (const node of path) {
+    breadcrumb.append(" / ", el("a", { href: `#/files/${node.id}` }, node.name));
+  }
+
+  const rows = children.map((node) => {
+    const link =
+      node.kind === "folder"
+        ? el("a", { href: `#/files/${node.id}` }, `${node.name}/`)
+        : el("a", { href: `/_file/${node.id}`, target: "_blank" }, node.name);
+    const rename = button("Rename", async () => {
+      const name = prompt("New name", node.name);
+      if (name && name !== node.name) {
+        await api("node.move", { id: node.id, parent: node.parent, name });
+        await refresh();
+      }
+    });
+    const remove = button(
+      "Delete",
+      async () => {
+        if (confirm(`Move "${node.name}" to the trash?`)) {
+          await api("node.delete", { id: node.id });
+          await refresh();
+        }
+      },
+      "danger",
+    );
+    return el(
+      "tr",
+      {},
+      el("td", {}, link),
+      el("td", {}, node.kind === "file" ? size(node.size) : ""),
+      el("td", { class: "muted" }, date(node.modified)),
+      el("td", { class: "actions" }, rename, " ", remove),
+    );
+  });
+  const table = el(
+    "table",
+    {},
+    el("thead", {}, el("tr", {}, el("th", {}, "Name"), el("th", {}, "Size"), el("th", {}, "Modified"), el("th", {}))),
+    el("tbody", {}, ...rows),
+  );
+
+  // A root folder needs the group that owns it.
+  const group = el("select", { title: "Group" });
+  for (const entry of groups) {
+    group.append(el("option", { value: entry.id }, `Group #${entry.id}`));
+  }
+  const name = el("input", { placeholder: "New folder", required: "" });
+  const create = el("form", {}, name, id === 0 ? group : "", el("button", {}, "Create"));
+  create.onsubmit = async (event) => {
+    event.preventDefault();
+    try {
+      await api("folder.create", { parent: id, group: id === 0 ? Number(group.value) : null, name: name.value });
+      await refresh();
+    } catch (err) {
+      showError(err);
+    }
+  };
+
+  const uploads = el("ul", { class: "uploads" });
+  const input = el("input", { type: "file", multiple: "" });
+  const dropzone = el("div", { class: "dropzone" }, "Drop files here, or ", input);
+  const send = async (files) => {
+    for (const file of files) {
+      const bar = el("progress", { max: 1, value: 0 });
+      uploads.append(el("li", {}, file.name, bar));
+      try {
+        await upload(file, id, id === 0 ? group.value : null, (fraction) => (bar.value = fraction));
+        bar.value = 1;
+      } catch (err) {
+        showError(err);
+      }
+    }
+    await refresh();
+  };
+  input.onchange = () => send([...input.files]);
+  dropzone.ondragover = (event) => {
+    event.preventDefault();
+    dropzone.classList.add("over");
+  };
+  dropzone.ondragleave = () => dropzone.classList.remove("over");
+  dropzone.ondrop = (event) => {
+    event.preventDefault();
+    dropzone.classList.remove("over");
+    send([...event.dataTransfer.files]);
+  };
+
+  const canCreate = id !== 0 || groups.length > 0;
+  view.replaceChildren(
+    breadcrumb,
+    table,
+    children.length ? "" : el("p", { class: "muted" }, "Empty folder"),
+    canCreate ? create : "",
+    canCreate ? dropzone : "",
+    uploads,
+  );
+}
+
+async function viewGroups(view) {
+  const rows = userGroups().map((group) =>
+    el("tr", {}, el("td", {}, el("a", { href: `#/groups/${group.id}` }, `Group #${group.id}`)), el("td", {}, group.level)),
+  );
+  const table = el(
+    "table",
+    {},
+    el("thead", {}, el("tr", {}, el("th", {}, "Group"), el("th", {}, "Your level"))),
+    el("tbody", {}, ...rows),
+  );
+
+  const name = el("input", { placeholder: "New group", required: "" });
+  const create = el("form", {}, name, el("button", {}, "Create"));
+  create.onsubmit = async (event) => {
+    event.preventDefault();
+    try {
+      const group = await api("group.create", { name: name.value });
+      currentUser().groups[group.id] = "SuperAdmin";
+      location.hash = `#/groups/${group.id}`;
+    } catch (err) {
+      showError(err);
+    }
+  };
+
+  const user = currentUser();
+  const isAdmin = user && LEVELS.indexOf(user.level) >= LEVELS.indexOf("Admin");
+  view.replaceChildren(el("h1", {}, "Groups"), table, isAdmin ? create : "");
+}
+
+async function viewGroup(view, id) {
+  const members = await api("group.members", { group: id });
+  // The level of the user in the group, as the server has it now.
+  const self = members.find((member) => member.id === currentUser().id);
+  if (!self) {
+    throw new ApiError(404, "You are not a member of this group");
+  }
+  currentUser().groups[id] = self.level;
+  const group = { id, name: `Group #${id}`, level: self.level };
+  const refresh = () => viewGroup(view, id).catch(showError);
+
+  const rows = members.map((member) => {
+    const level = levelSelect(member.level, "SuperAdmin");
+    level.onchange = () =>
+      api("group.set_level", { group: id, user: member.id, level: level.value })
+        .then(refresh)
+        .catch(showError);
+    const remove = button(
+      "Remove",
+      async () => {
+        if (confirm(`Remove ${member.name} from the group?`)) {
+          await api("group.remove_member", { group: id, user: member.id });
+          await refresh();
+        }
+      },
+      "danger",
+    );
+    return el(
+      "tr",
+      {},
+      el("td", {}, member.name),
+      el("td", { class: "muted" }, `${member.login} #${member.id}`),
+      el("td", {}, level),
+      el("td", { class: "actions" }, remove),
+    );
+  });
+  const table = el(
+    "table",
+    {},
+    el("thead", {}, el("tr", {}, el("th", {}, "Name"), el("th", {}, "Login"), el("th", {}, "Level"), el("th", {}))),
+    el("tbody", {}, ...rows),
+  );
+
+  const user = el("input", { type: "number", min: 1, placeholder: "User id", required: "" });
+  const level = levelSelect("SeeData", group.level);
+  const add = el("form", {}, user, level, el("button", {}, "Add member"));
+  add.onsubmit = async (event) => {
+    event.preventDefault();
+    try {
+      await api("group.add_member", { group: id, user: Number(user.value), level: level.value });
+      await refresh();
+    } catch (err) {
+      showError(err);
+    }
+  };
+
+  const remove = button(
+    "Delete the group",
+    async () => {
+      if (confirm(`Delete the group ${group.name}? It must not own any file.`)) {
+        await api("group.delete", { group: id });
+        delete currentUser().groups[id];
+        location.hash = "#/groups";
+      }
+    },
+    "danger",
+  );
+
+  const isAdmin = LEVELS.indexOf(group.level) >= LEVELS.indexOf("Admin");
+  view.replaceChildren(
+    el("p", {}, el("a", { href: "#/groups" }, "Groups")),
+    el("h1", {}, group.name),
+    table,
+    isAdmin ? add : "",
+    group.level === "SuperAdmin" ? remove : "",
+  );
+}
+
+/* ROUTER */
+
+async function route() {
+  const view = document.getElementById("view");
+  const [, page = "files", arg] = location.hash.split("/");
+  message("");
+  if (page !== "login" && !currentUser()) {
+    location.hash = "#/login";
+    return;
+  }
+  try {
+    switch (page) {
+      case "login":
+        return viewLogin(view);
+      case "groups":
+        return arg ? await viewGroup(view, Number(arg)) : await viewGroups(view);
+      default:
+        return await viewFiles(view, Number(arg) || 0);
+    }
+  } catch (err) {
+    view.replaceChildren();
+    showError(err);
+  }
+}
+
+window.addEventListener("hashchange", route);
+renderSession();
+route();
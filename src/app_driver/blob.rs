@@ -0,0 +1,436 @@
+//! Content of the files, stored on disk and addressed by its SHA-256 hash.
+//! The same content is stored only once, and shared by all the files.
+//!
+//! The references to a blob are counted in the storage, the blob is removed
+//! by the garbage collector when it is no more referenced.
+
+use crate::{
+    app_driver::{
+        State,
+        error::{err_storage, err_sync_fail},
+    },
+    io_http::ByteStream,
+    *,
+};
+use axum::http::StatusCode;
+use crypto::digest::Digest;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::AsyncWriteExt;
+
+#[derive(Debug)]
+pub struct BlobStore {
+    root: PathBuf,
+    /// Counter to get unique temporary file name.
+    tmp_counter: AtomicU64,
+}
+
+/// A blob received in a temporary file, removed if not stored.
+#[derive(Debug)]
+pub struct Received {
+    tmp: PathBuf,
+    /// Hexadecimal SHA-256 hash of the content.
+    pub hash: String,
+    /// Total size in bytes.
+    pub size: u64,
+    /// First bytes of the content, to detect the type.
+    pub head: Vec<u8>,
+}
+
+impl Drop for Received {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.tmp);
+    }
+}
+
+impl BlobStore {
+    const HEAD_LEN: usize = 512;
+
+    /// Open the store, and remove the temporary files of a previous run.
+    pub fn open(root: impl Into<PathBuf>) -> Result<Self> {
+        let root: PathBuf = root.into();
+        let _ = std::fs::remove_dir_all(root.join("tmp"));
+        std::fs::create_dir_all(root.join("tmp")).map_err(err_storage)?;
+        Ok(Self {
+            root,
+            tmp_counter: AtomicU64::new(0),
+        })
+    }
+
+    /// The path of a blob: `root/ab/abcdef...`
+    fn path(&self, hash: &str) -> PathBuf {
+        self.root.join(&hash[..2]).join(hash)
+    }
+
+    /// Write the stream into a temporary file.
+    /// Fail if the content is longer than `max` bytes.
+    pub async fn receive(&self, input: &mut dyn ByteStream, max: u64) -> Result<Received> {
+        let tmp_id = self.tmp_counter.fetch_add(1, Ordering::Relaxed);
+        let mut received = Received {
+            tmp: self.root.join("tmp").join(tmp_id.to_string()),
+            hash: String::new(),
+            size: 0,
+            head: Vec::new(),
+        };
+
+        let mut hasher = crypto::sha2::Sha256::new();
+        let mut file = tokio::fs::File::create(&received.tmp)
+            .await
+            .map_err(err_storage)?;
+        while let Some(chunk) = input.chunk().await? {
+            received.size += chunk.len() as u64;
+            if received.size > max {
+                return Err(WrapError::http(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    "The file is too big",
+                ));
+            }
+            if received.head.len() < Self::HEAD_LEN {
+                let len = chunk.len().min(Self::HEAD_LEN - received.head.len());
+                received.head.extend_from_slice(&chunk[..len]);
+            }
+            hasher.input(&chunk);
+            file.write_all(&chunk).await.map_err(err_storage)?;
+        }
+        file.sync_all().await.map_err(err_storage)?;
+        received.hash = hasher.result_str();
+
+        Ok(received)
+    }
+
+    /// Move the received content in the store, if the same content is not
+    /// already stored.
+    fn commit(&self, received: Received) -> Result<()> {
+        let path = self.path(&received.hash);
+        if path.exists() {
+            return Ok(());
+        }
+        let dir = path.parent().unwrap_or(&self.root);
+        std::fs::create_dir_all(dir).map_err(err_storage)?;
+        std::fs::rename(&received.tmp, &path).map_err(err_storage)?;
+        std::fs::File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .map_err(err_storage)
+    }
+
+    pub async fn open_file(&self, hash: &str) -> Result<tokio::fs::File> {
+        tokio::fs::File::open(self.path(hash))
+            .await
+            .map_err(err_storage)
+    }
+
+    pub async fn read(&self, hash: &str) -> Result<Vec<u8>> {
+        tokio::fs::read(self.path(hash)).await.map_err(err_storage)
+    }
+
+    fn remove(&self, hash: &str) -> Result<()> {
+        match std::fs::remove_file(self.path(hash)) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err_storage(err)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Compute the hash and the size of a stored blob, `None` if it is
+    /// missing.
+    pub async fn hash(&self, hash: &str) -> Result<Option<(String, u64)>> {
+        use tokio::io::AsyncReadExt;
+        let mut file = match tokio::fs::File::open(self.path(hash)).await {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err_storage(err)),
+        };
+        let mut hasher = crypto::sha2::Sha256::new();
+        let mut buffer = vec![0u8; 64 * 1024];
+        let mut size = 0;
+        loop {
+            let len = file.read(&mut buffer).await.map_err(err_storage)?;
+            if len == 0 {
+                break;
+            }
+            hasher.input(&buffer[..len]);
+            size += len as u64;
+        }
+        Ok(Some((hasher.result_str(), size)))
+    }
+
+    /// Store a content, that must match its hash.
+    pub async fn import(&self, hash: &str, input: &mut dyn ByteStream) -> Result<()> {
+        let received = self.receive(input, u64::MAX).await?;
+        match received.hash == hash {
+            true => self.commit(received),
+            false => Err(WrapError::http(
+                StatusCode::BAD_REQUEST,
+                "The blob content does not match its hash",
+            )),
+        }
+    }
+
+    /// List the hash of all the blobs on the disk.
+    pub fn list(&self) -> Result<Vec<String>> {
+        let mut hashes = Vec::new();
+        for dir in std::fs::read_dir(&self.root).map_err(err_storage)? {
+            let dir = dir.map_err(err_storage)?;
+            if dir.file_name().len() != 2 {
+                continue;
+            }
+            for entry in std::fs::read_dir(dir.path()).map_err(err_storage)? {
+                if let Some(name) = entry.map_err(err_storage)?.file_name().to_str() {
+                    hashes.push(name.to_string());
+                }
+            }
+        }
+        Ok(hashes)
+    }
+}
+
+/* REFERENCE COUNTING */
+
+/// Reference count of a stored blob.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub struct BlobRef {
+    /// Size in bytes.
+    pub size: u64,
+    pub refs: u64,
+}
+
+/// All the blob references, indexed by hash.
+pub type BlobRefs = std::collections::BTreeMap<String, BlobRef>;
+
+fn key(hash: &str) -> String {
+    format!("blob/{hash}")
+}
+
+/// Load all the blob references from the storage.
+pub fn init(server: &State) -> Result<()> {
+    let mut refs = server.blob_refs.lock().map_err(err_sync_fail)?;
+    for hash in server.storage.list("blob")? {
+        if let Some(blob_ref) = server.storage.load::<BlobRef>(&key(&hash))? {
+            refs.insert(hash, blob_ref);
+        }
+    }
+    Ok(())
+}
+
+/// Store the received content and count a new reference to it.
+/// Return the hash of the blob.
+pub fn store(server: &State, received: Received) -> Result<String> {
+    let mut refs = server.blob_refs.lock().map_err(err_sync_fail)?;
+    let hash = received.hash.clone();
+    let size = received.size;
+    server.blobs.commit(received)?;
+
+    let blob_ref = refs
+        .entry(hash.clone())
+        .or_insert(BlobRef { size, refs: 0 });
+    blob_ref.refs += 1;
+    server.storage.save(&key(&hash), blob_ref)?;
+    Ok(hash)
+}
+
+/// Count a new reference to a stored blob.
+pub fn acquire(server: &State, hash: &str) -> Result<()> {
+    let mut refs = server.blob_refs.lock().map_err(err_sync_fail)?;
+    let blob_ref = refs.get_mut(hash).ok_or_else(err_unknown_blob)?;
+    blob_ref.refs += 1;
+    server.storage.save(&key(hash), blob_ref)
+}
+
+/// Remove a reference to a blob.
+/// The content is removed later by [`collect`].
+pub fn release(server: &State, hash: &str) -> Result<()> {
+    let mut refs = server.blob_refs.lock().map_err(err_sync_fail)?;
+    let blob_ref = refs.get_mut(hash).ok_or_else(err_unknown_blob)?;
+    blob_ref.refs = blob_ref.refs.saturating_sub(1);
+    server.storage.save(&key(hash), blob_ref)
+}
+
+/// Result of a garbage collection.
+#[derive(Debug, Clone, Copy, Default, Serialize, PartialEq, JsonSchema)]
+pub struct Collected {
+    /// Number of removed blobs.
+    pub blobs: u64,
+    /// Freed size in bytes.
+    pub size: u64,
+}
+
+/// Remove the unreferenced blobs, and the blobs on disk never referenced
+/// (after a crash during a store).
+pub fn collect(server: &State) -> Result<Collected> {
+    let mut refs = server.blob_refs.lock().map_err(err_sync_fail)?;
+    let mut collected = Collected::default();
+
+    let unused: Vec<String> = refs
+        .iter()
+        .filter(|(_, blob_ref)| blob_ref.refs == 0)
+        .map(|(hash, _)| hash.clone())
+        .collect();
+    for hash in unused {
+        server.blobs.remove(&hash)?;
+        server.storage.remove(&key(&hash))?;
+        if let Some(blob_ref) = refs.remove(&hash) {
+            collected.blobs += 1;
+            collected.size += blob_ref.size;
+        }
+    }
+
+    for hash in server.blobs.list()? {
+        if !refs.contains_key(&hash) {
+            collected.size += std::fs::metadata(server.blobs.path(&hash)).map_or(0, |m| m.len());
+            collected.blobs += 1;
+            server.blobs.remove(&hash)?;
+        }
+    }
+
+    Ok(collected)
+}
+
+/// Result of a consistency check of the blobs.
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct Checked {
+    /// Number of referenced blobs.
+    pub blobs: u64,
+    /// Referenced blobs missing on the disk.
+    pub missing: Vec<String>,
+    /// Blobs with a content that does not match their hash or size.
+    pub corrupted: Vec<String>,
+    /// Blobs with a wrong reference count: hash, stored count, and the
+    /// number of file revisions using it.
+    pub wrong_refs: Vec<(String, u64, u64)>,
+    /// Blobs on the disk without reference, removed by the next garbage
+    /// collection.
+    pub orphans: Vec<String>,
+}
+
+impl Checked {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.corrupted.is_empty() && self.wrong_refs.is_empty()
+    }
+}
+
+/// Check the content of all the blobs and their reference counts against
+/// the file revisions. With `repair`, the reference counts are fixed.
+pub async fn check(server: &State, repair: bool) -> Result<Checked> {
+    let mut counted = std::collections::BTreeMap::<String, u64>::new();
+    for name in server.storage.list("version")? {
+        let Ok(id) = name.parse() else {
+            continue;
+        };
+        for revision in super::hand_files::load(server, id)? {
+            *counted.entry(revision.blob).or_default() += 1;
+        }
+    }
+    let refs = server.blob_refs.lock().map_err(err_sync_fail)?.clone();
+
+    let mut checked = Checked::default();
+    for (hash, blob_ref) in &refs {
+        let count = counted.get(hash).copied().unwrap_or(0);
+        if blob_ref.refs != count {
+            checked
+                .wrong_refs
+                .push((hash.clone(), blob_ref.refs, count));
+        }
+        if count == 0 {
+            continue;
+        }
+        checked.blobs += 1;
+        match server.blobs.hash(hash).await? {
+            None => checked.missing.push(hash.clone()),
+            Some(found) if found != (hash.clone(), blob_ref.size) => {
+                checked.corrupted.push(hash.clone())
+            }
+            Some(_) => {}
+        }
+    }
+    for (hash, &count) in &counted {
+        if !refs.contains_key(hash) {
+            checked.wrong_refs.push((hash.clone(), 0, count));
+            checked.blobs += 1;
+            if server.blobs.hash(hash).await?.is_none() {
+                checked.missing.push(hash.clone());
+            }
+        }
+    }
+    for hash in server.blobs.list()? {
+        if !refs.contains_key(&hash) && !counted.contains_key(&hash) {
+            checked.orphans.push(hash);
+        }
+    }
+
+    if repair {
+        let mut refs = server.blob_refs.lock().map_err(err_sync_fail)?;
+        for (hash, _, count) in &checked.wrong_refs {
+            let size = match refs.get(hash) {
+                Some(blob_ref) => blob_ref.size,
+                None => std::fs::metadata(server.blobs.path(hash)).map_or(0, |m| m.len()),
+            };
+            let blob_ref = refs
+                .entry(hash.clone())
+                .or_insert(BlobRef { size, refs: 0 });
+            blob_ref.refs = *count;
+            server.storage.save(&key(hash), blob_ref)?;
+        }
+    }
+    Ok(checked)
+}
+
+fn err_unknown_blob() -> WrapError {
+    WrapError::http(StatusCode::INTERNAL_SERVER_ERROR, "Unknown blob")
+}
+
+#[tokio::test]
+async fn deduplication() {
+    use crate::io_http::FileTarget;
+
+    let server = crate::app_driver::test_state("deduplication");
+    let new_file = |name: &str| FileTarget::New {
+        parent: 0,
+        group: Some(42),
+        name: name.to_string(),
+    };
+    let put = async |target| {
+        let mut body = axum::body::Body::from("Same content");
+        super::hand_files::put(&server, UserToken::DEV_EDITOR, target, &mut body)
+            .await
+            .unwrap()
+    };
+
+    let a = put(new_file("a")).await;
+    let b = put(new_file("b")).await;
+    assert_eq!(1, server.blobs.list().unwrap().len());
+    let hash = server.blobs.list().unwrap().remove(0);
+    assert_eq!(2, server.blob_refs.lock().unwrap()[&hash].refs);
+
+    // The old revision keeps its reference.
+    put(FileTarget::Replace(a.id)).await;
+    assert_eq!(3, server.blob_refs.lock().unwrap()[&hash].refs);
+
+    let checked = check(&server, false).await.unwrap();
+    assert!(checked.is_ok() && checked.orphans.is_empty());
+    server
+        .blob_refs
+        .lock()
+        .unwrap()
+        .get_mut(&hash)
+        .unwrap()
+        .refs = 5;
+    let checked = check(&server, true).await.unwrap();
+    assert_eq!(vec![(hash.clone(), 5, 3)], checked.wrong_refs);
+    assert!(check(&server, false).await.unwrap().is_ok());
+
+    for id in [a.id, b.id] {
+        let request = crate::io_http::DataRequest {
+            user: UserToken::DEV_EDITOR,
+            dto: super::hand_files::NodeId { id },
+        };
+        super::hand_files::trash::delete(&server, request)
+            .await
+            .unwrap();
+    }
+    assert_eq!(3, server.blob_refs.lock().unwrap()[&hash].refs);
+    super::hand_files::trash::purge(&server, |_| true).unwrap();
+    assert_eq!(0, server.blob_refs.lock().unwrap()[&hash].refs);
+    assert_eq!(Collected { blobs: 1, size: 12 }, collect(&server).unwrap());
+    assert!(server.blobs.list().unwrap().is_empty());
+}
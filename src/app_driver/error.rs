@@ -2,13 +2,30 @@ use crate::*;
 use axum::http::StatusCode;
 
 pub fn err_sync_fail(_: impl std::error::Error) -> WrapError {
-    WrapError::http(StatusCode::INTERNAL_SERVER_ERROR, "internal sync fail")
+    WrapError::http(StatusCode::INTERNAL_SERVER_ERROR, "internal sync fail").with_code("sync_fail")
 }
 
-pub fn err_empty_values(entries: &'static str) -> WrapError {
+pub fn err_storage<E: std::error::Error + Send + Sync + 'static>(err: E) -> WrapError {
+    WrapError::http(StatusCode::INTERNAL_SERVER_ERROR, "storage fail")
+        .with_code("storage_fail")
+        .add_err(err)
+}
+
+pub fn err_forbidden() -> WrapError {
     WrapError::http(
-        StatusCode::BAD_REQUEST,
-        "Some values are empty in the data body",
+        StatusCode::FORBIDDEN,
+        "You can not access to this resources",
     )
-    .add_err(WrapError::new(entries))
+    .with_code("forbidden")
+}
+
+pub fn err_not_found(entry: &'static str) -> WrapError {
+    WrapError::http(StatusCode::NOT_FOUND, "The resource does not exist")
+        .with_code("not_found")
+        .add_err(WrapError::new(entry))
+}
+
+pub fn err_unknown_operation() -> WrapError {
+    WrapError::http(StatusCode::NOT_FOUND, "The operation does not exist")
+        .with_code("unknown_operation")
 }
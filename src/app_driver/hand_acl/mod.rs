@@ -0,0 +1,701 @@
+//! The access control lists: the users and the groups that can access a
+//! resource, in addition to its owning group.
+//!
+//! An entry gives a level to a user, or to the members of a group their
+//! level in the group up to the level of the entry. A deny entry limits the
+//! level under its level, whatever the other entries give.
+//!
+//! A file or a folder inherits the lists of its folders. For each user or
+//! group, the nearest entry overrides the farther ones, so a folder can deny
+//! what its parent allows, or the reverse. An entry of the user overrides
+//! its groups, the owning group too. The lists are resolved at each request,
+//! so a change applies at once, without new tokens.
+//!
+//! The home page has no owning group: the server administrators and the
+//! principals of its list edit it.
+//!
+//! The administrators of the owning group always change the list, a deny
+//! entry can not lock them out. The other administrators can not remove
+//! their own level.
+
+use crate::{
+    app_driver::{
+        State,
+        error::{err_forbidden, err_not_found, err_sync_fail},
+        hand_files, hand_home, hand_user, hand_wiki,
+    },
+    io_http::{DTO, DataRequest, DataResponseResult, Validator, data_response_ok},
+    *,
+};
+use axum::http::StatusCode;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// The entries of a resource.
+pub type Acl = Vec<AclEntry>;
+
+/// Who an entry applies to, `{"user": 3}` or `{"group": 42}`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Principal {
+    User(u32),
+    Group(u32),
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct AclEntry {
+    #[serde(flatten)]
+    pub principal: Principal,
+    /// The highest level given, or the lowest level denied.
+    pub level: UserLevel,
+    /// Deny the level and the higher ones.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deny: bool,
+}
+
+impl AclEntry {
+    /// The level given to the user, if the entry applies to it.
+    fn level_of(&self, user: &UserToken) -> Option<UserLevel> {
+        let level = match self.principal {
+            Principal::User(id) => match id == user.id {
+                true => self.level,
+                false => return None,
+            },
+            Principal::Group(id) => match user.group_level(id) {
+                UserLevel::None => return None,
+                level => min(level, self.level),
+            },
+        };
+        Some(match self.deny {
+            true => below(self.level),
+            false => level,
+        })
+    }
+}
+
+/// Most entries in a list.
+const ACL_MAX: usize = 32;
+
+fn min(a: UserLevel, b: UserLevel) -> UserLevel {
+    if a < b { a } else { b }
+}
+
+fn max(a: UserLevel, b: UserLevel) -> UserLevel {
+    if a > b { a } else { b }
+}
+
+/// The level just under, the highest one left by a deny entry.
+fn below(level: UserLevel) -> UserLevel {
+    match level {
+        UserLevel::None | UserLevel::SeeData => UserLevel::None,
+        UserLevel::EditData => UserLevel::SeeData,
+        UserLevel::Admin => UserLevel::EditData,
+        UserLevel::SuperAdmin => UserLevel::Admin,
+    }
+}
+
+/// An entry that applies to a user.
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct Match {
+    #[serde(flatten)]
+    pub entry: AclEntry,
+    /// The position of the list, `0` for the resource and `1` for its
+    /// folder.
+    pub depth: usize,
+    /// The level given by the entry.
+    pub gives: UserLevel,
+    /// Overridden by a nearer entry of the same principal, or by an entry of
+    /// the user.
+    pub overridden: bool,
+}
+
+/// Resolve the level of a user, and the entries that apply to it.
+/// The lists are the one of the resource first, then the inherited ones.
+pub fn explain(
+    owner: Option<u32>,
+    lists: &[&[AclEntry]],
+    user: &UserToken,
+) -> (UserLevel, Vec<Match>) {
+    let mut matches: Vec<Match> = Vec::new();
+    for (depth, list) in lists.iter().enumerate() {
+        for entry in list.iter() {
+            if let Some(gives) = entry.level_of(user) {
+                let overridden = matches
+                    .iter()
+                    .any(|other| other.entry.principal == entry.principal);
+                matches.push(Match {
+                    entry: *entry,
+                    depth,
+                    gives,
+                    overridden,
+                });
+            }
+        }
+    }
+
+    let own = matches
+        .iter()
+        .find(|m| matches!(m.entry.principal, Principal::User(_)))
+        .map(|m| m.gives);
+    let level = match own {
+        Some(level) => {
+            for m in matches.iter_mut() {
+                m.overridden |= matches!(m.entry.principal, Principal::Group(_));
+            }
+            level
+        }
+        None => {
+            let owner = owner.map_or(UserLevel::None, |group| user.group_level(group));
+            let applied = matches.iter().filter(|m| !m.overridden);
+            let given = applied
+                .clone()
+                .filter(|m| !m.entry.deny)
+                .fold(owner, |best, m| max(best, m.gives));
+            applied
+                .filter(|m| m.entry.deny)
+                .fold(given, |best, m| min(best, m.gives))
+        }
+    };
+    (level, matches)
+}
+
+/// The level of the user on a resource.
+pub fn level(owner: Option<u32>, lists: &[&[AclEntry]], user: &UserToken) -> UserLevel {
+    explain(owner, lists, user).0
+}
+
+/// Check the user has the level on a resource.
+pub fn allow(
+    owner: Option<u32>,
+    lists: &[&[AclEntry]],
+    user: &UserToken,
+    level: UserLevel,
+) -> bool {
+    let got = self::level(owner, lists, user);
+    got != UserLevel::None && level <= got
+}
+
+/// Check the user can replace the list of a resource by the new one, the
+/// lists are the one of the resource first, then the inherited ones.
+pub fn check_set(
+    owner: Option<u32>,
+    old: &[&[AclEntry]],
+    new: &[&[AclEntry]],
+    user: &UserToken,
+) -> Result<()> {
+    if owner.is_some_and(|group| user.group_level(group) >= UserLevel::Admin) {
+        Ok(())
+    } else if !allow(owner, old, user, UserLevel::Admin) {
+        Err(err_forbidden())
+    } else if !allow(owner, new, user, UserLevel::Admin) {
+        Err(WrapError::http(
+            StatusCode::BAD_REQUEST,
+            "The list removes your administration of the resource",
+        )
+        .with_code("acl_lockout"))
+    } else {
+        Ok(())
+    }
+}
+
+/// The lists of a resource, with its owning group.
+pub struct Lists {
+    pub group: Option<u32>,
+    /// The list of the resource first, then the inherited ones.
+    pub lists: Vec<Acl>,
+    /// The server administrators keep their level.
+    pub admins: bool,
+}
+
+impl Lists {
+    /// Find a resource the user can see.
+    fn load(server: &State, resource: &Resource, user: &UserToken) -> Result<Self> {
+        match resource {
+            Resource::Home => Ok(Lists {
+                group: None,
+                lists: vec![hand_home::acl(server)?],
+                admins: true,
+            }),
+            Resource::Wiki { slug } => {
+                let wiki = server.wiki.read().map_err(err_sync_fail)?;
+                let page = wiki.get(slug).ok_or_else(|| err_not_found("wiki page"))?;
+                if !page.allow(user, UserLevel::SeeData) {
+                    return Err(err_forbidden());
+                }
+                Ok(Lists {
+                    group: Some(page.group),
+                    lists: vec![page.acl.clone()],
+                    admins: false,
+                })
+            }
+            Resource::Node { id } => {
+                let nodes = server.nodes.read().map_err(err_sync_fail)?;
+                let node = nodes.get(id).ok_or_else(|| err_not_found("node"))?;
+                if !node.allow(&nodes, user, UserLevel::SeeData) {
+                    return Err(err_forbidden());
+                }
+                Ok(Lists {
+                    group: Some(node.group),
+                    lists: node
+                        .acl_lists(&nodes)
+                        .into_iter()
+                        .map(<[_]>::to_vec)
+                        .collect(),
+                    admins: false,
+                })
+            }
+        }
+    }
+
+    fn explain(&self, user: &UserToken) -> (UserLevel, Vec<Match>) {
+        let lists: Vec<&[AclEntry]> = self.lists.iter().map(Vec::as_slice).collect();
+        let (level, matches) = explain(self.group, &lists, user);
+        match self.admins && user.level >= UserLevel::Admin {
+            true => (user.level, matches),
+            false => (level, matches),
+        }
+    }
+}
+
+/* HANDLERS */
+
+/// A resource with an access control list.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, JsonSchema)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Resource {
+    /// The page at `/`.
+    #[default]
+    Home,
+    Wiki {
+        slug: String,
+    },
+    /// A folder or a file.
+    Node {
+        id: u64,
+    },
+}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct AclTarget {
+    pub resource: Resource,
+}
+
+impl DTO for AclTarget {}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct AclSet {
+    pub resource: Resource,
+    pub acl: Acl,
+}
+
+impl DTO for AclSet {
+    fn check(&self) -> Result<()> {
+        let principals: Vec<Principal> = self.acl.iter().map(|entry| entry.principal).collect();
+        Validator::new()
+            .check(
+                "acl",
+                self.acl.len() <= ACL_MAX,
+                "too_long",
+                "Need at most 32 entries",
+            )
+            .check(
+                "acl",
+                principals
+                    .iter()
+                    .enumerate()
+                    .all(|(i, principal)| !principals[..i].contains(principal)),
+                "not_allowed",
+                "A user or a group can be only once in the list",
+            )
+            .check(
+                "acl",
+                !principals.contains(&Principal::User(0))
+                    && !principals.contains(&Principal::Group(0)),
+                "not_allowed",
+                "The user or group 0 does not exist",
+            )
+            .check(
+                "acl",
+                self.acl.iter().all(|entry| entry.level != UserLevel::None),
+                "not_allowed",
+                "The level can not be None, remove the entry",
+            )
+            .finish()
+    }
+}
+
+/// The access of a resource.
+#[derive(Debug, Clone, Default, Serialize, PartialEq, JsonSchema)]
+pub struct ResourceAcl {
+    pub resource: Resource,
+    /// The group that owns the resource, its members keep their level.
+    pub group: Option<u32>,
+    pub acl: Acl,
+    /// The lists of the folders, the nearest first.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub inherited: Vec<Acl>,
+    /// The level of the user on the resource.
+    pub level: UserLevel,
+}
+
+impl ResourceAcl {
+    fn new(resource: Resource, lists: Lists, user: &UserToken) -> Self {
+        let (level, _) = lists.explain(user);
+        let group = lists.group;
+        let mut lists = lists.lists.into_iter();
+        ResourceAcl {
+            resource,
+            group,
+            acl: lists.next().unwrap_or_default(),
+            inherited: lists.collect(),
+            level,
+        }
+    }
+}
+
+/// Get the list of a resource the user can see.
+pub async fn get(
+    server: &State,
+    request: DataRequest<AclTarget>,
+) -> DataResponseResult<ResourceAcl> {
+    let DataRequest { user, dto } = request;
+    let lists = Lists::load(server, &dto.resource, &user)?;
+    data_response_ok(ResourceAcl::new(dto.resource, lists, &user))
+}
+
+/// Replace the list of a resource, the user need to administrate it.
+pub async fn set(server: &State, request: DataRequest<AclSet>) -> DataResponseResult<ResourceAcl> {
+    let DataRequest { user, dto } = request;
+    let AclSet { resource, acl } = dto;
+    // The lists stored under the lock of the resource.
+    let lists = match &resource {
+        Resource::Home => hand_home::set_acl(server, &user, acl)?,
+        Resource::Wiki { slug } => hand_wiki::set_acl(server, &user, slug, acl)?,
+        Resource::Node { id } => hand_files::set_acl(server, &user, *id, acl)?,
+    };
+    data_response_ok(ResourceAcl::new(resource, lists, &user))
+}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct EffectiveRequest {
+    pub resource: Resource,
+    /// The user to diagnose.
+    pub user: u32,
+}
+
+impl DTO for EffectiveRequest {}
+
+/// The level of a user on a resource, and how it is resolved.
+#[derive(Debug, Clone, Default, Serialize, PartialEq, JsonSchema)]
+pub struct Effective {
+    pub resource: Resource,
+    pub user: u32,
+    /// The owning group, and the level of the user in it.
+    pub group: Option<u32>,
+    pub group_level: UserLevel,
+    /// The server level of the user.
+    pub server_level: UserLevel,
+    /// The entries that apply to the user, the nearest first.
+    pub entries: Vec<Match>,
+    /// The resolved level.
+    pub level: UserLevel,
+}
+
+/// Explain the level of a user on a resource, for the user itself or the
+/// administrators of the resource.
+pub async fn effective(
+    server: &State,
+    request: DataRequest<EffectiveRequest>,
+) -> DataResponseResult<Effective> {
+    let DataRequest { user, dto } = request;
+    let lists = Lists::load(server, &dto.resource, &user)?;
+    if dto.user != user.id && lists.explain(&user).0 < UserLevel::Admin {
+        return Err(err_forbidden());
+    }
+    let target = server
+        .users
+        .read()
+        .map_err(err_sync_fail)?
+        .get(&dto.user)
+        .map(hand_user::User::token)
+        .ok_or_else(|| err_not_found("user"))?;
+
+    let (level, entries) = lists.explain(&target);
+    data_response_ok(Effective {
+        resource: dto.resource,
+        user: target.id,
+        group: lists.group,
+        group_level: lists
+            .group
+            .map_or(UserLevel::None, |group| target.group_level(group)),
+        server_level: target.level,
+        entries,
+        level,
+    })
+}
+#[test]
+fn acl_level() {
+    use Principal::{Group, User};
+    use UserLevel::*;
+    let user = UserToken::DEV_EDITOR;
+    let give = |principal, level| AclEntry {
+        principal,
+        level,
+        deny: false,
+    };
+    let deny = |principal, level| AclEntry {
+        principal,
+        level,
+        deny: true,
+    };
+    let resolved = |owner, lists: &[&[AclEntry]]| level(owner, lists, &user);
+
+    assert_eq!(Admin, resolved(Some(42), &[]));
+    assert_eq!(None, resolved(Some(7), &[]));
+    assert_eq!(None, resolved(Option::None, &[]));
+    // The user is not the group of the same id.
+    assert_eq!(None, resolved(Some(56), &[]));
+    // The entry limits the level of the members.
+    assert_eq!(
+        EditData,
+        resolved(Option::None, &[&[give(Group(42), EditData)]])
+    );
+    assert_eq!(
+        Admin,
+        resolved(Option::None, &[&[give(Group(42), SuperAdmin)]])
+    );
+    // The highest level wins.
+    let groups = [give(Group(7), SeeData), give(Group(0x1234), SuperAdmin)];
+    assert_eq!(SuperAdmin, resolved(Some(42), &[&groups]));
+    // A deny entry limits under its level, even the owning group.
+    let denied = [deny(Group(0x1234), EditData)];
+    assert_eq!(SeeData, resolved(Some(42), &[&denied]));
+    assert_eq!(SeeData, resolved(Some(42), &[&denied, &groups]));
+    // The nearest entry of a group overrides the inherited ones.
+    let granted = [give(Group(0x1234), Admin)];
+    assert_eq!(Admin, resolved(Some(42), &[&granted, &denied]));
+    // The entry of the user overrides its groups.
+    let own = [give(User(56), SeeData)];
+    assert_eq!(SeeData, resolved(Some(42), &[&own, &groups]));
+    assert_eq!(
+        EditData,
+        resolved(Some(7), &[&[give(User(56), EditData)], &denied])
+    );
+    assert_eq!(None, resolved(Some(42), &[&[], &[deny(User(56), SeeData)]]));
+
+    let (_, matches) = explain(
+        Some(42),
+        &[&granted, &denied, &[give(Group(9), Admin)]],
+        &user,
+    );
+    assert_eq!(
+        vec![(0, Admin, false), (1, SeeData, true)],
+        matches
+            .iter()
+            .map(|m| (m.depth, m.gives, m.overridden))
+            .collect::<Vec<_>>()
+    );
+    assert!(allow(Some(7), &[&own], &user, SeeData));
+    assert!(!allow(Some(7), &[&own], &user, EditData));
+
+    let set = |acl| AclSet {
+        resource: Resource::Home,
+        acl,
+    };
+    assert!(
+        set(vec![give(Group(42), EditData), give(User(42), SeeData)])
+            .check()
+            .is_ok()
+    );
+    assert!(
+        set(vec![give(Group(42), EditData), deny(Group(42), Admin)])
+            .check()
+            .is_err()
+    );
+    assert!(set(vec![give(Group(0), EditData)]).check().is_err());
+    assert!(set(vec![give(User(3), None)]).check().is_err());
+
+    let json = r#"[{"group":42,"level":"EditData"},{"user":3,"level":"Admin","deny":true}]"#;
+    let acl: Acl = serde_json::from_str(json).unwrap();
+    assert_eq!(vec![give(Group(42), EditData), deny(User(3), Admin)], acl);
+    assert_eq!(json, serde_json::to_string(&acl).unwrap());
+}
+
+#[tokio::test]
+async fn resource_acl() {
+    use Principal::User;
+    use UserLevel::*;
+    let server = crate::app_driver::test_state("resource_acl");
+    let reader = hand_user::add(
+        &server,
+        hand_user::NewUser {
+            login: "reader".to_string(),
+            name: "Reader".to_string(),
+            password: "reader password".to_string(),
+            level: None,
+        },
+    )
+    .await
+    .unwrap();
+    let reader = reader.token();
+    let editor = UserToken::DEV_EDITOR;
+    fn request<T: DTO>(user: &UserToken, dto: T) -> DataRequest<T> {
+        DataRequest {
+            user: user.clone(),
+            dto,
+        }
+    }
+    let folder = |parent, name: &str| hand_files::FolderCreate {
+        parent,
+        group: Some(42),
+        name: name.to_string(),
+    };
+    let shared = hand_files::create(&server, request(&editor, folder(0, "shared")))
+        .await
+        .unwrap()
+        .dto;
+    let private = hand_files::create(&server, request(&editor, folder(shared.id, "private")))
+        .await
+        .unwrap()
+        .dto;
+    let target = |id| AclTarget {
+        resource: Resource::Node { id },
+    };
+    assert!(
+        get(&server, request(&reader, target(shared.id)))
+            .await
+            .is_err()
+    );
+
+    // The reader can see the folder and its content, but not the private
+    // folder, and not edit them.
+    let set_acl = |id, acl| AclSet {
+        resource: Resource::Node { id },
+        acl,
+    };
+    let see = vec![AclEntry {
+        principal: User(reader.id),
+        level: SeeData,
+        deny: false,
+    }];
+    assert!(
+        set(&server, request(&reader, set_acl(shared.id, see.clone())))
+            .await
+            .is_err()
+    );
+    set(&server, request(&editor, set_acl(shared.id, see.clone())))
+        .await
+        .unwrap();
+    let deny = vec![AclEntry {
+        principal: User(reader.id),
+        level: SeeData,
+        deny: true,
+    }];
+    let output = set(&server, request(&editor, set_acl(private.id, deny.clone())))
+        .await
+        .unwrap()
+        .dto;
+    assert_eq!((deny, vec![see.clone()]), (output.acl, output.inherited));
+
+    let output = get(&server, request(&reader, target(shared.id)))
+        .await
+        .unwrap();
+    assert_eq!((Some(42), SeeData), (output.dto.group, output.dto.level));
+    assert!(
+        get(&server, request(&reader, target(private.id)))
+            .await
+            .is_err()
+    );
+    let listed = hand_files::list(
+        &server,
+        request(&reader, hand_files::NodeId { id: shared.id }),
+    )
+    .await
+    .unwrap();
+    assert!(listed.dto.is_empty());
+
+    // The diagnostic, for the administrators of the node or the user itself.
+    let diagnose = |id, user| EffectiveRequest {
+        resource: Resource::Node { id },
+        user,
+    };
+    let output = effective(&server, request(&editor, diagnose(private.id, reader.id)))
+        .await
+        .unwrap()
+        .dto;
+    assert_eq!(None, output.level);
+    assert_eq!(
+        vec![(0, true, false), (1, false, true)],
+        output
+            .entries
+            .iter()
+            .map(|m| (m.depth, m.entry.deny, m.overridden))
+            .collect::<Vec<_>>()
+    );
+    assert!(
+        effective(&server, request(&reader, diagnose(shared.id, reader.id)))
+            .await
+            .is_ok()
+    );
+    assert!(
+        effective(&server, request(&reader, diagnose(shared.id, editor.id)))
+            .await
+            .is_err()
+    );
+    assert!(
+        effective(&server, request(&editor, diagnose(shared.id, 999)))
+            .await
+            .is_err()
+    );
+
+    // A deny of the owning group does not lock out its administrators, the
+    // answer is the stored list even if they can not see the node anymore.
+    let deny_owner = vec![AclEntry {
+        principal: Principal::Group(42),
+        level: SeeData,
+        deny: true,
+    }];
+    let output = set(
+        &server,
+        request(&editor, set_acl(private.id, deny_owner.clone())),
+    )
+    .await
+    .unwrap()
+    .dto;
+    assert_eq!((deny_owner, None), (output.acl, output.level));
+    set(&server, request(&editor, set_acl(private.id, Vec::new())))
+        .await
+        .unwrap();
+
+    // An administrator given by a list can not remove its own level.
+    let admin_entry = vec![AclEntry {
+        principal: User(reader.id),
+        level: Admin,
+        deny: false,
+    }];
+    set(&server, request(&editor, set_acl(shared.id, admin_entry)))
+        .await
+        .unwrap();
+    let err = set(&server, request(&reader, set_acl(shared.id, see.clone())))
+        .await
+        .unwrap_err();
+    assert_eq!("acl_lockout", err.code());
+
+    let home = AclTarget {
+        resource: Resource::Home,
+    };
+    let output = get(&server, request(&reader, home.clone())).await.unwrap();
+    assert_eq!(None, output.dto.level);
+    let admin = UserToken {
+        level: Admin,
+        ..Default::default()
+    };
+    let output = get(&server, request(&admin, home)).await.unwrap();
+    assert_eq!(Admin, output.dto.level);
+    let missing = AclTarget {
+        resource: Resource::Wiki {
+            slug: "missing".to_string(),
+        },
+    };
+    assert!(get(&server, request(&reader, missing)).await.is_err());
+}
@@ -0,0 +1,73 @@
+use crate::{
+    app_driver::{
+        State, blob,
+        error::{err_forbidden, err_sync_fail},
+        hand_files::NodeKind,
+    },
+    io_http::{DTO, DataRequest, DataResponseResult, data_response_ok},
+    *,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// An empty request, only for a server administrator.
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct AdminRequest();
+
+impl DTO for AdminRequest {
+    const IS_EMPTY: bool = true;
+
+    fn check_user(&self, user: &UserToken) -> Result<()> {
+        match user.level >= UserLevel::Admin {
+            true => Ok(()),
+            false => Err(err_forbidden()),
+        }
+    }
+}
+
+/// The disk usage of the files.
+#[derive(Debug, Clone, Default, Serialize, PartialEq, JsonSchema)]
+pub struct StorageUsage {
+    /// Number of files.
+    pub files: u64,
+    /// Sum of the file sizes, in bytes.
+    pub total_size: u64,
+    /// Number of different contents.
+    pub blobs: u64,
+    /// Sum of the size of all different contents, in bytes.
+    pub stored_size: u64,
+    /// Size of the contents no more used, removed by the next garbage collection.
+    pub unreferenced_size: u64,
+}
+
+pub async fn storage(
+    server: &State,
+    _: DataRequest<AdminRequest>,
+) -> DataResponseResult<StorageUsage> {
+    let mut usage = StorageUsage::default();
+
+    for node in server.nodes.read().map_err(err_sync_fail)?.values() {
+        if let NodeKind::File { size, .. } = node.kind {
+            usage.files += 1;
+            usage.total_size += size;
+        }
+    }
+
+    for blob_ref in server.blob_refs.lock().map_err(err_sync_fail)?.values() {
+        usage.blobs += 1;
+        usage.stored_size += blob_ref.size;
+        if blob_ref.refs == 0 {
+            usage.unreferenced_size += blob_ref.size;
+        }
+    }
+
+    data_response_ok(usage)
+}
+
+/// Remove now the unreferenced contents.
+pub async fn gc(
+    server: &State,
+    _: DataRequest<AdminRequest>,
+) -> DataResponseResult<blob::Collected> {
+    data_response_ok(blob::collect(server)?)
+}
@@ -0,0 +1,335 @@
+//! Access to the tree by path, for the WebDAV subsystem.
+//!
+//! The operations reuse the handlers of the JSON API, so the permissions
+//! and the trash are the same.
+
+use super::{FolderCreate, Node, NodeId, NodeKind, NodeTarget, Nodes, check_name, trash, tree};
+use crate::{
+    app_driver::{
+        State,
+        error::{err_forbidden, err_not_found, err_sync_fail},
+    },
+    io_http::{ByteStream, DTO, DataRequest, DavEntry, FileOutput, FileTarget},
+    *,
+};
+use axum::http::StatusCode;
+
+/// Find the node at the path, `None` for the root.
+fn resolve<'a>(nodes: &'a Nodes, path: &[String]) -> Result<Option<&'a Node>> {
+    let mut found = None;
+    let mut parent = 0;
+    for name in path {
+        let node = nodes
+            .values()
+            .find(|node| node.parent == parent && &node.name == name)
+            .ok_or_else(|| err_not_found("node"))?;
+        parent = node.id;
+        found = Some(node);
+    }
+    Ok(found)
+}
+
+/// Find the folder that will contain the last element of the path.
+/// Return the folder id, the name, and the existing node with this name.
+fn resolve_parent<'a>(
+    nodes: &'a Nodes,
+    path: &'a [String],
+) -> Result<(u64, &'a str, Option<&'a Node>)> {
+    let (name, parent) = path.split_last().ok_or_else(err_forbidden)?;
+    check_name(name)?;
+    let parent = match resolve(nodes, parent) {
+        Ok(None) => 0,
+        Ok(Some(node)) if node.is_folder() => node.id,
+        _ => return Err(err_no_parent()),
+    };
+    let existing = nodes
+        .values()
+        .find(|node| node.parent == parent && &node.name == name);
+    Ok((parent, name, existing))
+}
+
+/// The group of a new root node: the only group the user can edit. With
+/// several groups, a root node is created with the JSON API, with its group.
+fn root_group(user: &UserToken) -> Result<u32> {
+    let mut groups = user
+        .groups
+        .iter()
+        .take_while(|&&(_, id)| id != 0)
+        .filter(|&&(level, _)| level >= UserLevel::EditData)
+        .map(|&(_, id)| id);
+    match (groups.next(), groups.next()) {
+        (Some(group), None) => Ok(group),
+        _ => Err(WrapError::http(
+            StatusCode::FORBIDDEN,
+            "A root node need a group, create it in a group folder",
+        )),
+    }
+}
+
+fn entry(node: &Node) -> DavEntry {
+    match &node.kind {
+        NodeKind::Folder => DavEntry {
+            name: node.name.clone(),
+            is_folder: true,
+            modified: node.modified,
+            etag: format!("{}-{}", node.id, node.modified),
+            ..Default::default()
+        },
+        NodeKind::File { mime, size, blob } => DavEntry {
+            name: node.name.clone(),
+            is_folder: false,
+            size: *size,
+            mime: mime.clone(),
+            modified: node.modified,
+            etag: blob.clone(),
+        },
+    }
+}
+
+/// Get the entry at the path, and its visible children if asked.
+pub fn find(
+    server: &State,
+    user: &UserToken,
+    path: &[String],
+    children: bool,
+) -> Result<Vec<DavEntry>> {
+    let nodes = server.nodes.read().map_err(err_sync_fail)?;
+    let (id, first) = match resolve(&nodes, path)? {
+        None => (
+            0,
+            DavEntry {
+                is_folder: true,
+                ..Default::default()
+            },
+        ),
+        Some(node) if node.allow(&nodes, user, UserLevel::SeeData) => (node.id, entry(node)),
+        Some(_) => return Err(err_forbidden()),
+    };
+
+    let mut entries = vec![first];
+    if children && entries[0].is_folder {
+        entries.extend(
+            nodes
+                .values()
+                .filter(|node| node.parent == id && node.allow(&nodes, user, UserLevel::SeeData))
+                .map(entry),
+        );
+    }
+    Ok(entries)
+}
+
+/// Open the file at the path.
+pub async fn get(server: &State, user: UserToken, path: &[String]) -> Result<FileOutput> {
+    let id = {
+        let nodes = server.nodes.read().map_err(err_sync_fail)?;
+        resolve(&nodes, path)?.ok_or_else(err_is_folder)?.id
+    };
+    super::open(server, user, id).await
+}
+
+/// Store the file at the path, return `true` if it is created.
+pub async fn put(
+    server: &State,
+    user: UserToken,
+    path: &[String],
+    input: &mut dyn ByteStream,
+) -> Result<bool> {
+    let target = {
+        let nodes = server.nodes.read().map_err(err_sync_fail)?;
+        match resolve_parent(&nodes, path)? {
+            (_, _, Some(node)) if node.is_folder() => return Err(err_is_folder()),
+            (_, _, Some(node)) => FileTarget::Replace(node.id),
+            (parent, name, None) => FileTarget::New {
+                parent,
+                group: match parent {
+                    0 => Some(root_group(&user)?),
+                    _ => None,
+                },
+                name: name.to_string(),
+            },
+        }
+    };
+    let created = matches!(target, FileTarget::New { .. });
+    super::put(server, user, target, input).await?;
+    Ok(created)
+}
+
+/// Create a folder at the path.
+pub async fn mkcol(server: &State, user: UserToken, path: &[String]) -> Result<()> {
+    let dto = {
+        let nodes = server.nodes.read().map_err(err_sync_fail)?;
+        match resolve_parent(&nodes, path)? {
+            (_, _, Some(_)) => {
+                return Err(WrapError::http(
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    "The node already exist",
+                ));
+            }
+            (parent, name, None) => FolderCreate {
+                parent,
+                group: match parent {
+                    0 => Some(root_group(&user)?),
+                    _ => None,
+                },
+                name: name.to_string(),
+            },
+        }
+    };
+    tree::create(server, request(user, dto)?).await?;
+    Ok(())
+}
+
+/// Move the node at the path to the trash.
+pub async fn delete(server: &State, user: UserToken, path: &[String]) -> Result<()> {
+    let id = {
+        let nodes = server.nodes.read().map_err(err_sync_fail)?;
+        resolve(&nodes, path)?.ok_or_else(err_forbidden)?.id
+    };
+    trash::delete(server, request(user, NodeId { id })?).await?;
+    Ok(())
+}
+
+/// Move or copy the node, return `true` if the destination is created.
+/// An overwritten destination is moved to the trash.
+pub async fn transfer(
+    server: &State,
+    user: UserToken,
+    from: &[String],
+    to: &[String],
+    copy: bool,
+    overwrite: bool,
+) -> Result<bool> {
+    let (dto, existing) = {
+        let nodes = server.nodes.read().map_err(err_sync_fail)?;
+        let id = resolve(&nodes, from)?.ok_or_else(err_forbidden)?.id;
+        let (parent, name, existing) = resolve_parent(&nodes, to)?;
+        let existing = existing.map(|node| node.id);
+        if existing == Some(id) {
+            return Err(WrapError::http(
+                StatusCode::FORBIDDEN,
+                "The source and the destination are the same",
+            ));
+        } else if existing.is_some() && !overwrite {
+            return Err(WrapError::http(
+                StatusCode::PRECONDITION_FAILED,
+                "The destination already exist",
+            ));
+        }
+        let dto = NodeTarget {
+            id,
+            parent,
+            name: name.to_string(),
+        };
+        // Nothing is trashed when the move or the copy would fail.
+        tree::check_transfer(&nodes, &user, &dto, copy)?;
+        if let Some(existing) = existing {
+            trash::check_delete(&nodes, &user, existing)?;
+        }
+        (dto, existing)
+    };
+
+    if let Some(id) = existing {
+        trash::delete(server, request(user.clone(), NodeId { id })?).await?;
+    }
+    match copy {
+        true => tree::copy(server, request(user, dto)?).await?,
+        false => tree::r#move(server, request(user, dto)?).await?,
+    };
+    Ok(existing.is_none())
+}
+
+/// Build and check a request for a handler.
+fn request<T: DTO>(user: UserToken, dto: T) -> Result<DataRequest<T>> {
+    dto.check()?;
+    dto.check_user(&user)?;
+    Ok(DataRequest { user, dto })
+}
+
+fn err_is_folder() -> WrapError {
+    WrapError::http(StatusCode::METHOD_NOT_ALLOWED, "The node is a folder")
+}
+
+fn err_no_parent() -> WrapError {
+    WrapError::http(StatusCode::CONFLICT, "The parent folder does not exist")
+}
+
+#[tokio::test]
+async fn dav_paths() {
+    let server = crate::app_driver::test_state("dav_paths");
+    let user = UserToken::DEV_EDITOR;
+    let path = |path: &str| {
+        path.split('/')
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+            .collect::<Vec<_>>()
+    };
+
+    // The group of a root node is the only group of the user.
+    assert!(mkcol(&server, user.clone(), &path("docs")).await.is_err());
+    let mut single = user.clone();
+    single.groups[1] = (UserLevel::None, 0);
+    mkcol(&server, single, &path("docs")).await.unwrap();
+    assert_eq!(42, server.nodes.read().unwrap()[&1].group);
+    assert!(
+        mkcol(&server, user.clone(), &path("none/sub"))
+            .await
+            .is_err()
+    );
+
+    let mut body = axum::body::Body::from("Hello");
+    assert!(
+        put(&server, user.clone(), &path("docs/a.txt"), &mut body)
+            .await
+            .unwrap()
+    );
+    let mut body = axum::body::Body::from("Hello world");
+    assert!(
+        !put(&server, user.clone(), &path("docs/a.txt"), &mut body)
+            .await
+            .unwrap()
+    );
+
+    let entries = find(&server, &user, &path("docs"), true).unwrap();
+    assert_eq!(2, entries.len());
+    assert_eq!(("a.txt", 11), (entries[1].name.as_str(), entries[1].size));
+
+    let (from, to) = (path("docs/a.txt"), path("docs/b.txt"));
+    assert!(
+        transfer(&server, user.clone(), &from, &to, true, false)
+            .await
+            .unwrap()
+    );
+    assert!(
+        transfer(&server, user.clone(), &from, &to, false, false)
+            .await
+            .is_err()
+    );
+    assert!(
+        !transfer(&server, user.clone(), &from, &to, false, true)
+            .await
+            .unwrap()
+    );
+    assert_eq!(1, server.trash.lock().unwrap().len());
+
+    // The destination is kept when the move fails.
+    mkcol(&server, user.clone(), &path("docs/sub"))
+        .await
+        .unwrap();
+    assert!(
+        transfer(
+            &server,
+            user.clone(),
+            &path("docs"),
+            &path("docs/sub"),
+            false,
+            true
+        )
+        .await
+        .is_err()
+    );
+    assert_eq!(1, server.trash.lock().unwrap().len());
+    assert_eq!(3, find(&server, &user, &path("docs"), true).unwrap().len());
+
+    delete(&server, user.clone(), &path("docs")).await.unwrap();
+    assert_eq!(1, find(&server, &user, &[], true).unwrap().len());
+}
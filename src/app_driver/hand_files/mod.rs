@@ -0,0 +1,267 @@
+pub mod dav;
+pub mod trash;
+mod tree;
+mod version;
+
+use crate::{
+    app_driver::{
+        State, blob,
+        error::{err_forbidden, err_not_found, err_sync_fail},
+        hand_acl::{self, Acl, AclEntry},
+        now,
+    },
+    io_http::{ByteStream, FileOutput, FileTarget, Text, Validator},
+    *,
+};
+use axum::http::StatusCode;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+pub use tree::*;
+pub use version::*;
+
+/// A folder or a file in the tree.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct Node {
+    pub id: u64,
+    /// The parent folder, `0` for a root node.
+    pub parent: u64,
+    /// The group that owns the node, its members can see or edit it.
+    pub group: u32,
+    pub name: String,
+    /// Last modification, in seconds since Epoch.
+    pub modified: u64,
+    #[serde(flatten)]
+    pub kind: NodeKind,
+    /// The other users and groups allowed on the node, and its folders.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub acl: Acl,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, JsonSchema)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum NodeKind {
+    #[default]
+    Folder,
+    File {
+        mime: String,
+        /// Size in bytes.
+        size: u64,
+        /// Hash of the content.
+        blob: String,
+    },
+}
+
+impl Node {
+    pub fn is_folder(&self) -> bool {
+        self.kind == NodeKind::Folder
+    }
+
+    /// The lists of the node and of its folders, the nearest first.
+    pub fn acl_lists<'a>(&'a self, nodes: &'a Nodes) -> Vec<&'a [AclEntry]> {
+        let mut lists = vec![&self.acl[..]];
+        let mut parent = self.parent;
+        while let Some(folder) = nodes.get(&parent) {
+            lists.push(&folder.acl);
+            parent = folder.parent;
+        }
+        lists
+    }
+
+    /// Check the user has the level on the node, by its group or the lists.
+    pub fn allow(&self, nodes: &Nodes, user: &UserToken, level: UserLevel) -> bool {
+        hand_acl::allow(Some(self.group), &self.acl_lists(nodes), user, level)
+    }
+}
+
+/// All the nodes indexed by id.
+pub type Nodes = std::collections::BTreeMap<u64, Node>;
+
+fn key(id: u64) -> String {
+    format!("node/{id}")
+}
+
+/// Load all nodes and the trash from the storage.
+pub fn init(server: &State) -> Result<()> {
+    trash::init(server)?;
+
+    let mut nodes = server.nodes.write().map_err(err_sync_fail)?;
+    for name in server.storage.list("node")? {
+        if let Some(node) = server.storage.load::<Node>(&format!("node/{name}"))? {
+            nodes.insert(node.id, node);
+        }
+    }
+    version::init(server, &nodes)
+}
+
+/// The rules of a file or folder name.
+pub fn file_name(name: Text<'_>) -> Text<'_> {
+    name.code("invalid_name", "Invalid file name")
+        .required()
+        .max_bytes(255)
+        .valid(
+            |name| name != "." && name != ".." && !name.contains(['/', '\\', '\0']),
+            "Invalid file name",
+        )
+}
+
+pub fn check_name(name: &str) -> Result<()> {
+    Validator::new().text("name", name, file_name).finish()
+}
+
+/// Receive and store a file.
+pub async fn put(
+    server: &State,
+    user: UserToken,
+    target: FileTarget,
+    input: &mut dyn ByteStream,
+) -> Result<Node> {
+    // Check before receiving the content.
+    let (id, parent, group, name) = match target {
+        FileTarget::New {
+            parent,
+            group,
+            name,
+        } => {
+            check_name(&name)?;
+            let nodes = server.nodes.read().map_err(err_sync_fail)?;
+            let group = new_node_group(&nodes, &user, parent, group)?;
+            check_free_name(&nodes, parent, &name)?;
+            (None, parent, group, name)
+        }
+        FileTarget::Replace(id) => {
+            let node = get_allowed(server, &user, id, UserLevel::EditData)?;
+            if node.is_folder() {
+                return Err(err_is_folder());
+            }
+            (Some(id), node.parent, node.group, node.name)
+        }
+    };
+
+    let received = server.blobs.receive(input, server.upload_max).await?;
+
+    let mut nodes = server.nodes.write().map_err(err_sync_fail)?;
+    let id = match id {
+        Some(id) => id,
+        None => {
+            // The folder can be removed or the name taken during the upload.
+            new_node_group(&nodes, &user, parent, Some(group))?;
+            check_free_name(&nodes, parent, &name)?;
+            server.new_id("node")?
+        }
+    };
+    let node = Node {
+        id,
+        parent,
+        group,
+        kind: NodeKind::File {
+            mime: bmime::detect(&name, &received.head).to_string(),
+            size: received.size,
+            blob: blob::store(server, received)?,
+        },
+        name,
+        modified: now(),
+        // A replaced file keeps its list.
+        acl: nodes
+            .get(&id)
+            .map(|node| node.acl.clone())
+            .unwrap_or_default(),
+    };
+    server.storage.save(&key(id), &node)?;
+    version::push(server, &node, user.id)?;
+    nodes.insert(id, node.clone());
+
+    Ok(node)
+}
+
+/// Get the group of a new node in the parent, and check the user can edit it.
+/// A root node need an explicit group, else it is the parent's group.
+fn new_node_group(nodes: &Nodes, user: &UserToken, parent: u64, group: Option<u32>) -> Result<u32> {
+    let parent_group = match parent {
+        0 => None,
+        _ => match nodes.get(&parent) {
+            Some(node) if !node.is_folder() => return Err(err_not_folder()),
+            Some(node) if !node.allow(nodes, user, UserLevel::EditData) => {
+                return Err(err_forbidden());
+            }
+            Some(node) => Some(node.group),
+            None => return Err(err_not_found("parent folder")),
+        },
+    };
+
+    let group = group
+        .or(parent_group)
+        .ok_or_else(|| WrapError::http(StatusCode::BAD_REQUEST, "A root node need a group"))?;
+    match user.allow(group, UserLevel::EditData) {
+        true => Ok(group),
+        false => Err(err_forbidden()),
+    }
+}
+
+/// Check no other child of the folder has the name.
+fn check_free_name(nodes: &Nodes, parent: u64, name: &str) -> Result<()> {
+    match nodes
+        .values()
+        .any(|node| node.parent == parent && node.name == name)
+    {
+        true => Err(WrapError::http(
+            StatusCode::CONFLICT,
+            "A node with this name already exist in the folder",
+        )),
+        false => Ok(()),
+    }
+}
+
+/// Get a node, if the user has the level on its group.
+fn get_allowed(server: &State, user: &UserToken, id: u64, level: UserLevel) -> Result<Node> {
+    let nodes = server.nodes.read().map_err(err_sync_fail)?;
+    let node = nodes.get(&id).ok_or_else(|| err_not_found("node"))?;
+    if !node.allow(&nodes, user, level) {
+        return Err(err_forbidden());
+    }
+    Ok(node.clone())
+}
+
+/// Replace the list of a node, the user need to administrate it.
+/// Return the new lists of the node.
+pub fn set_acl(server: &State, user: &UserToken, id: u64, acl: Acl) -> Result<hand_acl::Lists> {
+    let mut nodes = server.nodes.write().map_err(err_sync_fail)?;
+    let node = nodes.get(&id).ok_or_else(|| err_not_found("node"))?;
+    let old = node.acl_lists(&nodes);
+    let mut new = old.clone();
+    new[0] = &acl;
+    hand_acl::check_set(Some(node.group), &old, &new, user)?;
+    let lists = hand_acl::Lists {
+        group: Some(node.group),
+        lists: new.into_iter().map(<[_]>::to_vec).collect(),
+        admins: false,
+    };
+    let mut updated = node.clone();
+    updated.acl = acl;
+    server.storage.save(&key(id), &updated)?;
+    nodes.insert(id, updated);
+    Ok(lists)
+}
+
+/// Open a file to send it.
+pub async fn open(server: &State, user: UserToken, id: u64) -> Result<FileOutput> {
+    let node = get_allowed(server, &user, id, UserLevel::SeeData)?;
+    let NodeKind::File { mime, size, blob } = node.kind else {
+        return Err(err_is_folder());
+    };
+    Ok(FileOutput {
+        file: server.blobs.open_file(&blob).await?,
+        name: node.name,
+        mime,
+        size,
+        etag: format!("\"{blob}\""),
+        modified: node.modified,
+    })
+}
+
+fn err_is_folder() -> WrapError {
+    WrapError::http(StatusCode::BAD_REQUEST, "The node is a folder")
+}
+
+fn err_not_folder() -> WrapError {
+    WrapError::http(StatusCode::BAD_REQUEST, "The node is not a folder")
+}
@@ -0,0 +1,384 @@
+//! Deleted nodes are moved in the trash of their group, and purged later.
+
+use super::{
+    Node, NodeId, Nodes, check_free_name, key as node_key, new_node_group, subtree, version,
+};
+use crate::{
+    app_driver::{
+        State,
+        error::{err_forbidden, err_not_found, err_sync_fail},
+        hand_acl::{self, Acl, AclEntry},
+        now,
+    },
+    io_http::{DTO, DataRequest, DataResponseResult, data_response_ok},
+    *,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// A deleted node with all its descendants.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct TrashEntry {
+    /// The id of the deleted node.
+    pub id: u64,
+    /// The group of the deleted node.
+    pub group: u32,
+    /// Deletion time, in seconds since Epoch.
+    pub time: u64,
+    /// The user who deleted the node.
+    pub by: u32,
+    /// The deleted node first, and all its descendants.
+    pub nodes: Vec<Node>,
+    /// The lists of the folders of the deleted node, the nearest first.
+    #[serde(default)]
+    pub inherited: Vec<Acl>,
+}
+
+impl TrashEntry {
+    /// Check the user has the level on all the nodes, with the lists they
+    /// had when deleted.
+    pub fn allow(&self, user: &UserToken, level: UserLevel) -> bool {
+        let nodes: std::collections::BTreeMap<u64, &Node> =
+            self.nodes.iter().map(|node| (node.id, node)).collect();
+        self.nodes.iter().all(|node| {
+            let mut lists = vec![&node.acl[..]];
+            let mut parent = node.parent;
+            while let Some(folder) = nodes.get(&parent) {
+                lists.push(&folder.acl);
+                parent = folder.parent;
+            }
+            lists.extend(self.inherited.iter().map(Vec::as_slice));
+            hand_acl::allow(Some(node.group), &lists, user, level)
+        })
+    }
+}
+
+/// Merge the lists, the nearest first, in one list resolved the same way:
+/// the nearest entry of each principal.
+fn merge_lists(lists: &[&[AclEntry]]) -> Acl {
+    let mut merged: Acl = Vec::new();
+    for entry in lists.iter().flat_map(|list| list.iter()) {
+        if !merged
+            .iter()
+            .any(|other| other.principal == entry.principal)
+        {
+            merged.push(*entry);
+        }
+    }
+    merged
+}
+
+/// All the trash entries, indexed by the deleted node id.
+pub type Trash = std::collections::BTreeMap<u64, TrashEntry>;
+
+fn key(id: u64) -> String {
+    format!("trash/{id}")
+}
+
+/// Load the trash from the storage.
+pub fn init(server: &State) -> Result<()> {
+    let mut trash = server.trash.lock().map_err(err_sync_fail)?;
+    for name in server.storage.list("trash")? {
+        if let Some(entry) = server
+            .storage
+            .load::<TrashEntry>(&format!("trash/{name}"))?
+        {
+            trash.insert(entry.id, entry);
+        }
+    }
+    Ok(())
+}
+
+/// Check the user can edit the node and all its descendants.
+/// Return their ids, the node first.
+pub fn check_delete(nodes: &Nodes, user: &UserToken, id: u64) -> Result<Vec<u64>> {
+    let ids = subtree(nodes, id);
+    match ids
+        .iter()
+        .all(|id| nodes[id].allow(nodes, user, UserLevel::EditData))
+    {
+        true => Ok(ids),
+        false => Err(err_forbidden()),
+    }
+}
+
+/// Move a node and all its descendants in the trash.
+pub async fn delete(server: &State, request: DataRequest<NodeId>) -> DataResponseResult<()> {
+    let DataRequest { user, dto } = request;
+    let mut nodes = server.nodes.write().map_err(err_sync_fail)?;
+    let node = nodes.get(&dto.id).ok_or_else(|| err_not_found("node"))?;
+    let ids = check_delete(&nodes, &user, dto.id)?;
+
+    let entry = TrashEntry {
+        id: dto.id,
+        group: node.group,
+        time: now(),
+        by: user.id,
+        nodes: ids.iter().map(|id| nodes[id].clone()).collect(),
+        inherited: node.acl_lists(&nodes)[1..]
+            .iter()
+            .map(|list| list.to_vec())
+            .collect(),
+    };
+    let mut trash = server.trash.lock().map_err(err_sync_fail)?;
+    server.storage.save(&key(entry.id), &entry)?;
+    trash.insert(entry.id, entry);
+
+    // Children first, to never keep an orphan node.
+    for &id in ids.iter().rev() {
+        server.storage.remove(&node_key(id))?;
+        nodes.remove(&id);
+    }
+
+    data_response_ok(())
+}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct TrashGroup {
+    pub group: u32,
+}
+
+impl DTO for TrashGroup {}
+
+/// List the trash of a group, the entries the user could delete.
+pub async fn list(
+    server: &State,
+    request: DataRequest<TrashGroup>,
+) -> DataResponseResult<Vec<TrashEntry>> {
+    let DataRequest { user, dto } = request;
+    let trash = server.trash.lock().map_err(err_sync_fail)?;
+    let entries = trash
+        .values()
+        .filter(|entry| entry.group == dto.group && entry.allow(&user, UserLevel::EditData))
+        .cloned()
+        .collect();
+    data_response_ok(entries)
+}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct TrashRestore {
+    /// The id of the deleted node.
+    pub id: u64,
+    /// The new parent folder, else the old one.
+    pub parent: Option<u64>,
+}
+
+impl DTO for TrashRestore {}
+
+/// Put back the nodes in the tree.
+pub async fn restore(
+    server: &State,
+    request: DataRequest<TrashRestore>,
+) -> DataResponseResult<Node> {
+    let DataRequest { user, dto } = request;
+    let mut nodes = server.nodes.write().map_err(err_sync_fail)?;
+    let mut trash = server.trash.lock().map_err(err_sync_fail)?;
+    let entry = trash
+        .get(&dto.id)
+        .ok_or_else(|| err_not_found("trash entry"))?;
+    if !entry.allow(&user, UserLevel::EditData) {
+        return Err(err_forbidden());
+    }
+
+    let mut restored = entry.nodes.clone();
+    let root = &mut restored[0];
+    if let Some(parent) = dto.parent.filter(|&parent| parent != root.parent) {
+        // Keep what the old folders gave or denied, over the new ones.
+        let mut lists = vec![&root.acl[..]];
+        lists.extend(entry.inherited.iter().map(Vec::as_slice));
+        root.acl = merge_lists(&lists);
+        root.parent = parent;
+    }
+    new_node_group(&nodes, &user, root.parent, Some(root.group))?;
+    check_free_name(&nodes, root.parent, &root.name)?;
+
+    // Parent first, to never keep an orphan node.
+    for node in restored.iter() {
+        server.storage.save(&node_key(node.id), node)?;
+    }
+    server.storage.remove(&key(dto.id))?;
+    trash.remove(&dto.id);
+    let root = restored[0].clone();
+    nodes.extend(restored.into_iter().map(|node| (node.id, node)));
+
+    data_response_ok(root)
+}
+
+/// Remove now the trash of the group, the entries the user administers.
+pub async fn empty(server: &State, request: DataRequest<TrashGroup>) -> DataResponseResult<()> {
+    let DataRequest { user, dto } = request;
+    purge(server, |entry| {
+        entry.group == dto.group && entry.allow(&user, UserLevel::Admin)
+    })?;
+    data_response_ok(())
+}
+
+/// Remove definitively the trash entries, and the revisions of their files.
+/// Return the number of removed entries.
+pub fn purge(server: &State, filter: impl Fn(&TrashEntry) -> bool) -> Result<usize> {
+    let mut trash = server.trash.lock().map_err(err_sync_fail)?;
+    let ids: Vec<u64> = trash
+        .values()
+        .filter(|entry| filter(entry))
+        .map(|entry| entry.id)
+        .collect();
+
+    for id in ids.iter() {
+        server.storage.remove(&key(*id))?;
+        if let Some(entry) = trash.remove(id) {
+            for node in entry.nodes.iter().filter(|node| !node.is_folder()) {
+                version::remove_all(server, node.id)?;
+            }
+        }
+    }
+
+    Ok(ids.len())
+}
+
+/// Remove the trash entries older than [`State::trash_max_age`].
+pub fn purge_old(server: &State) -> Result<usize> {
+    let limit = now().saturating_sub(server.trash_max_age);
+    purge(server, |entry| entry.time < limit)
+}
+
+#[tokio::test]
+async fn trash_restore() {
+    use super::tree::{FolderCreate, create};
+    fn request<T: DTO>(dto: T) -> DataRequest<T> {
+        DataRequest {
+            user: UserToken::DEV_EDITOR,
+            dto,
+        }
+    }
+    let folder = |parent, name: &str| FolderCreate {
+        parent,
+        group: Some(42),
+        name: name.to_string(),
+    };
+
+    let server = crate::app_driver::test_state("trash_restore");
+    let a = create(&server, request(folder(0, "a"))).await.unwrap().dto;
+    let new_file = crate::io_http::FileTarget::New {
+        parent: a.id,
+        group: None,
+        name: "file.txt".to_string(),
+    };
+    let mut body = axum::body::Body::from("Hello");
+    let file = super::put(&server, UserToken::DEV_EDITOR, new_file, &mut body)
+        .await
+        .unwrap();
+
+    delete(&server, request(NodeId { id: a.id })).await.unwrap();
+    assert!(server.nodes.read().unwrap().is_empty());
+    let entries = list(&server, request(TrashGroup { group: 42 }))
+        .await
+        .unwrap()
+        .dto;
+    assert_eq!(1, entries.len());
+    assert_eq!(
+        vec![a.id, file.id],
+        entries[0]
+            .nodes
+            .iter()
+            .map(|node| node.id)
+            .collect::<Vec<_>>()
+    );
+
+    // The name is taken again, restore in another folder.
+    let b = create(&server, request(folder(0, "a"))).await.unwrap().dto;
+    let restore_in = |parent| TrashRestore { id: a.id, parent };
+    assert!(restore(&server, request(restore_in(None))).await.is_err());
+    let restored = restore(&server, request(restore_in(Some(b.id))))
+        .await
+        .unwrap()
+        .dto;
+    assert_eq!((a.id, b.id), (restored.id, restored.parent));
+    assert_eq!(a.id, server.nodes.read().unwrap()[&file.id].parent);
+    let entries = list(&server, request(TrashGroup { group: 42 }))
+        .await
+        .unwrap()
+        .dto;
+    assert!(entries.is_empty());
+    assert!(restore(&server, request(restore_in(None))).await.is_err());
+}
+
+#[tokio::test]
+async fn trash_acl() {
+    use super::tree::{FolderCreate, create};
+    use hand_acl::Principal::User;
+    fn request<T: DTO>(user: &UserToken, dto: T) -> DataRequest<T> {
+        DataRequest {
+            user: user.clone(),
+            dto,
+        }
+    }
+    let folder = |parent, name: &str| FolderCreate {
+        parent,
+        group: Some(42),
+        name: name.to_string(),
+    };
+    let editor = UserToken::DEV_EDITOR;
+    let shared = UserToken {
+        level: UserLevel::EditData,
+        id: 7,
+        ..Default::default()
+    };
+    let mut member = shared.clone();
+    member.id = 8;
+    member.groups[0] = (UserLevel::EditData, 42);
+
+    // The folder shares its content with a user, and denies it to a member.
+    let server = crate::app_driver::test_state("trash_acl");
+    let p = create(&server, request(&editor, folder(0, "p")))
+        .await
+        .unwrap()
+        .dto;
+    let c = create(&server, request(&editor, folder(p.id, "c")))
+        .await
+        .unwrap()
+        .dto;
+    let entry = |id, level, deny| AclEntry {
+        principal: User(id),
+        level,
+        deny,
+    };
+    let acl = vec![
+        entry(shared.id, UserLevel::EditData, false),
+        entry(member.id, UserLevel::EditData, true),
+    ];
+    super::set_acl(&server, &editor, p.id, acl).unwrap();
+    delete(&server, request(&editor, NodeId { id: c.id }))
+        .await
+        .unwrap();
+
+    let group = || TrashGroup { group: 42 };
+    async fn listed(server: &State, user: &UserToken) -> usize {
+        let dto = TrashGroup { group: 42 };
+        list(server, request(user, dto)).await.unwrap().dto.len()
+    }
+    assert_eq!(1, listed(&server, &shared).await);
+    assert_eq!(0, listed(&server, &member).await);
+    let restore_in = |parent| TrashRestore { id: c.id, parent };
+    assert!(
+        restore(&server, request(&member, restore_in(None)))
+            .await
+            .is_err()
+    );
+    empty(&server, request(&shared, group())).await.unwrap();
+    assert_eq!(1, listed(&server, &editor).await);
+
+    // Restored in another folder, the node keeps the lists of the old one.
+    let b = create(&server, request(&editor, folder(0, "b")))
+        .await
+        .unwrap()
+        .dto;
+    restore(&server, request(&editor, restore_in(Some(b.id))))
+        .await
+        .unwrap();
+    let nodes = server.nodes.read().unwrap();
+    let restored = &nodes[&c.id];
+    assert_eq!(b.id, restored.parent);
+    assert!(restored.allow(&nodes, &shared, UserLevel::EditData));
+    assert!(!restored.allow(&nodes, &member, UserLevel::EditData));
+    assert!(restored.allow(&nodes, &member, UserLevel::SeeData));
+}
@@ -0,0 +1,337 @@
+use super::{
+    Node, NodeKind, Nodes, check_free_name, err_not_folder, file_name, key, new_node_group,
+};
+use crate::{
+    app_driver::{
+        State, blob,
+        error::{err_forbidden, err_not_found, err_sync_fail},
+        hand_acl::Acl,
+        now,
+    },
+    io_http::{DTO, DataRequest, DataResponseResult, Validator, data_response_ok},
+    *,
+};
+use axum::http::StatusCode;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct NodeId {
+    pub id: u64,
+}
+
+impl DTO for NodeId {}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct FolderCreate {
+    pub parent: u64,
+    /// Needed for a root folder, else the parent group is used.
+    pub group: Option<u32>,
+    pub name: String,
+}
+
+impl DTO for FolderCreate {
+    fn check(&self) -> Result<()> {
+        Validator::new()
+            .text("name", &self.name, file_name)
+            .finish()
+    }
+}
+
+/// The node `id` is moved or copied in the folder `parent` with the name.
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct NodeTarget {
+    pub id: u64,
+    pub parent: u64,
+    pub name: String,
+}
+
+impl DTO for NodeTarget {
+    fn check(&self) -> Result<()> {
+        Validator::new()
+            .text("name", &self.name, file_name)
+            .finish()
+    }
+}
+
+/// Get all the descendants of the node, and the node itself in first.
+/// The parent is always before its children.
+pub fn subtree(nodes: &Nodes, id: u64) -> Vec<u64> {
+    let mut ids = vec![id];
+    let mut i = 0;
+    while i < ids.len() {
+        let parent = ids[i];
+        ids.extend(
+            nodes
+                .values()
+                .filter(|node| node.parent == parent)
+                .map(|node| node.id),
+        );
+        i += 1;
+    }
+    ids
+}
+
+/// Get one node.
+pub async fn get(server: &State, request: DataRequest<NodeId>) -> DataResponseResult<Node> {
+    let nodes = server.nodes.read().map_err(err_sync_fail)?;
+    let node = nodes
+        .get(&request.dto.id)
+        .ok_or_else(|| err_not_found("node"))?;
+    match node.allow(&nodes, &request.user, UserLevel::SeeData) {
+        true => data_response_ok(node.clone()),
+        false => Err(err_forbidden()),
+    }
+}
+
+/// List the children of a folder that the user can see.
+/// The id `0` lists the root nodes.
+pub async fn list(server: &State, request: DataRequest<NodeId>) -> DataResponseResult<Vec<Node>> {
+    let DataRequest { user, dto } = request;
+    let nodes = server.nodes.read().map_err(err_sync_fail)?;
+    if dto.id != 0 {
+        let folder = nodes.get(&dto.id).ok_or_else(|| err_not_found("folder"))?;
+        if !folder.is_folder() {
+            return Err(err_not_folder());
+        } else if !folder.allow(&nodes, &user, UserLevel::SeeData) {
+            return Err(err_forbidden());
+        }
+    }
+
+    let children = nodes
+        .values()
+        .filter(|node| node.parent == dto.id && node.allow(&nodes, &user, UserLevel::SeeData))
+        .cloned()
+        .collect();
+    data_response_ok(children)
+}
+
+pub async fn create(
+    server: &State,
+    request: DataRequest<FolderCreate>,
+) -> DataResponseResult<Node> {
+    let DataRequest { user, dto } = request;
+    let mut nodes = server.nodes.write().map_err(err_sync_fail)?;
+    let group = new_node_group(&nodes, &user, dto.parent, dto.group)?;
+    check_free_name(&nodes, dto.parent, &dto.name)?;
+
+    let node = Node {
+        id: server.new_id("node")?,
+        parent: dto.parent,
+        group,
+        name: dto.name,
+        modified: now(),
+        kind: NodeKind::Folder,
+        acl: Acl::new(),
+    };
+    server.storage.save(&key(node.id), &node)?;
+    nodes.insert(node.id, node.clone());
+
+    data_response_ok(node)
+}
+
+/// Check the user can move the node in the folder, but not the name.
+fn check_move(nodes: &Nodes, user: &UserToken, node: &Node, parent: u64) -> Result<()> {
+    if !node.allow(nodes, user, UserLevel::EditData) {
+        return Err(err_forbidden());
+    } else if node.parent == parent {
+        return Ok(());
+    }
+
+    new_node_group(nodes, user, parent, Some(node.group))?;
+    // Walk up from the destination, to not move a folder in itself.
+    let mut ancestor = parent;
+    while ancestor != 0 {
+        if ancestor == node.id {
+            return Err(WrapError::http(
+                StatusCode::BAD_REQUEST,
+                "Can not move a folder into itself",
+            ));
+        }
+        ancestor = nodes.get(&ancestor).map_or(0, |node| node.parent);
+    }
+    Ok(())
+}
+
+/// Check the user can copy the node in the folder, but not the name.
+/// Return the group of the copies.
+fn check_copy(nodes: &Nodes, user: &UserToken, source: &Node, parent: u64) -> Result<u32> {
+    if !source.allow(nodes, user, UserLevel::SeeData) {
+        return Err(err_forbidden());
+    }
+    let root_group = match parent {
+        0 => Some(source.group),
+        _ => None,
+    };
+    new_node_group(nodes, user, parent, root_group)
+}
+
+/// Check the user can move or copy the node to the target, before the
+/// node with the target name is removed.
+pub fn check_transfer(nodes: &Nodes, user: &UserToken, dto: &NodeTarget, copy: bool) -> Result<()> {
+    let node = nodes.get(&dto.id).ok_or_else(|| err_not_found("node"))?;
+    match copy {
+        true => check_copy(nodes, user, node, dto.parent).map(|_| ()),
+        false => check_move(nodes, user, node, dto.parent),
+    }
+}
+
+/// Move and rename a node.
+pub async fn r#move(server: &State, request: DataRequest<NodeTarget>) -> DataResponseResult<Node> {
+    let DataRequest { user, dto } = request;
+    let mut nodes = server.nodes.write().map_err(err_sync_fail)?;
+    let mut node = nodes
+        .get(&dto.id)
+        .ok_or_else(|| err_not_found("node"))?
+        .clone();
+    check_move(&nodes, &user, &node, dto.parent)?;
+    if node.parent == dto.parent && node.name == dto.name {
+        return data_response_ok(node);
+    }
+    check_free_name(&nodes, dto.parent, &dto.name)?;
+
+    node.parent = dto.parent;
+    node.name = dto.name;
+    node.modified = now();
+    server.storage.save(&key(node.id), &node)?;
+    nodes.insert(node.id, node.clone());
+
+    data_response_ok(node)
+}
+
+/// Copy a node and all its visible descendants.
+/// The copies are owned by the destination folder group, without the lists
+/// of the sources.
+pub async fn copy(server: &State, request: DataRequest<NodeTarget>) -> DataResponseResult<Node> {
+    let DataRequest { user, dto } = request;
+    let mut nodes = server.nodes.write().map_err(err_sync_fail)?;
+    let source = nodes.get(&dto.id).ok_or_else(|| err_not_found("node"))?;
+    let group = check_copy(&nodes, &user, source, dto.parent)?;
+    check_free_name(&nodes, dto.parent, &dto.name)?;
+
+    // Old id to new id, the parent of the source to the destination.
+    let mut copied = std::collections::BTreeMap::from([(source.parent, dto.parent)]);
+    let now = now();
+    for id in subtree(&nodes, dto.id) {
+        let source = &nodes[&id];
+        let Some(&parent) = copied.get(&source.parent) else {
+            continue;
+        };
+        if !source.allow(&nodes, &user, UserLevel::SeeData) {
+            continue;
+        }
+        let node = Node {
+            id: server.new_id("node")?,
+            parent,
+            group,
+            name: match id == dto.id {
+                true => dto.name.clone(),
+                false => source.name.clone(),
+            },
+            modified: now,
+            kind: source.kind.clone(),
+            acl: Acl::new(),
+        };
+        if let NodeKind::File { blob, .. } = &node.kind {
+            blob::acquire(server, blob)?;
+        }
+        server.storage.save(&key(node.id), &node)?;
+        super::version::push(server, &node, user.id)?;
+        copied.insert(id, node.id);
+        nodes.insert(node.id, node);
+    }
+
+    data_response_ok(nodes[&copied[&dto.id]].clone())
+}
+
+#[tokio::test]
+async fn tree_operations() {
+    fn request<T: DTO>(dto: T) -> DataRequest<T> {
+        DataRequest {
+            user: UserToken::DEV_EDITOR,
+            dto,
+        }
+    }
+    let folder = |parent, name: &str| FolderCreate {
+        parent,
+        group: Some(42),
+        name: name.to_string(),
+    };
+    let target = |id, parent, name: &str| NodeTarget {
+        id,
+        parent,
+        name: name.to_string(),
+    };
+
+    let server = crate::app_driver::test_state("tree_operations");
+    let a = create(&server, request(folder(0, "a"))).await.unwrap().dto;
+    let b = create(&server, request(folder(a.id, "b")))
+        .await
+        .unwrap()
+        .dto;
+    assert_eq!(42, b.group);
+    let new_file = crate::io_http::FileTarget::New {
+        parent: b.id,
+        group: None,
+        name: "file.txt".to_string(),
+    };
+    let mut body = axum::body::Body::from("Hello");
+    let file = super::put(&server, UserToken::DEV_EDITOR, new_file, &mut body)
+        .await
+        .unwrap();
+
+    assert!(create(&server, request(folder(a.id, "b"))).await.is_err());
+    assert!(
+        r#move(&server, request(target(a.id, b.id, "a")))
+            .await
+            .is_err()
+    );
+
+    let c = copy(&server, request(target(a.id, 0, "c")))
+        .await
+        .unwrap()
+        .dto;
+    let nodes = server.nodes.read().unwrap().clone();
+    assert_eq!(vec![a.id, b.id, file.id], subtree(&nodes, a.id));
+    assert_eq!(3, subtree(&nodes, c.id).len());
+
+    r#move(&server, request(target(b.id, c.id, "moved")))
+        .await
+        .unwrap();
+    super::trash::delete(&server, request(NodeId { id: a.id }))
+        .await
+        .unwrap();
+    let nodes = server.nodes.read().unwrap().clone();
+    assert_eq!(5, nodes.len());
+    assert_eq!(c.id, nodes[&b.id].parent);
+
+    // A nested folder, copied with its file.
+    let d = copy(&server, request(target(b.id, c.id, "d")))
+        .await
+        .unwrap()
+        .dto;
+    assert_eq!(c.id, d.parent);
+    let nodes = server.nodes.read().unwrap().clone();
+    assert_eq!(file.id, subtree(&nodes, b.id)[1]);
+    let mut names: Vec<_> = subtree(&nodes, c.id)
+        .iter()
+        .map(|id| {
+            let node = &nodes[id];
+            let parent = nodes.get(&node.parent).map(|parent| parent.name.as_str());
+            (node.name.as_str(), parent)
+        })
+        .collect();
+    names.sort();
+    assert_eq!(
+        vec![
+            ("b", Some("c")),
+            ("c", None),
+            ("d", Some("c")),
+            ("file.txt", Some("b")),
+            ("file.txt", Some("d")),
+            ("file.txt", Some("moved")),
+            ("moved", Some("c")),
+        ],
+        names
+    );
+}
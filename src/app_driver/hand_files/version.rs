@@ -0,0 +1,464 @@
+//! History of the file contents.
+//!
+//! Each revision holds a reference to its blob. The last revision is the
+//! current content of the file.
+
+use super::{Node, NodeKind, get_allowed, key as node_key};
+use crate::{
+    app_driver::{
+        State, blob,
+        error::{err_forbidden, err_not_found, err_sync_fail},
+        now,
+    },
+    io_http::{DTO, DataRequest, DataResponseResult, Validator, data_response_ok},
+    *,
+};
+use axum::http::StatusCode;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct Revision {
+    /// Number of the revision, the first is `1`.
+    pub version: u64,
+    /// Identifier of the user who uploaded the content.
+    pub author: u32,
+    /// Creation time, in seconds since Epoch.
+    pub time: u64,
+    /// Size in bytes.
+    pub size: u64,
+    pub mime: String,
+    pub blob: String,
+}
+
+/// How long the old revisions are kept, for all files of a group.
+/// The current revision is always kept.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct Retention {
+    pub group: u32,
+    /// Keep only this number of last revisions.
+    pub keep_last: Option<u64>,
+    /// Keep only the revisions younger than this number of days.
+    pub keep_days: Option<u64>,
+}
+
+/// Most revisions kept by `keep_last`.
+const MAX_KEEP_LAST: u64 = 100_000;
+/// Most days kept by `keep_days`, a century.
+const MAX_KEEP_DAYS: u64 = 36_500;
+
+impl DTO for Retention {
+    fn check(&self) -> Result<()> {
+        Validator::new()
+            .check(
+                "keep_last",
+                self.keep_last.is_none_or(|keep| keep <= MAX_KEEP_LAST),
+                "too_large",
+                "Need at most 100000 revisions",
+            )
+            .check(
+                "keep_days",
+                self.keep_days.is_none_or(|days| days <= MAX_KEEP_DAYS),
+                "too_large",
+                "Need at most 36500 days",
+            )
+            .finish()
+    }
+
+    fn check_user(&self, user: &UserToken) -> Result<()> {
+        match user.allow(self.group, UserLevel::Admin) {
+            true => Ok(()),
+            false => Err(err_forbidden()),
+        }
+    }
+}
+
+fn key(id: u64) -> String {
+    format!("version/{id}")
+}
+
+fn retention_key(group: u32) -> String {
+    format!("retention/{group}")
+}
+
+/// Get all the revisions of the file, the oldest first.
+pub fn load(server: &State, id: u64) -> Result<Vec<Revision>> {
+    Ok(server.storage.load(&key(id))?.unwrap_or_default())
+}
+
+/// Give a first revision to the files created before the revisions, so the
+/// reference of their blob is released with the revision, like the others.
+pub fn init(server: &State, nodes: &super::Nodes) -> Result<()> {
+    for node in nodes.values().filter(|node| !node.is_folder()) {
+        if load(server, node.id)?.is_empty() {
+            push(server, node, 0)?;
+        }
+    }
+    Ok(())
+}
+
+/// Add the node content as a new revision, and remove the old revisions
+/// out of the group retention.
+/// The new revision takes a blob reference already counted.
+pub fn push(server: &State, node: &Node, author: u32) -> Result<()> {
+    let NodeKind::File { mime, size, blob } = &node.kind else {
+        return Ok(());
+    };
+    let mut revisions = load(server, node.id)?;
+    revisions.push(Revision {
+        version: revisions.last().map_or(1, |r| r.version + 1),
+        author,
+        time: node.modified,
+        size: *size,
+        mime: mime.clone(),
+        blob: blob.clone(),
+    });
+
+    let retention = server
+        .storage
+        .load::<Retention>(&retention_key(node.group))?
+        .unwrap_or_default();
+    let removed = prune(&mut revisions, &retention, now());
+
+    server.storage.save(&key(node.id), &revisions)?;
+    for revision in removed {
+        blob::release(server, &revision.blob)?;
+    }
+    Ok(())
+}
+
+/// Remove all the revisions of a deleted file.
+pub fn remove_all(server: &State, id: u64) -> Result<()> {
+    let revisions = load(server, id)?;
+    server.storage.remove(&key(id))?;
+    for revision in revisions {
+        blob::release(server, &revision.blob)?;
+    }
+    Ok(())
+}
+
+/// Remove and return the old revisions out of the retention.
+fn prune(revisions: &mut Vec<Revision>, retention: &Retention, now: u64) -> Vec<Revision> {
+    let len = revisions.len() as u64;
+    let mut i = 0u64;
+    let (kept, removed) = std::mem::take(revisions).into_iter().partition(|revision| {
+        i += 1;
+        let is_current = i == len;
+        // Saturate, the stored retentions were not bounded before.
+        let too_many = retention
+            .keep_last
+            .is_some_and(|keep| i.saturating_add(keep) <= len);
+        let too_old = retention
+            .keep_days
+            .is_some_and(|days| revision.time.saturating_add(days.saturating_mul(24 * 3600)) < now);
+        is_current || !(too_many || too_old)
+    });
+    *revisions = kept;
+    removed
+}
+
+/* HANDLERS */
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct VersionRequest {
+    pub id: u64,
+    pub version: u64,
+}
+
+impl DTO for VersionRequest {}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct DiffRequest {
+    pub id: u64,
+    pub from: u64,
+    pub to: u64,
+}
+
+impl DTO for DiffRequest {}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct GroupRequest {
+    pub group: u32,
+}
+
+impl DTO for GroupRequest {}
+
+/// List the revisions of a file.
+pub async fn versions(
+    server: &State,
+    request: DataRequest<super::NodeId>,
+) -> DataResponseResult<Vec<Revision>> {
+    let id = request.dto.id;
+    get_allowed(server, &request.user, id, UserLevel::SeeData)?;
+    data_response_ok(load(server, id)?)
+}
+
+/// Restore an old revision, as a new revision.
+pub async fn restore(
+    server: &State,
+    request: DataRequest<VersionRequest>,
+) -> DataResponseResult<Node> {
+    let DataRequest { user, dto } = request;
+    let mut nodes = server.nodes.write().map_err(err_sync_fail)?;
+    let node = nodes.get(&dto.id).ok_or_else(|| err_not_found("node"))?;
+    if !node.allow(&nodes, &user, UserLevel::EditData) {
+        return Err(err_forbidden());
+    }
+    let node = nodes
+        .get_mut(&dto.id)
+        .ok_or_else(|| err_not_found("node"))?;
+    let revision = load(server, dto.id)?
+        .into_iter()
+        .find(|revision| revision.version == dto.version)
+        .ok_or_else(|| err_not_found("version"))?;
+
+    blob::acquire(server, &revision.blob)?;
+    node.kind = NodeKind::File {
+        mime: revision.mime,
+        size: revision.size,
+        blob: revision.blob,
+    };
+    node.modified = now();
+    server.storage.save(&node_key(node.id), &node)?;
+    push(server, node, user.id)?;
+
+    data_response_ok(node.clone())
+}
+
+/// One line of a diff.
+#[derive(Debug, Clone, Serialize, PartialEq, JsonSchema)]
+#[serde(tag = "op", content = "line", rename_all = "lowercase")]
+pub enum DiffLine {
+    Equal(String),
+    Delete(String),
+    Insert(String),
+}
+
+/// Largest revision compared by a diff, in bytes.
+const MAX_DIFF_SIZE: u64 = 4 << 20;
+
+/// Get the line difference between two revisions of a text file.
+pub async fn diff(
+    server: &State,
+    request: DataRequest<DiffRequest>,
+) -> DataResponseResult<Vec<DiffLine>> {
+    let DataRequest { user, dto } = request;
+    get_allowed(server, &user, dto.id, UserLevel::SeeData)?;
+    let revisions = load(server, dto.id)?;
+    let get = |version| {
+        revisions
+            .iter()
+            .find(|revision| revision.version == version)
+            .ok_or_else(|| err_not_found("version"))
+    };
+    let (from, to) = (get(dto.from)?.clone(), get(dto.to)?.clone());
+    for revision in [&from, &to] {
+        if !revision.mime.starts_with("text/") {
+            return Err(WrapError::http(
+                StatusCode::BAD_REQUEST,
+                "Only the text files can be compared",
+            ));
+        }
+        if revision.size > MAX_DIFF_SIZE {
+            return Err(WrapError::http(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "The files are too large to be compared",
+            ));
+        }
+    }
+
+    let from = server.blobs.read(&from.blob).await?;
+    let to = server.blobs.read(&to.blob).await?;
+    let lines = tokio::task::spawn_blocking(move || {
+        let from = String::from_utf8_lossy(&from);
+        let to = String::from_utf8_lossy(&to);
+        diff_lines(
+            &from.lines().collect::<Vec<_>>(),
+            &to.lines().collect::<Vec<_>>(),
+        )
+    })
+    .await
+    .map_err(|err| {
+        WrapError::http(StatusCode::INTERNAL_SERVER_ERROR, "Diff fail").add_err(err)
+    })??;
+    data_response_ok(lines)
+}
+
+/// Compute the diff with the longest common subsequence of lines.
+fn diff_lines(from: &[&str], to: &[&str]) -> Result<Vec<DiffLine>> {
+    // Skip the common prefix and suffix.
+    let prefix = from.iter().zip(to).take_while(|(a, b)| a == b).count();
+    let suffix = from[prefix..]
+        .iter()
+        .rev()
+        .zip(to[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &from[prefix..from.len() - suffix];
+    let b = &to[prefix..to.len() - suffix];
+
+    const MAX_CELLS: usize = 16 << 20;
+    if (a.len() + 1).saturating_mul(b.len() + 1) > MAX_CELLS {
+        return Err(WrapError::http(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "The files are too different to be compared",
+        ));
+    }
+
+    // lcs[i][j] is the LCS length of a[i..] and b[j..].
+    let width = b.len() + 1;
+    let mut lcs = vec![0u32; (a.len() + 1) * width];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i * width + j] = match a[i] == b[j] {
+                true => lcs[(i + 1) * width + j + 1] + 1,
+                false => lcs[(i + 1) * width + j].max(lcs[i * width + j + 1]),
+            };
+        }
+    }
+
+    let mut lines: Vec<DiffLine> = from[..prefix]
+        .iter()
+        .map(|line| DiffLine::Equal(line.to_string()))
+        .collect();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            lines.push(DiffLine::Equal(a[i].to_string()));
+            i += 1;
+            j += 1;
+        } else if i < a.len()
+            && (j == b.len() || lcs[(i + 1) * width + j] >= lcs[i * width + j + 1])
+        {
+            lines.push(DiffLine::Delete(a[i].to_string()));
+            i += 1;
+        } else {
+            lines.push(DiffLine::Insert(b[j].to_string()));
+            j += 1;
+        }
+    }
+    lines.extend(
+        from[from.len() - suffix..]
+            .iter()
+            .map(|line| DiffLine::Equal(line.to_string())),
+    );
+
+    Ok(lines)
+}
+
+/// Get the retention of a group.
+pub async fn retention_get(
+    server: &State,
+    request: DataRequest<GroupRequest>,
+) -> DataResponseResult<Retention> {
+    let group = request.dto.group;
+    if !request.user.allow(group, UserLevel::SeeData) {
+        return Err(err_forbidden());
+    }
+    let retention = server.storage.load(&retention_key(group))?;
+    data_response_ok(retention.unwrap_or(Retention {
+        group,
+        ..Default::default()
+    }))
+}
+
+/// Set the retention of a group, applied at the next revision of each file.
+pub async fn retention_set(
+    server: &State,
+    request: DataRequest<Retention>,
+) -> DataResponseResult<Retention> {
+    server
+        .storage
+        .save(&retention_key(request.dto.group), &request.dto)?;
+    data_response_ok(request.dto)
+}
+
+#[tokio::test]
+async fn file_without_revision() {
+    let server = crate::app_driver::test_state("file_without_revision");
+    let new_file = crate::io_http::FileTarget::New {
+        parent: 0,
+        group: Some(42),
+        name: "old.txt".to_string(),
+    };
+    let mut body = axum::body::Body::from("Old");
+    let file = super::put(&server, UserToken::DEV_EDITOR, new_file, &mut body)
+        .await
+        .unwrap();
+    // As stored before the revisions.
+    server.storage.remove(&key(file.id)).unwrap();
+
+    init(&server, &server.nodes.read().unwrap()).unwrap();
+    let revisions = load(&server, file.id).unwrap();
+    assert_eq!(1, revisions.len());
+    assert_eq!(0, revisions[0].author);
+
+    let target = crate::io_http::FileTarget::Replace(file.id);
+    let mut body = axum::body::Body::from("New");
+    super::put(&server, UserToken::DEV_EDITOR, target, &mut body)
+        .await
+        .unwrap();
+    assert_eq!(2, load(&server, file.id).unwrap().len());
+    assert!(blob::check(&server, false).await.unwrap().is_ok());
+}
+
+#[test]
+fn test_prune() {
+    let revisions = (1..=5)
+        .map(|version| Revision {
+            version,
+            time: version * 24 * 3600,
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+    let versions = |revisions: &[Revision]| revisions.iter().map(|r| r.version).collect::<Vec<_>>();
+    let retention = |keep_last, keep_days| Retention {
+        group: 42,
+        keep_last,
+        keep_days,
+    };
+
+    let mut kept = revisions.clone();
+    assert!(prune(&mut kept, &retention(None, None), 100).is_empty());
+
+    let mut kept = revisions.clone();
+    let removed = prune(&mut kept, &retention(Some(2), None), 0);
+    assert_eq!(vec![4, 5], versions(&kept));
+    assert_eq!(vec![1, 2, 3], versions(&removed));
+
+    let mut kept = revisions.clone();
+    prune(&mut kept, &retention(None, Some(2)), 5 * 24 * 3600);
+    assert_eq!(vec![3, 4, 5], versions(&kept));
+
+    let mut kept = revisions.clone();
+    prune(&mut kept, &retention(Some(10), Some(1)), 100 * 24 * 3600);
+    assert_eq!(vec![5], versions(&kept));
+
+    // Keep all, without an overflow.
+    let mut kept = revisions.clone();
+    let all = retention(Some(u64::MAX), Some(u64::MAX));
+    assert!(prune(&mut kept, &all, u64::MAX).is_empty());
+    assert!(all.check().is_err());
+    assert!(
+        retention(Some(MAX_KEEP_LAST), Some(MAX_KEEP_DAYS))
+            .check()
+            .is_ok()
+    );
+}
+
+#[test]
+fn test_diff_lines() {
+    use DiffLine::*;
+    let s = |s: &str| s.to_string();
+    assert_eq!(
+        vec![
+            Equal(s("a")),
+            Delete(s("b")),
+            Insert(s("x")),
+            Equal(s("c")),
+            Insert(s("d")),
+            Equal(s("e")),
+        ],
+        diff_lines(&["a", "b", "c", "e"], &["a", "x", "c", "d", "e"]).unwrap()
+    );
+}
@@ -0,0 +1,538 @@
+//! The groups and their members.
+//!
+//! The level of each member is stored in its [`User`], and checked in the
+//! user directory rather than in the token, which can be older.
+//! The rules follow [`UserLevel`]:
+//! - an administrator adds members and promotes them until administrator;
+//! - a super administrator also degrades and removes members, and removes
+//!   the group;
+//! - a group always keeps a super administrator.
+
+use crate::{
+    app_driver::{
+        State,
+        error::{err_forbidden, err_not_found, err_sync_fail},
+        hand_user::{self, User, Users},
+        now,
+    },
+    io_http::{DTO, DataRequest, DataResponse, DataResponseResult, Validator, data_response_ok},
+    *,
+};
+use axum::http::StatusCode;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct Group {
+    /// Shared with the user identifiers.
+    pub id: u32,
+    pub name: String,
+}
+
+/// All the groups indexed by id.
+pub type Groups = std::collections::BTreeMap<u32, Group>;
+
+/// A user in a group.
+#[derive(Debug, Clone, Default, Serialize, PartialEq, JsonSchema)]
+pub struct Member {
+    pub id: u32,
+    pub login: String,
+    pub name: String,
+    pub level: UserLevel,
+}
+
+fn key(id: u32) -> String {
+    format!("group/{id}")
+}
+
+/// Load all the groups from the storage.
+pub fn init(server: &State) -> Result<()> {
+    let mut groups = server.groups.write().map_err(err_sync_fail)?;
+    for name in server.storage.list("group")? {
+        if let Some(group) = server.storage.load::<Group>(&format!("group/{name}"))? {
+            groups.insert(group.id, group);
+        }
+    }
+    Ok(())
+}
+
+/// The level of the user in the group.
+fn level(users: &Users, user: u32, group: u32) -> UserLevel {
+    users
+        .get(&user)
+        .and_then(|user| user.groups.get(&group))
+        .copied()
+        .unwrap_or_default()
+}
+
+/// Check the group keeps a super administrator other than the user.
+fn check_other_super_admin(users: &Users, group: u32, user: u32) -> Result<()> {
+    match users
+        .values()
+        .any(|other| other.id != user && level(users, other.id, group) == UserLevel::SuperAdmin)
+    {
+        true => Ok(()),
+        false => Err(WrapError::http(
+            StatusCode::CONFLICT,
+            "The group need a super administrator",
+        )),
+    }
+}
+
+/// Set the level of the user in the group, `None` to remove it.
+fn save_level(server: &State, user: &mut User, group: u32, level: UserLevel) -> Result<()> {
+    let mut updated = user.clone();
+    match level {
+        UserLevel::None => updated.groups.remove(&group),
+        level => updated.groups.insert(group, level),
+    };
+    updated.rights_changed = now();
+    server.storage.save(&hand_user::key(user.id), &updated)?;
+    *user = updated;
+    Ok(())
+}
+
+/// The response, with a new token if the user changed its own rights.
+/// The other members get their new token at their next request.
+fn response<T: Serialize>(user: &UserToken, changed: &User, dto: T) -> DataResponseResult<T> {
+    Ok(DataResponse {
+        user: (user.id == changed.id).then(|| changed.token()),
+        dto,
+    })
+}
+
+/* HANDLERS */
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct GroupCreate {
+    pub name: String,
+}
+
+impl DTO for GroupCreate {
+    fn check(&self) -> Result<()> {
+        Validator::new()
+            .text("name", &self.name, |name| name.required())
+            .finish()
+    }
+    fn check_user(&self, user: &UserToken) -> Result<()> {
+        match user.level >= UserLevel::Admin {
+            true => Ok(()),
+            false => Err(err_forbidden()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct GroupId {
+    pub group: u32,
+}
+
+impl DTO for GroupId {}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct MemberLevel {
+    pub group: u32,
+    pub user: u32,
+    pub level: UserLevel,
+}
+
+impl DTO for MemberLevel {
+    fn check(&self) -> Result<()> {
+        Validator::new()
+            .check(
+                "level",
+                self.level != UserLevel::None,
+                "not_allowed",
+                "The level can not be None, remove the member",
+            )
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct MemberId {
+    pub group: u32,
+    pub user: u32,
+}
+
+impl DTO for MemberId {}
+
+/// Create a group, the creator is its super administrator.
+/// Only for the server administrators.
+pub async fn create(
+    server: &State,
+    request: DataRequest<GroupCreate>,
+) -> DataResponseResult<Group> {
+    let DataRequest { user, dto } = request;
+    let mut groups = server.groups.write().map_err(err_sync_fail)?;
+    let mut users = server.users.write().map_err(err_sync_fail)?;
+    let creator = users.get_mut(&user.id).ok_or_else(err_forbidden)?;
+
+    let group = Group {
+        id: server.new_id("principal")? as u32,
+        name: dto.name,
+    };
+    server.storage.save(&key(group.id), &group)?;
+    groups.insert(group.id, group.clone());
+    save_level(server, creator, group.id, UserLevel::SuperAdmin)?;
+
+    Ok(DataResponse {
+        user: Some(creator.token()),
+        dto: group,
+    })
+}
+
+/// List the members of a group, for its editors.
+pub async fn members(
+    server: &State,
+    request: DataRequest<GroupId>,
+) -> DataResponseResult<Vec<Member>> {
+    let DataRequest { user, dto } = request;
+    let users = server.users.read().map_err(err_sync_fail)?;
+    if level(&users, user.id, dto.group) < UserLevel::EditData {
+        return Err(err_forbidden());
+    }
+    let members = users
+        .values()
+        .filter_map(|user| {
+            Some(Member {
+                id: user.id,
+                login: user.login.clone(),
+                name: user.name.clone(),
+                level: *user.groups.get(&dto.group)?,
+            })
+        })
+        .collect();
+    data_response_ok(members)
+}
+
+/// Add a user in the group, at most at the level of the administrator.
+pub async fn add_member(
+    server: &State,
+    request: DataRequest<MemberLevel>,
+) -> DataResponseResult<User> {
+    let DataRequest { user, dto } = request;
+    let groups = server.groups.read().map_err(err_sync_fail)?;
+    let mut users = server.users.write().map_err(err_sync_fail)?;
+    if !groups.contains_key(&dto.group) {
+        return Err(err_not_found("group"));
+    }
+    let actor = level(&users, user.id, dto.group);
+    if actor < UserLevel::Admin || dto.level > actor {
+        return Err(err_forbidden());
+    }
+    let member = users
+        .get_mut(&dto.user)
+        .ok_or_else(|| err_not_found("user"))?;
+    if member.groups.contains_key(&dto.group) {
+        return Err(WrapError::http(
+            StatusCode::CONFLICT,
+            "The user is already a member",
+        ));
+    }
+
+    save_level(server, member, dto.group, dto.level)?;
+    response(&user, member, member.clone())
+}
+
+/// Change the level of a member.
+/// An administrator only promotes until its level, a super administrator
+/// also degrades.
+pub async fn set_level(
+    server: &State,
+    request: DataRequest<MemberLevel>,
+) -> DataResponseResult<User> {
+    let DataRequest { user, dto } = request;
+    let mut users = server.users.write().map_err(err_sync_fail)?;
+    let actor = level(&users, user.id, dto.group);
+    let old = level(&users, dto.user, dto.group);
+    if old == UserLevel::None {
+        return Err(err_not_found("member"));
+    }
+    let allowed = match dto.level > old {
+        true => actor >= UserLevel::Admin && dto.level <= actor,
+        false => actor == UserLevel::SuperAdmin,
+    };
+    if !allowed {
+        return Err(err_forbidden());
+    } else if old == UserLevel::SuperAdmin && dto.level < old {
+        check_other_super_admin(&users, dto.group, dto.user)?;
+    }
+
+    let member = users
+        .get_mut(&dto.user)
+        .ok_or_else(|| err_not_found("user"))?;
+    save_level(server, member, dto.group, dto.level)?;
+    response(&user, member, member.clone())
+}
+
+/// Remove a member, by a super administrator or by the member itself.
+pub async fn remove_member(
+    server: &State,
+    request: DataRequest<MemberId>,
+) -> DataResponseResult<()> {
+    let DataRequest { user, dto } = request;
+    let mut users = server.users.write().map_err(err_sync_fail)?;
+    let old = level(&users, dto.user, dto.group);
+    if old == UserLevel::None {
+        return Err(err_not_found("member"));
+    } else if user.id != dto.user && level(&users, user.id, dto.group) != UserLevel::SuperAdmin {
+        return Err(err_forbidden());
+    } else if old == UserLevel::SuperAdmin {
+        check_other_super_admin(&users, dto.group, dto.user)?;
+    }
+
+    let member = users
+        .get_mut(&dto.user)
+        .ok_or_else(|| err_not_found("user"))?;
+    save_level(server, member, dto.group, UserLevel::None)?;
+    response(&user, member, ())
+}
+
+/// Remove an empty group and all its memberships.
+pub async fn delete(server: &State, request: DataRequest<GroupId>) -> DataResponseResult<()> {
+    let DataRequest { user, dto } = request;
+    let nodes = server.nodes.read().map_err(err_sync_fail)?;
+    let trash = server.trash.lock().map_err(err_sync_fail)?;
+    let wiki = server.wiki.read().map_err(err_sync_fail)?;
+    let mut groups = server.groups.write().map_err(err_sync_fail)?;
+    let mut users = server.users.write().map_err(err_sync_fail)?;
+    if !groups.contains_key(&dto.group) {
+        return Err(err_not_found("group"));
+    } else if level(&users, user.id, dto.group) != UserLevel::SuperAdmin {
+        return Err(err_forbidden());
+    } else if nodes.values().any(|node| node.group == dto.group)
+        || trash.values().any(|entry| entry.group == dto.group)
+        || wiki.values().any(|page| page.group == dto.group)
+    {
+        return Err(WrapError::http(
+            StatusCode::CONFLICT,
+            "The group still owns some files or wiki pages",
+        ));
+    }
+
+    for member in users.values_mut() {
+        if member.groups.contains_key(&dto.group) {
+            save_level(server, member, dto.group, UserLevel::None)?;
+        }
+    }
+    server.storage.remove(&key(dto.group))?;
+    groups.remove(&dto.group);
+    match users.get(&user.id) {
+        Some(actor) => response(&user, actor, ()),
+        None => data_response_ok(()),
+    }
+}
+
+#[tokio::test]
+async fn level_transitions() {
+    use crate::app_driver::hand_files;
+    use UserLevel::*;
+    let server = crate::app_driver::test_state("level_transitions");
+    let mut ids = Vec::new();
+    for (login, level) in [
+        ("root", Admin),
+        ("sa", None),
+        ("admin", None),
+        ("editor", None),
+        ("bob", None),
+    ] {
+        let new = hand_user::NewUser {
+            login: login.to_string(),
+            name: login.to_string(),
+            password: "12345678".to_string(),
+            level,
+        };
+        ids.push(hand_user::add(&server, new).await.unwrap().id);
+    }
+    let [root, sa, admin, editor, bob] = ids[..] else {
+        unreachable!()
+    };
+    let token = |id| UserToken {
+        id,
+        ..Default::default()
+    };
+    fn request<T: DTO>(user: UserToken, dto: T) -> DataRequest<T> {
+        DataRequest { user, dto }
+    }
+    let change = |group, user, level| MemberLevel { group, user, level };
+    let remove = |group, user| MemberId { group, user };
+
+    // Only a server administrator creates a group.
+    let dto = GroupCreate {
+        name: "team".to_string(),
+    };
+    assert!(dto.check_user(&token(sa)).is_err());
+    let root_token = server.users.read().unwrap()[&root].token();
+    let response = create(&server, request(root_token, dto)).await.unwrap();
+    let group = response.dto.id;
+    assert!(response.user.unwrap().allow(group, SuperAdmin));
+    let level_of = |user| level(&server.users.read().unwrap(), user, group);
+    assert_eq!(SuperAdmin, level_of(root));
+
+    // A super administrator adds a super administrator.
+    add_member(&server, request(token(root), change(group, sa, SuperAdmin)))
+        .await
+        .unwrap();
+    add_member(&server, request(token(sa), change(group, admin, Admin)))
+        .await
+        .unwrap();
+
+    // An administrator adds until administrator.
+    assert!(
+        add_member(
+            &server,
+            request(token(admin), change(group, editor, SuperAdmin))
+        )
+        .await
+        .is_err()
+    );
+    add_member(
+        &server,
+        request(token(admin), change(group, editor, SeeData)),
+    )
+    .await
+    .unwrap();
+    assert!(
+        add_member(
+            &server,
+            request(token(admin), change(group, editor, SeeData))
+        )
+        .await
+        .is_err()
+    );
+
+    // An editor adds nobody.
+    add_member(&server, request(token(admin), change(group, bob, SeeData)))
+        .await
+        .unwrap();
+    set_level(
+        &server,
+        request(token(admin), change(group, editor, EditData)),
+    )
+    .await
+    .unwrap();
+    assert!(
+        add_member(&server, request(token(editor), change(group, 999, SeeData)))
+            .await
+            .is_err()
+    );
+    assert!(
+        set_level(
+            &server,
+            request(token(editor), change(group, bob, EditData))
+        )
+        .await
+        .is_err()
+    );
+
+    // An administrator promotes until administrator, and does not degrade.
+    let before = now() - 1;
+    set_level(&server, request(token(admin), change(group, editor, Admin)))
+        .await
+        .unwrap();
+    assert_eq!(Admin, level_of(editor));
+    // The token of the member is replaced at its next request.
+    use crate::HTTPState;
+    let current = server.current_user(&token(editor), before).unwrap();
+    assert!(current.allow(group, Admin));
+    assert!(server.current_user(&token(editor), now() + 1).is_none());
+    assert!(
+        set_level(
+            &server,
+            request(token(admin), change(group, editor, SuperAdmin))
+        )
+        .await
+        .is_err()
+    );
+    assert!(
+        set_level(
+            &server,
+            request(token(admin), change(group, editor, SeeData))
+        )
+        .await
+        .is_err()
+    );
+
+    // A super administrator degrades and promotes to super administrator.
+    set_level(&server, request(token(sa), change(group, editor, SeeData)))
+        .await
+        .unwrap();
+    assert_eq!(SeeData, level_of(editor));
+    set_level(
+        &server,
+        request(token(sa), change(group, admin, SuperAdmin)),
+    )
+    .await
+    .unwrap();
+    set_level(&server, request(token(sa), change(group, admin, Admin)))
+        .await
+        .unwrap();
+
+    // Only a super administrator removes a member, or the member itself.
+    assert!(
+        remove_member(&server, request(token(admin), remove(group, bob)))
+            .await
+            .is_err()
+    );
+    remove_member(&server, request(token(bob), remove(group, bob)))
+        .await
+        .unwrap();
+    remove_member(&server, request(token(sa), remove(group, editor)))
+        .await
+        .unwrap();
+    assert_eq!(None, level_of(editor));
+
+    // The last super administrator stays.
+    remove_member(&server, request(token(sa), remove(group, root)))
+        .await
+        .unwrap();
+    assert!(
+        set_level(&server, request(token(sa), change(group, sa, Admin)))
+            .await
+            .is_err()
+    );
+    assert!(
+        remove_member(&server, request(token(sa), remove(group, sa)))
+            .await
+            .is_err()
+    );
+
+    // Only a super administrator removes the group, once empty.
+    let mut body = axum::body::Body::from("Hello");
+    let target = crate::io_http::FileTarget::New {
+        parent: 0,
+        group: Some(group),
+        name: "file.txt".to_string(),
+    };
+    let mut editor_token = token(sa);
+    editor_token.groups[0] = (EditData, group);
+    let file = hand_files::put(&server, editor_token.clone(), target, &mut body)
+        .await
+        .unwrap();
+    assert!(
+        delete(&server, request(token(admin), GroupId { group }))
+            .await
+            .is_err()
+    );
+    assert!(
+        delete(&server, request(token(sa), GroupId { group }))
+            .await
+            .is_err()
+    );
+    let node = hand_files::NodeId { id: file.id };
+    hand_files::trash::delete(&server, request(editor_token, node))
+        .await
+        .unwrap();
+    assert!(
+        delete(&server, request(token(sa), GroupId { group }))
+            .await
+            .is_err()
+    );
+    hand_files::trash::purge(&server, |_| true).unwrap();
+    delete(&server, request(token(sa), GroupId { group }))
+        .await
+        .unwrap();
+    assert_eq!(None, level_of(admin));
+    assert!(server.groups.read().unwrap().is_empty());
+}
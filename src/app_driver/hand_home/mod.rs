@@ -1,16 +1,19 @@
 use crate::{
     app_driver::{
         State,
-        error::{err_empty_values, err_sync_fail},
+        error::{err_forbidden, err_sync_fail},
+        hand_acl::{self, Acl, AclEntry},
+    },
+    io_http::{
+        CachedPage, DTO, DataRequest, DataResponse, DataResponseResult, EmptyDTO, Validator,
+        data_response_ok,
     },
-    io_http::{DTO, DataRequest, DataResponse, DataResponseResult, EmptyDTO, data_response_ok},
     *,
 };
-use axum::http::StatusCode;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, JsonSchema)]
 pub struct Page {
     pub title: String,
     pub description: String,
@@ -18,42 +21,95 @@ pub struct Page {
 }
 
 impl DTO for Page {
-    fn check(self: &Self) -> Result<()> {
-        if self.title.len() == 0 || self.description.len() == 0 || self.body.len() == 0 {
-            Err(err_empty_values("need: title, description, body"))
-        } else {
-            Ok(())
-        }
-    }
-    fn check_user(self: &Self, user: &UserToken) -> Result<()> {
-        match user.allow(42, UserLevel::EditData) {
-            true => Ok(()),
-            false => Err(WrapError::http(
-                StatusCode::FORBIDDEN,
-                "You can not access to this resources",
-            )),
-        }
+    fn check(&self) -> Result<()> {
+        Validator::new()
+            .text("title", &self.title, |title| title.required())
+            .text("description", &self.description, |description| {
+                description.required()
+            })
+            .text("body", &self.body, |body| body.required())
+            .finish()
     }
 }
 
+/// Storage key of the home page.
+const KEY: &str = "home";
+/// Storage key of the list of the home page.
+const ACL_KEY: &str = "home_acl";
+
 pub fn init(server: &State) -> Result<()> {
     let mut page = server.home.lock().map_err(err_sync_fail)?;
 
-    *page = Page {
+    *page = server.storage.load(KEY)?.unwrap_or_else(|| Page {
         title: "Brume server".to_string(),
         description: "The brume server home page.".to_string(),
         body: "Yolo".to_string(),
-    };
-    render(server, &page)?;
+    });
+    publish(server, render(server, &page)?)?;
 
+    *server.home_acl.lock().map_err(err_sync_fail)? =
+        server.storage.load(ACL_KEY)?.unwrap_or_else(default_acl);
     Ok(())
 }
 
+/// The list before any change: the editors of the group 42 edit the page,
+/// like before the lists.
+fn default_acl() -> Acl {
+    vec![AclEntry {
+        principal: hand_acl::Principal::Group(42),
+        level: UserLevel::EditData,
+        deny: false,
+    }]
+}
+
+/// The level of the user on the page: a server administrator keeps its
+/// level, the others get it from the list.
+fn level(acl: &[AclEntry], user: &UserToken) -> UserLevel {
+    match user.level >= UserLevel::Admin {
+        true => user.level,
+        false => hand_acl::level(None, &[acl], user),
+    }
+}
+
+fn check_editor(server: &State, user: &UserToken, need: UserLevel) -> Result<()> {
+    let got = level(&server.home_acl.lock().map_err(err_sync_fail)?, user);
+    match got != UserLevel::None && need <= got {
+        true => Ok(()),
+        false => Err(err_forbidden()),
+    }
+}
+
+/// The list of the page.
+pub fn acl(server: &State) -> Result<Acl> {
+    Ok(server.home_acl.lock().map_err(err_sync_fail)?.clone())
+}
+
+/// Replace the list of the page, the user need to administrate it.
+/// Return the new list of the page.
+pub fn set_acl(server: &State, user: &UserToken, acl: Acl) -> Result<hand_acl::Lists> {
+    let mut current = server.home_acl.lock().map_err(err_sync_fail)?;
+    if user.level < UserLevel::Admin {
+        hand_acl::check_set(None, &[&current], &[&acl], user)?;
+    }
+    server.storage.save(ACL_KEY, &acl)?;
+    *current = acl;
+    Ok(hand_acl::Lists {
+        group: None,
+        lists: vec![current.clone()],
+        admins: true,
+    })
+}
+
 pub async fn set(
     server: &State,
-    DataRequest { dto, .. }: DataRequest<Page>,
+    DataRequest { user, dto }: DataRequest<Page>,
 ) -> Result<DataResponse<Page>> {
+    check_editor(server, &user, UserLevel::EditData)?;
     let mut home = server.home.lock().map_err(err_sync_fail)?;
+    // A page that fails to render is not stored.
+    let rendered = render(server, &dto)?;
+    server.storage.save(KEY, &dto)?;
+    publish(server, rendered)?;
     *home = dto.clone();
     data_response_ok(dto)
 }
@@ -63,14 +119,68 @@ pub async fn get(server: &State, _: DataRequest<EmptyDTO>) -> DataResponseResult
     data_response_ok(home.clone())
 }
 
-fn render(server: &State, page: &Page) -> Result<()> {
-    let content = format!(
-        "{}\r\n=====\r\n\r\n*{}*\r\n\r\n{}",
-        page.title, page.description, page.body,
-    );
+/// Generate the HTML document of the page, the body is Markdown.
+fn render(server: &State, page: &Page) -> Result<CachedPage> {
+    let content = server.theme.render(
+        "home.html",
+        minijinja::context! {
+            title => page.title,
+            description => page.description,
+            body => theme::safe(markdown::to_html(&page.body)),
+        },
+    )?;
 
-    let mut pages = server.pages.write().map_err(err_sync_fail)?;
-    pages.insert(String::from("/"), (bmime::TEXT, Arc::new(content.into())));
+    Ok(CachedPage::new(bmime::HTML, content))
+}
 
+/// Serve the HTML document of the page at `/`.
+fn publish(server: &State, page: CachedPage) -> Result<()> {
+    let mut pages = server.pages.write().map_err(err_sync_fail)?;
+    pages.insert(String::from("/"), page);
     Ok(())
 }
+
+#[tokio::test]
+async fn restart_keep_home() {
+    use crate::app_driver::{
+        blob::BlobStore,
+        storage::{DirStorage, test_dir},
+    };
+
+    let dir = test_dir("restart_keep_home");
+    let open = || {
+        let storage = DirStorage::open(dir.join("db")).unwrap();
+        let blobs = BlobStore::open(dir.join("blob")).unwrap();
+        let keys = crate::io_http::TokenKeys::random().unwrap();
+        State::new(Box::new(storage), blobs, keys, Default::default()).unwrap()
+    };
+    let page = Page {
+        title: "Title".to_string(),
+        description: "Description".to_string(),
+        body: "Body".to_string(),
+    };
+
+    let request = || DataRequest {
+        user: UserToken::DEV_EDITOR,
+        dto: page.clone(),
+    };
+    let admin = UserToken {
+        level: UserLevel::Admin,
+        ..Default::default()
+    };
+
+    // The editors of the group 42 edit the page, until the administrators
+    // remove the group.
+    let server = open();
+    assert_eq!(default_acl(), acl(&server).unwrap());
+    set(&server, request()).await.unwrap();
+    assert!(set_acl(&server, &UserToken::DEV_EDITOR, Vec::new()).is_err());
+    set_acl(&server, &admin, Vec::new()).unwrap();
+    assert!(set(&server, request()).await.is_err());
+    drop(server);
+
+    let server = open();
+    assert_eq!(page, *server.home.lock().unwrap());
+    assert!(acl(&server).unwrap().is_empty());
+    assert!(set(&server, request()).await.is_err());
+}
@@ -0,0 +1,576 @@
+//! The user directory, and the authentication with a password.
+//!
+//! The password hashes are stored apart from the users, so a [`User`] can be
+//! sent to the clients. They are only read at login.
+
+use crate::{
+    app_driver::{
+        State,
+        error::{err_forbidden, err_not_found, err_sync_fail},
+        now,
+    },
+    io_http::{
+        DTO, DataRequest, DataResponse, DataResponseResult, EmptyDTO, Text, Validator,
+        data_response_ok,
+    },
+    *,
+};
+use axum::http::StatusCode;
+use regex_lite::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::LazyLock;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct User {
+    /// A principal apart from the groups: the lists name it as a user, and
+    /// the token does not count it in the groups. It still comes from the
+    /// counter of the groups, because the older servers used it as a
+    /// personal group: a new group never takes the id of the resources
+    /// stored in such a group.
+    pub id: u32,
+    /// Unique name used to log in.
+    pub login: String,
+    /// Name displayed to the other users.
+    pub name: String,
+    /// Global level in this server.
+    pub level: UserLevel,
+    /// Level in each group, indexed by group id.
+    pub groups: BTreeMap<u32, UserLevel>,
+    /// Last change of the level or the groups, in seconds since Epoch.
+    /// The older tokens are replaced.
+    #[serde(default)]
+    pub rights_changed: u64,
+    /// The tokens created until this time, in seconds since Epoch, are
+    /// rejected. A login in the same second is dated after it.
+    #[serde(default)]
+    pub sessions_revoked: u64,
+}
+
+impl User {
+    /// Get the token of the user, with only the first groups if the user
+    /// is in too many groups.
+    pub fn token(&self) -> UserToken {
+        let mut token = UserToken {
+            level: self.level,
+            id: self.id,
+            ..Default::default()
+        };
+        for (slot, (&id, &level)) in token.groups.iter_mut().zip(self.groups.iter()) {
+            *slot = (level, id);
+        }
+        token
+    }
+}
+
+/// All the users indexed by id.
+pub type Users = BTreeMap<u32, User>;
+
+pub(crate) fn key(id: u32) -> String {
+    format!("user/{id}")
+}
+
+fn password_key(id: u32) -> String {
+    format!("password/{id}")
+}
+
+/// Load all the users from the storage.
+pub fn init(server: &State) -> Result<()> {
+    let mut users = server.users.write().map_err(err_sync_fail)?;
+    for name in server.storage.list("user")? {
+        if let Some(user) = server.storage.load::<User>(&format!("user/{name}"))? {
+            users.insert(user.id, user);
+        }
+    }
+    Ok(())
+}
+
+/// Create the user `admin` if the directory is empty.
+/// Return the password, generated if it is not given.
+pub async fn first_admin(server: &State, password: Option<String>) -> Result<Option<String>> {
+    if !server.users.read().map_err(err_sync_fail)?.is_empty() {
+        return Ok(None);
+    }
+    let password = match password {
+        Some(password) => password,
+        None => random_password()?,
+    };
+    let user = NewUser {
+        login: "admin".to_string(),
+        name: "Administrator".to_string(),
+        password: password.clone(),
+        level: UserLevel::SuperAdmin,
+    };
+    user.check()?;
+    add(server, user).await?;
+    Ok(Some(password))
+}
+
+/// Store a new user with its password.
+pub async fn add(server: &State, new: NewUser) -> Result<User> {
+    let hash = hash_password(new.password).await?;
+
+    let mut users = server.users.write().map_err(err_sync_fail)?;
+    if users.values().any(|user| user.login == new.login) {
+        return Err(WrapError::http(
+            StatusCode::CONFLICT,
+            "The login is already used",
+        ));
+    }
+    let user = User {
+        id: server.new_id("principal")? as u32,
+        login: new.login,
+        name: new.name,
+        level: new.level,
+        groups: BTreeMap::new(),
+        rights_changed: 0,
+        sessions_revoked: 0,
+    };
+    server.storage.save(&password_key(user.id), &hash)?;
+    server.storage.save(&key(user.id), &user)?;
+    users.insert(user.id, user.clone());
+    Ok(user)
+}
+
+/// Get a user by login, if the password match.
+pub async fn check_password(server: &State, login: &str, password: String) -> Result<User> {
+    let user = server
+        .users
+        .read()
+        .map_err(err_sync_fail)?
+        .values()
+        .find(|user| user.login == login)
+        .cloned();
+    let hash = match &user {
+        Some(user) => server.storage.load::<String>(&password_key(user.id))?,
+        None => None,
+    };
+    let checked = match (&user, &hash) {
+        (Some(user), Some(hash)) => Some((user.id, checked_digest(hash, &password))),
+        _ => None,
+    };
+    let now = now();
+    let recently_checked = match &checked {
+        Some((id, digest)) => server
+            .checked_passwords
+            .lock()
+            .map_err(err_sync_fail)?
+            .get(id)
+            .is_some_and(|(checked, expire)| checked == digest && now < *expire),
+        None => false,
+    };
+
+    // Always compute a hash, to not reveal if the login exist.
+    let valid = match hash {
+        _ if recently_checked => true,
+        Some(hash) => verify_password(password, hash).await?,
+        None => {
+            hash_password(password).await?;
+            false
+        }
+    };
+    if valid
+        && !recently_checked
+        && let Some((id, digest)) = checked
+    {
+        let mut checked_passwords = server.checked_passwords.lock().map_err(err_sync_fail)?;
+        checked_passwords.retain(|_, (_, expire)| now < *expire);
+        checked_passwords.insert(id, (digest, now + CHECKED_PASSWORD_TIME));
+    }
+    match (user, valid) {
+        (Some(user), true) => Ok(user),
+        _ => Err(WrapError::http(
+            StatusCode::UNAUTHORIZED,
+            "Invalid login or password",
+        )),
+    }
+}
+
+/// The passwords checked recently, to not compute scrypt on each request of
+/// the HTTP Basic clients, like WebDAV. Indexed by user id, the value is the
+/// digest of the password with its stored hash, and the expiration time.
+pub type CheckedPasswords = BTreeMap<u32, (String, u64)>;
+
+/// How long a checked password is kept, in seconds.
+const CHECKED_PASSWORD_TIME: u64 = 60;
+
+/// A digest that changes with the stored hash, so with a new password.
+fn checked_digest(hash: &str, password: &str) -> String {
+    use crypto::digest::Digest;
+    let mut hasher = crypto::sha2::Sha256::new();
+    hasher.input_str(hash);
+    hasher.input_str(password);
+    hasher.result_str()
+}
+
+/// Replace the password of a user.
+pub async fn set_password(server: &State, id: u32, password: String) -> Result<()> {
+    let hash = hash_password(password).await?;
+    let users = server.users.read().map_err(err_sync_fail)?;
+    if !users.contains_key(&id) {
+        return Err(err_not_found("user"));
+    }
+    server.storage.save(&password_key(id), &hash)
+}
+
+/// Reject all the tokens of a user created until now.
+pub fn revoke(server: &State, id: u32) -> Result<()> {
+    let mut users = server.users.write().map_err(err_sync_fail)?;
+    let user = users.get_mut(&id).ok_or_else(|| err_not_found("user"))?;
+    user.sessions_revoked = now();
+    server.storage.save(&key(id), user)
+}
+
+/* PASSWORD */
+
+#[cfg(not(test))]
+const SCRYPT_LOG_N: u8 = 15;
+/// Weak parameter, to run the tests fast.
+#[cfg(test)]
+const SCRYPT_LOG_N: u8 = 4;
+
+const PASSWORD_MIN_LEN: usize = 8;
+
+fn err_hash(_: impl std::error::Error) -> WrapError {
+    WrapError::http(StatusCode::INTERNAL_SERVER_ERROR, "Password hash fail")
+}
+
+/// Hash the password with scrypt, in a blocking thread.
+async fn hash_password(password: String) -> Result<String> {
+    tokio::task::spawn_blocking(move || {
+        let params = crypto::scrypt::ScryptParams::new(SCRYPT_LOG_N, 8, 1);
+        crypto::scrypt::scrypt_simple(&password, &params).map_err(err_hash)
+    })
+    .await
+    .map_err(err_hash)?
+}
+
+async fn verify_password(password: String, hash: String) -> Result<bool> {
+    tokio::task::spawn_blocking(move || {
+        crypto::scrypt::scrypt_check(&password, &hash).map_err(|_| {
+            WrapError::http(StatusCode::INTERNAL_SERVER_ERROR, "Invalid password hash")
+        })
+    })
+    .await
+    .map_err(err_hash)?
+}
+
+pub fn random_password() -> Result<String> {
+    use base64::Engine;
+    let mut bytes = [0u8; 12];
+    getrandom::fill(&mut bytes).map_err(|_| {
+        WrapError::http(StatusCode::INTERNAL_SERVER_ERROR, "Random generation fail")
+    })?;
+    Ok(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes))
+}
+
+/// A login is lowercase letters, digits, `_`, `.` or `-`.
+static LOGIN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new("^[a-z0-9_.-]{1,64}$").expect("valid regex"));
+
+const INVALID_LOGIN: &str = "The login need only lowercase letters, digits, '_', '.' or '-'";
+
+/// The rules of a new password.
+pub fn new_password(password: Text<'_>) -> Text<'_> {
+    password
+        .code(
+            "password_too_short",
+            "The password is too short, need 8 characters",
+        )
+        .min_chars(PASSWORD_MIN_LEN)
+}
+
+/* HANDLERS */
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct Login {
+    pub login: String,
+    pub password: String,
+}
+
+impl DTO for Login {
+    fn check(&self) -> Result<()> {
+        Validator::new()
+            .text("login", &self.login, |login| login.required())
+            .text("password", &self.password, |password| password.required())
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct NewUser {
+    pub login: String,
+    pub name: String,
+    pub password: String,
+    #[serde(default)]
+    pub level: UserLevel,
+}
+
+impl DTO for NewUser {
+    fn check(&self) -> Result<()> {
+        Validator::new()
+            .text("login", &self.login, |login| {
+                login
+                    .required()
+                    .code("invalid_login", INVALID_LOGIN)
+                    .pattern(&LOGIN, INVALID_LOGIN)
+            })
+            .text("name", &self.name, |name| name.required())
+            .text("password", &self.password, new_password)
+            .finish()
+    }
+
+    /// An administrator can create users until its level, but not super
+    /// administrators.
+    fn check_user(&self, user: &UserToken) -> Result<()> {
+        match user.level >= UserLevel::Admin
+            && self.level <= user.level
+            && self.level <= UserLevel::Admin
+        {
+            true => Ok(()),
+            false => Err(err_forbidden()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct PasswordChange {
+    pub old: String,
+    pub new: String,
+}
+
+impl DTO for PasswordChange {
+    fn check(&self) -> Result<()> {
+        Validator::new()
+            .text("new", &self.new, new_password)
+            .finish()
+    }
+    fn check_user(&self, user: &UserToken) -> Result<()> {
+        match user.id {
+            0 => Err(err_forbidden()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct UserId {
+    pub user: u32,
+}
+
+impl DTO for UserId {
+    fn check_user(&self, user: &UserToken) -> Result<()> {
+        match user.level >= UserLevel::Admin {
+            true => Ok(()),
+            false => Err(err_forbidden()),
+        }
+    }
+}
+
+/// Check the password and send the user token in a cookie.
+pub async fn login(server: &State, request: DataRequest<Login>) -> DataResponseResult<User> {
+    let Login { login, password } = request.dto;
+    let user = check_password(server, &login, password).await?;
+    Ok(DataResponse {
+        user: Some(user.token()),
+        dto: user,
+    })
+}
+
+/// Remove the user token cookie.
+pub async fn logout(_: &State, _: DataRequest<EmptyDTO>) -> DataResponseResult<()> {
+    Ok(DataResponse {
+        user: Some(UserToken::default()),
+        dto: (),
+    })
+}
+
+/// Revoke all the tokens of the current user, on all its devices.
+pub async fn logout_all(server: &State, request: DataRequest<EmptyDTO>) -> DataResponseResult<()> {
+    if request.user.id == 0 {
+        return Err(err_forbidden());
+    }
+    revoke(server, request.user.id)?;
+    Ok(DataResponse {
+        user: Some(UserToken::default()),
+        dto: (),
+    })
+}
+
+/// Change the password of the current user, and revoke its other tokens.
+/// The user gets a new token.
+pub async fn password(
+    server: &State,
+    request: DataRequest<PasswordChange>,
+) -> DataResponseResult<()> {
+    let DataRequest { user, dto } = request;
+    let login = server
+        .users
+        .read()
+        .map_err(err_sync_fail)?
+        .get(&user.id)
+        .map(|user| user.login.clone())
+        .ok_or_else(err_forbidden)?;
+    check_password(server, &login, dto.old).await?;
+    set_password(server, user.id, dto.new).await?;
+    revoke(server, user.id)?;
+    let token = server.users.read().map_err(err_sync_fail)?[&user.id].token();
+    Ok(DataResponse {
+        user: Some(token),
+        dto: (),
+    })
+}
+
+/// Create a new user.
+pub async fn create(server: &State, request: DataRequest<NewUser>) -> DataResponseResult<User> {
+    data_response_ok(add(server, request.dto).await?)
+}
+
+/// Revoke all the tokens of a user, an administrator can not revoke a user
+/// with a higher level.
+pub async fn revoke_sessions(
+    server: &State,
+    request: DataRequest<UserId>,
+) -> DataResponseResult<()> {
+    let DataRequest { user, dto } = request;
+    let level = server
+        .users
+        .read()
+        .map_err(err_sync_fail)?
+        .get(&dto.user)
+        .map(|user| user.level)
+        .ok_or_else(|| err_not_found("user"))?;
+    if user.level < level {
+        return Err(err_forbidden());
+    }
+    revoke(server, dto.user)?;
+    Ok(DataResponse {
+        user: (user.id == dto.user).then(UserToken::default),
+        dto: (),
+    })
+}
+
+#[tokio::test]
+async fn login_password() {
+    let server = crate::app_driver::test_state("login_password");
+    let password = first_admin(&server, None).await.unwrap().unwrap();
+    assert_eq!(None, first_admin(&server, None).await.unwrap());
+
+    let request = |login: &str, password: &str| DataRequest {
+        user: UserToken::default(),
+        dto: Login {
+            login: login.to_string(),
+            password: password.to_string(),
+        },
+    };
+    let response = login(&server, request("admin", &password)).await.unwrap();
+    let token = response.user.unwrap();
+    assert_eq!(UserLevel::SuperAdmin, token.level);
+    assert_eq!(response.dto.id, token.id);
+    assert!(
+        login(&server, request("admin", "wrong password"))
+            .await
+            .is_err()
+    );
+    assert!(login(&server, request("nobody", &password)).await.is_err());
+
+    // The checked password is kept, until the password changes.
+    assert!(
+        server
+            .checked_passwords
+            .lock()
+            .unwrap()
+            .contains_key(&token.id)
+    );
+    login(&server, request("admin", &password)).await.unwrap();
+    set_password(&server, token.id, "new password".to_string())
+        .await
+        .unwrap();
+    assert!(login(&server, request("admin", &password)).await.is_err());
+    login(&server, request("admin", "new password"))
+        .await
+        .unwrap();
+
+    let new_user = |level| NewUser {
+        login: "bob".to_string(),
+        name: "Bob".to_string(),
+        password: "12345678".to_string(),
+        level,
+    };
+    assert!(new_user(UserLevel::SuperAdmin).check_user(&token).is_err());
+    assert!(
+        new_user(UserLevel::EditData)
+            .check_user(&UserToken::default())
+            .is_err()
+    );
+    let mut bob = add(&server, new_user(UserLevel::EditData)).await.unwrap();
+    assert!(add(&server, new_user(UserLevel::EditData)).await.is_err());
+
+    bob.groups = BTreeMap::from([(42, UserLevel::Admin), (7, UserLevel::SeeData)]);
+    let token = bob.token();
+    assert!(token.allow(42, UserLevel::Admin));
+    assert!(token.allow(7, UserLevel::SeeData));
+    assert!(!token.allow(7, UserLevel::EditData));
+}
+
+#[tokio::test]
+async fn session_revocation() {
+    use crate::HTTPState;
+    let server = crate::app_driver::test_state("session_revocation");
+    first_admin(&server, None).await.unwrap();
+    let bob = NewUser {
+        login: "bob".to_string(),
+        name: "Bob".to_string(),
+        password: "12345678".to_string(),
+        level: UserLevel::EditData,
+    };
+    let bob = add(&server, bob).await.unwrap();
+    let admin = server.users.read().unwrap()[&1].token();
+    assert_eq!(0, server.sessions_revoked(bob.id));
+
+    assert!(UserId { user: admin.id }.check_user(&bob.token()).is_err());
+    let request = DataRequest {
+        user: admin.clone(),
+        dto: UserId { user: bob.id },
+    };
+    let response = revoke_sessions(&server, request).await;
+    assert_eq!(None, response.unwrap().user);
+    assert!(now() - 1 <= server.sessions_revoked(bob.id));
+
+    let response = logout_all(
+        &server,
+        DataRequest {
+            user: admin.clone(),
+            dto: EmptyDTO(),
+        },
+    )
+    .await;
+    assert_eq!(Some(UserToken::default()), response.unwrap().user);
+    assert!(0 < server.sessions_revoked(admin.id));
+    let stored = server.storage.load::<User>(&key(admin.id)).unwrap();
+    assert_eq!(
+        server.sessions_revoked(admin.id),
+        stored.unwrap().sessions_revoked
+    );
+
+    // A new password ends the other sessions, the user keeps a new token.
+    server
+        .users
+        .write()
+        .unwrap()
+        .get_mut(&bob.id)
+        .unwrap()
+        .sessions_revoked = 0;
+    let change = PasswordChange {
+        old: "12345678".to_string(),
+        new: "a new password".to_string(),
+    };
+    let request = DataRequest {
+        user: bob.token(),
+        dto: change,
+    };
+    let response = password(&server, request).await.unwrap();
+    assert_eq!(Some(bob.token()), response.user);
+    assert!(now() - 1 <= server.sessions_revoked(bob.id));
+}
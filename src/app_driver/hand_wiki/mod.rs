@@ -0,0 +1,573 @@
+//! The wiki: Markdown pages at `/wiki/<slug>`, rendered in the generated
+//! pages with an index at `/wiki/`.
+//!
+//! A page is owned by a group, its members read it and its editors can
+//! change it. The last changes are kept as revisions. The link `[[slug]]` or
+//! `[[slug|text]]` points to another page.
+
+use crate::{
+    app_driver::{
+        State,
+        error::{err_forbidden, err_not_found, err_sync_fail},
+        hand_acl::{self, Acl},
+        now,
+    },
+    io_http::{
+        CachedPage, DTO, DataRequest, DataResponseResult, EmptyDTO, Text, Validator,
+        data_response_ok,
+    },
+    *,
+};
+use regex_lite::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::LazyLock;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct WikiPage {
+    /// Name of the page in its path `/wiki/<slug>`.
+    pub slug: String,
+    /// The group of the editors.
+    pub group: u32,
+    pub title: String,
+    /// Content in Markdown.
+    pub body: String,
+    /// Number of the revision, the first is `1`.
+    pub version: u64,
+    /// The user of the last change.
+    pub author: u32,
+    /// Time of the last change, in seconds since Epoch.
+    pub modified: u64,
+    /// The other users and groups allowed on the page, kept by the
+    /// revisions.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub acl: Acl,
+}
+
+impl WikiPage {
+    /// Check the user has the level on the page, by its group or its list.
+    pub fn allow(&self, user: &UserToken, level: UserLevel) -> bool {
+        hand_acl::allow(Some(self.group), &[&self.acl], user, level)
+    }
+}
+
+/// All the pages indexed by slug.
+pub type Wiki = std::collections::BTreeMap<String, WikiPage>;
+
+/// A page in the list of the pages.
+#[derive(Debug, Clone, Default, Serialize, PartialEq, JsonSchema)]
+pub struct WikiEntry {
+    pub slug: String,
+    pub group: u32,
+    pub title: String,
+    pub modified: u64,
+}
+
+/// Number of the last revisions kept for a page.
+const MAX_REVISIONS: u64 = 100;
+
+/// Path of the index page.
+pub const INDEX: &str = "/wiki/";
+
+fn key(slug: &str) -> String {
+    format!("wiki/{slug}")
+}
+
+/// The revisions of a page, one key by revision.
+fn revisions_prefix(slug: &str) -> String {
+    format!("wiki_version/{slug}")
+}
+
+fn revision_key(slug: &str, version: u64) -> String {
+    format!("wiki_version/{slug}/{version}")
+}
+
+/// All the revisions of the page in one value, before the keys by revision.
+fn legacy_revisions_key(slug: &str) -> String {
+    format!("wiki_revision/{slug}")
+}
+
+fn path(slug: &str) -> String {
+    format!("{INDEX}{slug}")
+}
+
+/// Load and render all the pages.
+pub fn init(server: &State) -> Result<()> {
+    let mut wiki = server.wiki.write().map_err(err_sync_fail)?;
+    for name in server.storage.list("wiki")? {
+        if let Some(page) = server.storage.load::<WikiPage>(&key(&name))? {
+            split_revisions(server, &page.slug)?;
+            render(server, &page)?;
+            wiki.insert(page.slug.clone(), page);
+        }
+    }
+    Ok(())
+}
+
+/// Move the revisions stored in one value to their own keys, only the last
+/// ones are kept.
+fn split_revisions(server: &State, slug: &str) -> Result<()> {
+    let legacy = legacy_revisions_key(slug);
+    let Some(revisions) = server.storage.load::<Vec<WikiPage>>(&legacy)? else {
+        return Ok(());
+    };
+    let last = revisions.last().map_or(0, |page| page.version);
+    for page in revisions {
+        if page.version + MAX_REVISIONS > last {
+            server
+                .storage
+                .save(&revision_key(slug, page.version), &page)?;
+        }
+    }
+    server.storage.remove(&legacy)
+}
+
+/// A slug is lowercase letters, digits, `-` or `_`.
+static SLUG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new("^[a-z0-9_-]{1,64}$").expect("valid regex"));
+
+/// The rules of a slug.
+fn slug(slug: Text<'_>) -> Text<'_> {
+    const INVALID: &str = "The slug need only lowercase letters, digits, '-' or '_'";
+    slug.code("invalid_slug", INVALID)
+        .required()
+        .pattern(&SLUG, INVALID)
+}
+
+/// Replace the links `[[slug]]` and `[[slug|text]]` by Markdown links.
+fn wiki_links(body: &str) -> String {
+    let mut output = String::with_capacity(body.len());
+    let mut rest = body;
+    while let Some(begin) = rest.find("[[") {
+        let Some(len) = rest[begin + 2..].find("]]") else {
+            break;
+        };
+        let link = &rest[begin + 2..begin + 2 + len];
+        let (slug, text) = link.split_once('|').unwrap_or((link, link));
+        output.push_str(&rest[..begin]);
+        match SLUG.is_match(slug.trim()) {
+            true => {
+                output.push('[');
+                output.push_str(text.trim());
+                output.push_str("](");
+                output.push_str(&path(slug.trim()));
+                output.push(')');
+            }
+            false => output.push_str(&rest[begin..begin + 4 + len]),
+        }
+        rest = &rest[begin + 4 + len..];
+    }
+    output.push_str(rest);
+    output
+}
+
+fn render(server: &State, page: &WikiPage) -> Result<()> {
+    let content = server.theme.render(
+        "wiki_page.html",
+        minijinja::context! {
+            title => page.title,
+            description => "Wiki page",
+            slug => page.slug,
+            body => theme::safe(markdown::to_html(&wiki_links(&page.body))),
+            version => page.version,
+            modified => page.modified,
+        },
+    )?;
+    let cached = CachedPage::new(bmime::HTML, content);
+    let mut pages = server.pages.write().map_err(err_sync_fail)?;
+    pages.insert(path(&page.slug), cached);
+    Ok(())
+}
+
+/// The HTML document at the path under `/wiki`, if the user can read it.
+/// The index lists only the pages of the user, it is rendered for each
+/// request.
+pub fn cached(server: &State, path: &str, user: &UserToken) -> Result<Option<CachedPage>> {
+    let wiki = server.wiki.read().map_err(err_sync_fail)?;
+    match path.strip_prefix(INDEX) {
+        None | Some("") => render_index(server, &wiki, user).map(Some),
+        Some(slug) => match wiki.get(slug) {
+            Some(page) if page.allow(user, UserLevel::SeeData) => {
+                let pages = server.pages.read().map_err(err_sync_fail)?;
+                Ok(pages.get(path).cloned())
+            }
+            _ => Ok(None),
+        },
+    }
+}
+
+/// The index lists the pages of the user sorted by title.
+fn render_index(server: &State, wiki: &Wiki, user: &UserToken) -> Result<CachedPage> {
+    let mut sorted: Vec<&WikiPage> = wiki
+        .values()
+        .filter(|page| page.allow(user, UserLevel::SeeData))
+        .collect();
+    sorted.sort_by(|a, b| a.title.cmp(&b.title));
+    let entries: Vec<_> = sorted
+        .into_iter()
+        .map(|page| {
+            minijinja::context! {
+                path => path(&page.slug),
+                slug => page.slug,
+                title => page.title,
+                modified => page.modified,
+            }
+        })
+        .collect();
+    let content = server.theme.render(
+        "wiki_index.html",
+        minijinja::context! {
+            title => "Wiki",
+            description => "All the wiki pages",
+            pages => entries,
+        },
+    )?;
+    Ok(CachedPage::new(bmime::HTML, content))
+}
+
+/* HANDLERS */
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct WikiSlug {
+    pub slug: String,
+}
+
+impl DTO for WikiSlug {
+    fn check(&self) -> Result<()> {
+        Validator::new().text("slug", &self.slug, slug).finish()
+    }
+}
+
+/// Create or change a page. The page stays in its group, or moves to the
+/// new group if the user edits both groups: an editor given by the list of
+/// the page keeps the group.
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct WikiSet {
+    pub slug: String,
+    pub group: u32,
+    pub title: String,
+    pub body: String,
+}
+
+impl DTO for WikiSet {
+    fn check(&self) -> Result<()> {
+        Validator::new()
+            .text("slug", &self.slug, slug)
+            .text("title", &self.title, |title| title.required())
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct WikiVersion {
+    pub slug: String,
+    pub version: u64,
+}
+
+impl DTO for WikiVersion {
+    fn check(&self) -> Result<()> {
+        Validator::new().text("slug", &self.slug, slug).finish()
+    }
+}
+
+pub async fn get(server: &State, request: DataRequest<WikiSlug>) -> DataResponseResult<WikiPage> {
+    let wiki = server.wiki.read().map_err(err_sync_fail)?;
+    let page = get_allowed(&wiki, &request.user, &request.dto.slug)?;
+    data_response_ok(page.clone())
+}
+
+/// Get a page the user can read.
+fn get_allowed<'a>(wiki: &'a Wiki, user: &UserToken, slug: &str) -> Result<&'a WikiPage> {
+    match wiki.get(slug) {
+        Some(page) if page.allow(user, UserLevel::SeeData) => Ok(page),
+        Some(_) => Err(err_forbidden()),
+        None => Err(err_not_found("wiki page")),
+    }
+}
+
+/// List the pages the user can read.
+pub async fn list(
+    server: &State,
+    request: DataRequest<EmptyDTO>,
+) -> DataResponseResult<Vec<WikiEntry>> {
+    let wiki = server.wiki.read().map_err(err_sync_fail)?;
+    let entries = wiki
+        .values()
+        .filter(|page| page.allow(&request.user, UserLevel::SeeData))
+        .map(|page| WikiEntry {
+            slug: page.slug.clone(),
+            group: page.group,
+            title: page.title.clone(),
+            modified: page.modified,
+        })
+        .collect();
+    data_response_ok(entries)
+}
+
+pub async fn set(server: &State, request: DataRequest<WikiSet>) -> DataResponseResult<WikiPage> {
+    let DataRequest { user, dto } = request;
+    let mut wiki = server.wiki.write().map_err(err_sync_fail)?;
+    let (version, acl) = match wiki.get(&dto.slug) {
+        Some(old) if !old.allow(&user, UserLevel::EditData) => return Err(err_forbidden()),
+        Some(old)
+            if old.group != dto.group
+                && !(user.allow(old.group, UserLevel::EditData)
+                    && user.allow(dto.group, UserLevel::EditData)) =>
+        {
+            return Err(err_forbidden());
+        }
+        Some(old) => (old.version + 1, old.acl.clone()),
+        None if !user.allow(dto.group, UserLevel::EditData) => return Err(err_forbidden()),
+        None => (1, Acl::new()),
+    };
+    let page = WikiPage {
+        slug: dto.slug,
+        group: dto.group,
+        title: dto.title,
+        body: dto.body,
+        version,
+        author: user.id,
+        modified: now(),
+        acl,
+    };
+    save(server, &mut wiki, page)
+}
+
+/// Store and render a new revision of the page, and remove the revision
+/// out of the [`MAX_REVISIONS`] last ones.
+fn save(server: &State, wiki: &mut Wiki, page: WikiPage) -> DataResponseResult<WikiPage> {
+    server
+        .storage
+        .save(&revision_key(&page.slug, page.version), &page)?;
+    if page.version > MAX_REVISIONS {
+        let old = page.version - MAX_REVISIONS;
+        server.storage.remove(&revision_key(&page.slug, old))?;
+    }
+    server.storage.save(&key(&page.slug), &page)?;
+    render(server, &page)?;
+    wiki.insert(page.slug.clone(), page.clone());
+    data_response_ok(page)
+}
+
+/// The versions of the kept revisions of a page, the oldest first.
+fn versions(server: &State, slug: &str) -> Result<Vec<u64>> {
+    let mut versions: Vec<u64> = server
+        .storage
+        .list(&revisions_prefix(slug))?
+        .iter()
+        .filter_map(|name| name.parse().ok())
+        .collect();
+    versions.sort();
+    Ok(versions)
+}
+
+/// Replace the list of a page, the user need to administrate it.
+pub fn set_acl(server: &State, user: &UserToken, slug: &str, acl: Acl) -> Result<hand_acl::Lists> {
+    let mut wiki = server.wiki.write().map_err(err_sync_fail)?;
+    let page = wiki
+        .get_mut(slug)
+        .ok_or_else(|| err_not_found("wiki page"))?;
+    hand_acl::check_set(Some(page.group), &[&page.acl], &[&acl], user)?;
+    let mut updated = page.clone();
+    updated.acl = acl;
+    server.storage.save(&key(slug), &updated)?;
+    *page = updated;
+    Ok(hand_acl::Lists {
+        group: Some(page.group),
+        lists: vec![page.acl.clone()],
+        admins: false,
+    })
+}
+
+/// Remove a page and its revisions.
+pub async fn delete(server: &State, request: DataRequest<WikiSlug>) -> DataResponseResult<()> {
+    let DataRequest { user, dto } = request;
+    let mut wiki = server.wiki.write().map_err(err_sync_fail)?;
+    let page = wiki
+        .get(&dto.slug)
+        .ok_or_else(|| err_not_found("wiki page"))?;
+    if !page.allow(&user, UserLevel::EditData) {
+        return Err(err_forbidden());
+    }
+    server.storage.remove(&key(&dto.slug))?;
+    for version in versions(server, &dto.slug)? {
+        server.storage.remove(&revision_key(&dto.slug, version))?;
+    }
+    server
+        .pages
+        .write()
+        .map_err(err_sync_fail)?
+        .remove(&path(&dto.slug));
+    wiki.remove(&dto.slug);
+    data_response_ok(())
+}
+
+/// The kept revisions of a page, the oldest first.
+pub async fn revisions(
+    server: &State,
+    request: DataRequest<WikiSlug>,
+) -> DataResponseResult<Vec<WikiPage>> {
+    let DataRequest { user, dto } = request;
+    let wiki = server.wiki.read().map_err(err_sync_fail)?;
+    get_allowed(&wiki, &user, &dto.slug)?;
+    let mut revisions = Vec::new();
+    for version in versions(server, &dto.slug)? {
+        let key = revision_key(&dto.slug, version);
+        revisions.extend(server.storage.load::<WikiPage>(&key)?);
+    }
+    data_response_ok(revisions)
+}
+
+/// Copy an old revision as a new revision.
+pub async fn restore(
+    server: &State,
+    request: DataRequest<WikiVersion>,
+) -> DataResponseResult<WikiPage> {
+    let DataRequest { user, dto } = request;
+    let mut wiki = server.wiki.write().map_err(err_sync_fail)?;
+    let current = wiki
+        .get(&dto.slug)
+        .ok_or_else(|| err_not_found("wiki page"))?;
+    if !current.allow(&user, UserLevel::EditData) {
+        return Err(err_forbidden());
+    }
+    let version = current.version + 1;
+    let old = server
+        .storage
+        .load::<WikiPage>(&revision_key(&dto.slug, dto.version))?
+        .ok_or_else(|| err_not_found("revision"))?;
+    let page = WikiPage {
+        group: current.group,
+        acl: current.acl.clone(),
+        version,
+        author: user.id,
+        modified: now(),
+        ..old
+    };
+    save(server, &mut wiki, page)
+}
+
+#[tokio::test]
+async fn wiki_pages() {
+    use crate::HTTPState;
+    let server = crate::app_driver::test_state("wiki_pages");
+    fn request<T: DTO>(dto: T) -> DataRequest<T> {
+        DataRequest {
+            user: UserToken::DEV_EDITOR,
+            dto,
+        }
+    }
+    let page = |group, body: &str| WikiSet {
+        slug: "start".to_string(),
+        group,
+        title: "Start <here>".to_string(),
+        body: body.to_string(),
+    };
+    let html = |path: &str| {
+        let page = server
+            .cached(path, &UserToken::DEV_EDITOR)
+            .unwrap()
+            .unwrap();
+        String::from_utf8(page.body.to_vec()).unwrap()
+    };
+
+    assert!(
+        WikiSlug {
+            slug: "Bad/Slug".to_string()
+        }
+        .check()
+        .is_err()
+    );
+    assert!(set(&server, request(page(7, ""))).await.is_err());
+    set(&server, request(page(42, "See [[other|the other page]]")))
+        .await
+        .unwrap();
+    assert!(html("/wiki/start").contains("<a href=\"/wiki/other\">the other page</a>"));
+    assert!(html("/wiki").contains("<a href=\"/wiki/start\">Start &lt;here&gt;</a>"));
+
+    let changed = set(&server, request(page(42, "Changed"))).await.unwrap();
+    assert_eq!(2, changed.dto.version);
+    let bob = UserToken {
+        id: 7,
+        ..Default::default()
+    };
+    let forbidden = || DataRequest {
+        user: bob.clone(),
+        dto: WikiSlug {
+            slug: "start".to_string(),
+        },
+    };
+    assert!(delete(&server, forbidden()).await.is_err());
+    // Only the members of the group read the page.
+    assert!(get(&server, forbidden()).await.is_err());
+    assert!(revisions(&server, forbidden()).await.is_err());
+    let listed = DataRequest {
+        user: bob.clone(),
+        dto: EmptyDTO(),
+    };
+    assert!(list(&server, listed).await.unwrap().dto.is_empty());
+    assert!(server.cached("/wiki/start", &bob).unwrap().is_none());
+    let acl_target = || DataRequest {
+        user: bob.clone(),
+        dto: hand_acl::AclTarget {
+            resource: hand_acl::Resource::Wiki {
+                slug: "start".to_string(),
+            },
+        },
+    };
+    let err = hand_acl::get(&server, acl_target()).await.unwrap_err();
+    assert_eq!(Some(axum::http::StatusCode::FORBIDDEN), err.status_http);
+    let index = server.cached("/wiki", &bob).unwrap().unwrap();
+    assert!(
+        !String::from_utf8(index.body.to_vec())
+            .unwrap()
+            .contains("start")
+    );
+
+    // An editor given by the list changes the page, but not its group, a
+    // reader given by the list reads it.
+    let entry = |user, level| hand_acl::AclEntry {
+        principal: hand_acl::Principal::User(user),
+        level,
+        deny: false,
+    };
+    let acl = vec![entry(7, UserLevel::EditData), entry(9, UserLevel::SeeData)];
+    set_acl(&server, &UserToken::DEV_EDITOR, "start", acl).unwrap();
+    let reader = UserToken {
+        id: 9,
+        ..Default::default()
+    };
+    assert!(server.cached("/wiki/start", &reader).unwrap().is_some());
+    let by = |user: &UserToken, group| DataRequest {
+        user: user.clone(),
+        dto: page(group, "Changed by Bob"),
+    };
+    assert!(set(&server, by(&reader, 42)).await.is_err());
+    let by_bob = |group| by(&bob, group);
+    assert!(set(&server, by_bob(7)).await.is_err());
+    let changed = set(&server, by_bob(42)).await.unwrap();
+    assert_eq!((3, 42), (changed.dto.version, changed.dto.group));
+    assert!(get(&server, forbidden()).await.is_ok());
+    assert!(hand_acl::get(&server, acl_target()).await.is_ok());
+
+    let restore_first = WikiVersion {
+        slug: "start".to_string(),
+        version: 1,
+    };
+    let restored = restore(&server, request(restore_first)).await.unwrap();
+    assert_eq!((4, 42), (restored.dto.version, restored.dto.group));
+    assert!(html("/wiki/start").contains("the other page"));
+    let slug = || WikiSlug {
+        slug: "start".to_string(),
+    };
+    assert_eq!(
+        4,
+        revisions(&server, request(slug())).await.unwrap().dto.len()
+    );
+
+    delete(&server, request(slug())).await.unwrap();
+    let editor = UserToken::DEV_EDITOR;
+    assert!(server.cached("/wiki/start", &editor).unwrap().is_none());
+    assert!(!html("/wiki/").contains("start"));
+    assert!(get(&server, request(slug())).await.is_err());
+}
@@ -0,0 +1,341 @@
+//! Operations of the command line, run on the data directory while the
+//! server is stopped.
+
+use crate::{
+    app_driver::{
+        State,
+        error::{err_not_found, err_storage, err_sync_fail},
+        hand_group, hand_user, now,
+    },
+    io_http::{ByteStream, DTO, DataRequest, Validator, encode_user_token, issue_time},
+    *,
+};
+use axum::{body::Bytes, http::StatusCode};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+/// Create a user, with a generated password if none is given.
+/// Return the password.
+pub async fn user_add(
+    server: &State,
+    login: String,
+    name: String,
+    level: UserLevel,
+    password: Option<String>,
+) -> Result<String> {
+    let password = match password {
+        Some(password) => password,
+        None => hand_user::random_password()?,
+    };
+    let user = hand_user::NewUser {
+        login,
+        name,
+        password: password.clone(),
+        level,
+    };
+    user.check()?;
+    hand_user::add(server, user).await?;
+    Ok(password)
+}
+
+/// Replace the password of a user, and revoke its tokens.
+/// Return the password, generated if none is given.
+pub async fn reset_password(
+    server: &State,
+    login: &str,
+    password: Option<String>,
+) -> Result<String> {
+    let id = user_id(server, login)?;
+    let password = match password {
+        Some(password) => password,
+        None => hand_user::random_password()?,
+    };
+    Validator::new()
+        .text("password", &password, hand_user::new_password)
+        .finish()?;
+    hand_user::set_password(server, id, password.clone()).await?;
+    hand_user::revoke(server, id)?;
+    Ok(password)
+}
+
+/// Create a group with the user `owner` as super administrator.
+/// Return the group id.
+pub async fn group_add(server: &State, name: String, owner: &str) -> Result<u32> {
+    let id = user_id(server, owner)?;
+    let user = server.users.read().map_err(err_sync_fail)?[&id].token();
+    let dto = hand_group::GroupCreate { name };
+    dto.check()?;
+    let response = hand_group::create(server, DataRequest { user, dto }).await?;
+    Ok(response.dto.id)
+}
+
+/// Sign a token of the user with the current key, valid even after a
+/// revocation of the same second.
+pub fn token_issue(server: &State, id: u32) -> Result<String> {
+    let (user, revoked) = server
+        .users
+        .read()
+        .map_err(err_sync_fail)?
+        .get(&id)
+        .map(|user| (user.token(), user.sessions_revoked))
+        .ok_or_else(|| err_not_found("user"))?;
+    let created = issue_time(now(), revoked);
+    Ok(encode_user_token(
+        &user,
+        &server.token_keys.current,
+        created,
+    ))
+}
+
+fn user_id(server: &State, login: &str) -> Result<u32> {
+    server
+        .users
+        .read()
+        .map_err(err_sync_fail)?
+        .values()
+        .find(|user| user.login == login)
+        .map(|user| user.id)
+        .ok_or_else(|| err_not_found("user"))
+}
+
+/* EXPORT */
+
+/// Version of the export format. The version 1 has the blobs in one line.
+const EXPORT_VERSION: u32 = 2;
+
+/// Maximal size of the blob data in a line, before the base64.
+const BLOB_CHUNK: usize = 1 << 20;
+
+/// A line of an export file, in JSON.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum ExportLine {
+    /// The first line.
+    Header { brume_export: u32 },
+    /// A storage value.
+    Value {
+        key: String,
+        value: serde_json::Value,
+    },
+    /// A part of a file content, in base64. The parts of a blob follow each
+    /// other.
+    Blob { blob: String, data: String },
+}
+
+/// Number of exported or imported items.
+#[derive(Debug, Clone, Copy, Default, Serialize, PartialEq)]
+pub struct Exported {
+    pub values: u64,
+    pub blobs: u64,
+}
+
+fn err_export(err: impl std::error::Error + Send + Sync + 'static) -> WrapError {
+    WrapError::http(StatusCode::BAD_REQUEST, "Invalid export file").add_err(err)
+}
+
+/// Write all the storage values and the blobs in a file, one JSON by line.
+pub async fn export(server: &State, path: &Path) -> Result<Exported> {
+    let file = std::fs::File::create(path).map_err(err_storage)?;
+    let mut output = std::io::BufWriter::new(file);
+    let mut exported = Exported::default();
+    let mut write = |line: &ExportLine| {
+        serde_json::to_writer(&mut output, line).map_err(err_storage)?;
+        output.write_all(b"\n").map_err(err_storage)
+    };
+
+    write(&ExportLine::Header {
+        brume_export: EXPORT_VERSION,
+    })?;
+    for key in server.storage.keys()? {
+        let Some(value) = server.storage.get(&key)? else {
+            continue;
+        };
+        let value = serde_json::from_slice(&value).map_err(err_storage)?;
+        write(&ExportLine::Value { key, value })?;
+        exported.values += 1;
+    }
+    let mut buffer = vec![0u8; BLOB_CHUNK];
+    for hash in server.blobs.list()? {
+        let mut file = server.blobs.open_file(&hash).await?;
+        // At least one line, for an empty blob.
+        let mut first = true;
+        loop {
+            let len = read_chunk(&mut file, &mut buffer).await?;
+            if len == 0 && !first {
+                break;
+            }
+            let data = base64::engine::general_purpose::STANDARD.encode(&buffer[..len]);
+            write(&ExportLine::Blob {
+                blob: hash.clone(),
+                data,
+            })?;
+            first = false;
+        }
+        exported.blobs += 1;
+    }
+
+    let file = output
+        .into_inner()
+        .map_err(|err| err_storage(err.into_error()))?;
+    file.sync_all().map_err(err_storage)?;
+    Ok(exported)
+}
+
+/// Fill the buffer from the file, return the length read, less than the
+/// buffer at the end of the file.
+async fn read_chunk(file: &mut tokio::fs::File, buffer: &mut [u8]) -> Result<usize> {
+    use tokio::io::AsyncReadExt;
+    let mut len = 0;
+    while len < buffer.len() {
+        match file.read(&mut buffer[len..]).await.map_err(err_storage)? {
+            0 => break,
+            read => len += read,
+        }
+    }
+    Ok(len)
+}
+
+/// The parts of a blob in the next lines of an export file.
+struct BlobParts<'a, I: Iterator<Item = Result<ExportLine>>> {
+    hash: String,
+    lines: &'a mut std::iter::Peekable<I>,
+}
+
+#[async_trait::async_trait]
+impl<I: Iterator<Item = Result<ExportLine>> + Send> ByteStream for BlobParts<'_, I> {
+    async fn chunk(&mut self) -> Result<Option<Bytes>> {
+        match self.lines.peek() {
+            Some(Ok(ExportLine::Blob { blob, .. })) if *blob == self.hash => {}
+            _ => return Ok(None),
+        }
+        let Some(Ok(ExportLine::Blob { data, .. })) = self.lines.next() else {
+            return Ok(None);
+        };
+        let data = base64::engine::general_purpose::STANDARD
+            .decode(data)
+            .map_err(err_export)?;
+        Ok(Some(data.into()))
+    }
+}
+
+/// Load an export file in an empty data directory.
+pub async fn import(server: &State, path: &Path) -> Result<Exported> {
+    if !server.storage.keys()?.is_empty() || !server.blobs.list()?.is_empty() {
+        return Err(WrapError::http(
+            StatusCode::CONFLICT,
+            "The data directory is not empty",
+        ));
+    }
+    let file = std::fs::File::open(path).map_err(err_storage)?;
+    let mut lines = std::io::BufReader::new(file)
+        .lines()
+        .map(|line| {
+            serde_json::from_str::<ExportLine>(&line.map_err(err_export)?).map_err(err_export)
+        })
+        .peekable();
+    let mut imported = Exported::default();
+
+    match lines.next() {
+        Some(Ok(ExportLine::Header { brume_export })) if brume_export <= EXPORT_VERSION => {}
+        Some(Ok(_)) => {
+            return Err(WrapError::http(
+                StatusCode::BAD_REQUEST,
+                "Unsupported export version",
+            ));
+        }
+        Some(Err(err)) => return Err(err),
+        None => return Err(err_export(WrapError::new("empty file"))),
+    }
+    while let Some(line) = lines.peek() {
+        let hash = match line {
+            Ok(ExportLine::Blob { blob, .. }) => blob.clone(),
+            _ => {
+                match lines.next() {
+                    Some(Ok(ExportLine::Value { key, value })) => {
+                        server.storage.save(&key, &value)?;
+                        imported.values += 1;
+                    }
+                    Some(Err(err)) => return Err(err),
+                    _ => return Err(err_export(WrapError::new("second header"))),
+                }
+                continue;
+            }
+        };
+        let mut parts = BlobParts {
+            hash: hash.clone(),
+            lines: &mut lines,
+        };
+        server.blobs.import(&hash, &mut parts).await?;
+        imported.blobs += 1;
+    }
+    Ok(imported)
+}
+
+#[tokio::test]
+async fn export_import() {
+    let server = crate::app_driver::test_state("export_import");
+    let password = user_add(
+        &server,
+        "root".to_string(),
+        "Root".to_string(),
+        UserLevel::SuperAdmin,
+        None,
+    )
+    .await
+    .unwrap();
+    assert!(
+        hand_user::check_password(&server, "root", password)
+            .await
+            .is_ok()
+    );
+    let group = group_add(&server, "Team".to_string(), "root")
+        .await
+        .unwrap();
+    let token = token_issue(&server, 1).unwrap();
+    assert!(token.starts_with("U1."));
+    assert!(token_issue(&server, 404).is_err());
+
+    let file = crate::io_http::FileTarget::New {
+        parent: 0,
+        group: Some(group),
+        name: "a.txt".to_string(),
+    };
+    let user = server.users.read().unwrap()[&1].token();
+    let mut body = axum::body::Body::from("Hello");
+    super::hand_files::put(&server, user.clone(), file, &mut body)
+        .await
+        .unwrap();
+    // Exported in two parts, and in one empty part.
+    for (name, data) in [
+        ("big.bin", vec![7u8; BLOB_CHUNK + 10]),
+        ("empty", Vec::new()),
+    ] {
+        let file = crate::io_http::FileTarget::New {
+            parent: 0,
+            group: Some(group),
+            name: name.to_string(),
+        };
+        let mut body = axum::body::Body::from(data);
+        super::hand_files::put(&server, user.clone(), file, &mut body)
+            .await
+            .unwrap();
+    }
+
+    let path = crate::app_driver::storage::test_dir("export_import_file").join("export.jsonl");
+    let exported = export(&server, &path).await.unwrap();
+    assert_eq!(3, exported.blobs);
+    let blob_lines = std::fs::read_to_string(&path)
+        .unwrap()
+        .lines()
+        .filter(|line| line.starts_with(r#"{"blob""#))
+        .count();
+    assert_eq!(4, blob_lines);
+    assert!(import(&server, &path).await.is_err());
+
+    let copy = crate::app_driver::test_state("export_import_copy");
+    assert_eq!(exported, import(&copy, &path).await.unwrap());
+    assert_eq!(server.storage.keys().unwrap(), copy.storage.keys().unwrap());
+    assert_eq!(server.blobs.list().unwrap(), copy.blobs.list().unwrap());
+}
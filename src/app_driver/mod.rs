@@ -1,35 +1,215 @@
+pub mod blob;
 mod error;
+mod hand_acl;
+mod hand_admin;
+mod hand_files;
+mod hand_group;
 mod hand_home;
+mod hand_user;
+mod hand_wiki;
+pub mod maintenance;
+pub mod storage;
 
-use crate::{bmime, io_http::*, *};
+use crate::{bmime, io_http::*, theme::Theme, *};
+use blob::BlobStore;
 use std::sync::Arc;
+use storage::Storage;
+
+/// Pre generated pages indexed by absolute path.
+/// Value is a MIME type and the content.
+pub type Pages = std::collections::BTreeMap<String, CachedPage>;
 
 /// Access to all informations for the handlers.
 #[derive(Debug)]
 pub struct State {
+    /// Persistent data, loaded at start and written by all mutating handlers.
+    pub storage: Box<dyn Storage>,
+    /// Serialize the update of the identifier counters.
+    counter_lock: std::sync::Mutex<()>,
+    /// Keys to sign the user tokens.
+    pub token_keys: TokenKeys,
+    /// Validity duration of a user token, in seconds.
+    pub token_lifetime: u64,
+
+    /// The content of the files.
+    pub blobs: BlobStore,
+    /// Reference count of the blobs.
+    pub blob_refs: std::sync::Mutex<blob::BlobRefs>,
+    /// Maximum size of an uploaded file, in bytes.
+    pub upload_max: u64,
+    /// The tree of folders and files.
+    pub nodes: std::sync::RwLock<hand_files::Nodes>,
+    /// The deleted nodes.
+    pub trash: std::sync::Mutex<hand_files::trash::Trash>,
+    /// Age in seconds after which a trash entry is purged.
+    pub trash_max_age: u64,
+
+    /// The user directory.
+    pub users: std::sync::RwLock<hand_user::Users>,
+    /// The groups, their members are in the users.
+    pub groups: std::sync::RwLock<hand_group::Groups>,
+    /// The passwords checked recently, for HTTP Basic.
+    pub checked_passwords: std::sync::Mutex<hand_user::CheckedPasswords>,
+
+    /// The active WebDAV locks.
+    pub dav_locks: DavLocks,
+
     /// Pre generated pages, ready to send to HTTP client.
-    /// Indexed by absolute path.
-    /// Value is a MIME type and the content.
-    pub pages: std::sync::RwLock<std::collections::BTreeMap<String, (&'static str, Arc<Vec<u8>>)>>,
+    pub pages: std::sync::RwLock<Pages>,
+    /// Templates of the generated pages.
+    pub theme: Theme,
+
+    /// The wiki pages, rendered in the generated pages.
+    pub wiki: std::sync::RwLock<hand_wiki::Wiki>,
 
     /// The page data behind the root path `/`.
-    /// Only administrator can edit it.
     pub home: std::sync::Mutex<hand_home::Page>,
+    /// The groups that can edit the home page, with the administrators.
+    pub home_acl: std::sync::Mutex<hand_acl::Acl>,
 }
 
 impl State {
-    pub fn new() -> Result<Self> {
+    /// Default value of [`State::upload_max`]: 1 GiB.
+    pub const UPLOAD_MAX_DEFAULT: u64 = 1 << 30;
+    /// Default value of [`State::token_lifetime`]: 3.5 days.
+    pub const TOKEN_LIFETIME_DEFAULT: u64 = 7 * 12 * 3600;
+    /// Default value of [`State::trash_max_age`]: 30 days.
+    pub const TRASH_MAX_AGE_DEFAULT: u64 = 30 * 24 * 3600;
+
+    pub fn new(
+        storage: Box<dyn Storage>,
+        blobs: BlobStore,
+        token_keys: TokenKeys,
+        theme: Theme,
+    ) -> Result<Self> {
         let server = State {
-            pages: std::sync::RwLock::new(std::collections::BTreeMap::new()),
+            storage,
+            counter_lock: Default::default(),
+            token_keys,
+            token_lifetime: Self::TOKEN_LIFETIME_DEFAULT,
+            blobs,
+            blob_refs: Default::default(),
+            upload_max: Self::UPLOAD_MAX_DEFAULT,
+            nodes: Default::default(),
+            trash: Default::default(),
+            trash_max_age: Self::TRASH_MAX_AGE_DEFAULT,
+            users: Default::default(),
+            groups: Default::default(),
+            checked_passwords: Default::default(),
+            dav_locks: Default::default(),
+            pages: std::sync::RwLock::new(Pages::new()),
+            theme,
+            wiki: Default::default(),
             home: hand_home::Page::default().into(),
+            home_acl: Default::default(),
         };
 
         hand_home::init(&server)?;
+        blob::init(&server)?;
+        hand_files::init(&server)?;
+        hand_user::init(&server)?;
+        hand_group::init(&server)?;
+        hand_wiki::init(&server)?;
 
         Ok(server)
     }
+
+    /// Start the background maintenance, every hour: purge the old trash
+    /// entries and remove the unused blobs.
+    pub fn spawn_tasks(self: &Arc<Self>) {
+        let server = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(3600));
+            loop {
+                interval.tick().await;
+                // The storage is blocking, out of the runtime.
+                let server = server.clone();
+                let result = tokio::task::spawn_blocking(move || {
+                    hand_files::trash::purge_old(&server).and_then(|_| blob::collect(&server))
+                })
+                .await;
+                match result {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(err)) => crate::log!(Error, "Maintenance fail: {err}"),
+                    Err(err) => crate::log!(Error, "Maintenance fail: {err}"),
+                }
+            }
+        });
+    }
+
+    /// Create the first administrator if there is no user, with the given
+    /// password or a generated one. Return the password of the new user.
+    pub async fn first_admin(&self, password: Option<String>) -> Result<Option<String>> {
+        hand_user::first_admin(self, password).await
+    }
+
+    /// Get a new unique identifier from the persistent counter.
+    pub fn new_id(&self, counter: &str) -> Result<u64> {
+        let _guard = self.counter_lock.lock().map_err(error::err_sync_fail)?;
+        let key = format!("counter/{counter}");
+        let id = self.storage.load::<u64>(&key)?.unwrap_or(0) + 1;
+        self.storage.save(&key, &id)?;
+        Ok(id)
+    }
+}
+
+/// Create a state with a volatile storage for a test.
+#[cfg(test)]
+pub fn test_state(name: &str) -> State {
+    let blobs = BlobStore::open(storage::test_dir(name)).unwrap();
+    let keys = TokenKeys::random().unwrap();
+    let storage = Box::<storage::MemoryStorage>::default();
+    State::new(storage, blobs, keys, Theme::default()).unwrap()
 }
 
+pub use crate::now;
+
+/// The operations of the JSON API `/_api.json/{name}`, and `_catalog` lists
+/// them with the JSON Schema of their DTO. The global level is checked first:
+/// `SeeData` for a logged user, `EditData` to change the data. The handlers
+/// then check the level on the group or the resource.
+const OPERATIONS: &[Operation<State>] = &[
+    api_operation!("auth.login", None, hand_user::login),
+    api_operation!("auth.logout", None, hand_user::logout),
+    api_operation!("auth.logout_all", SeeData, hand_user::logout_all),
+    api_operation!("auth.password", SeeData, hand_user::password),
+    api_operation!("user.create", Admin, hand_user::create),
+    api_operation!("group.create", Admin, hand_group::create),
+    api_operation!("group.members", SeeData, hand_group::members),
+    api_operation!("group.add_member", SeeData, hand_group::add_member),
+    api_operation!("group.set_level", SeeData, hand_group::set_level),
+    api_operation!("group.remove_member", SeeData, hand_group::remove_member),
+    api_operation!("group.delete", SeeData, hand_group::delete),
+    api_operation!("home.get", None, hand_home::get),
+    api_operation!("home.set", EditData, hand_home::set),
+    api_operation!("wiki.get", None, hand_wiki::get),
+    api_operation!("wiki.list", None, hand_wiki::list),
+    api_operation!("wiki.set", EditData, hand_wiki::set),
+    api_operation!("wiki.delete", EditData, hand_wiki::delete),
+    api_operation!("wiki.revisions", None, hand_wiki::revisions),
+    api_operation!("wiki.restore", EditData, hand_wiki::restore),
+    api_operation!("node.get", None, hand_files::get),
+    api_operation!("node.move", EditData, hand_files::r#move),
+    api_operation!("node.copy", EditData, hand_files::copy),
+    api_operation!("node.delete", EditData, hand_files::trash::delete),
+    api_operation!("file.versions", None, hand_files::versions),
+    api_operation!("file.restore", EditData, hand_files::restore),
+    api_operation!("file.diff", None, hand_files::diff),
+    api_operation!("file.retention.get", None, hand_files::retention_get),
+    api_operation!("file.retention.set", EditData, hand_files::retention_set),
+    api_operation!("folder.list", None, hand_files::list),
+    api_operation!("folder.create", EditData, hand_files::create),
+    api_operation!("trash.list", SeeData, hand_files::trash::list),
+    api_operation!("trash.restore", EditData, hand_files::trash::restore),
+    api_operation!("trash.empty", EditData, hand_files::trash::empty),
+    api_operation!("acl.get", SeeData, hand_acl::get),
+    api_operation!("acl.set", EditData, hand_acl::set),
+    api_operation!("acl.effective", None, hand_acl::effective),
+    api_operation!("admin.storage", Admin, hand_admin::storage),
+    api_operation!("admin.gc", Admin, hand_admin::gc),
+    api_operation!("admin.revoke_sessions", Admin, hand_user::revoke_sessions),
+];
+
 #[async_trait::async_trait]
 impl HTTPState for State {
     const ASSETS: &[(&str, &str, &[u8])] = &[
@@ -43,21 +223,31 @@ impl HTTPState for State {
             bmime::WEBP,
             include_bytes!("assets/favicon.webp"),
         ),
+        // The web client.
+        ("/app/", bmime::HTML, include_bytes!("assets/app.html")),
+        ("/app/app.js", bmime::JS, include_bytes!("assets/app.js")),
+        ("/app/app.css", bmime::CSS, include_bytes!("assets/app.css")),
     ];
 
-    fn cached(&self, path: &str) -> Option<(&'static str, Arc<Vec<u8>>)> {
-        if let Some(pages) = self.pages.read().ok() {
-            pages.get(path).cloned()
-        } else {
-            None
+    fn cached(&self, path: &str, user: &UserToken) -> Result<Option<CachedPage>> {
+        if path == "/wiki" || path.starts_with(hand_wiki::INDEX) {
+            return hand_wiki::cached(self, path, user);
         }
+        let pages = self.pages.read().map_err(error::err_sync_fail)?;
+        Ok(pages.get(path).cloned())
     }
 
-    const ERROR_404: &[u8] = b"<!DOCTYPE html>404 Not Found\r\n";
+    fn theme(&self) -> &Theme {
+        &self.theme
+    }
 
     /// Key to sign user token.
-    fn user_token_key<'a>(&'a self) -> &'a [u8] {
-        &[0u8]
+    fn user_token_keys(&self) -> &TokenKeys {
+        &self.token_keys
+    }
+
+    fn token_lifetime(&self) -> u64 {
+        self.token_lifetime
     }
 
     async fn api_json(
@@ -66,10 +256,149 @@ impl HTTPState for State {
         user: UserToken,
         data: &[u8],
     ) -> Result<(Option<UserToken>, Vec<u8>)> {
-        match operation {
-            "home.get" => api_data_call(self, user, data, hand_home::get).await,
-            "home.set" => api_data_call(self, user, data, hand_home::set).await,
-            _ => Ok((None, vec![])),
+        if operation == "_catalog" {
+            return Ok((None, encode_json(&catalog(OPERATIONS))?));
+        }
+        let Some(operation) = OPERATIONS.iter().find(|op| op.name == operation) else {
+            return Err(error::err_unknown_operation());
+        };
+        if user.level < operation.level {
+            return Err(error::err_forbidden());
+        }
+        (operation.call)(self, user, data).await
+    }
+
+    fn current_user(&self, user: &UserToken, created: u64) -> Option<UserToken> {
+        let users = self.users.read().ok()?;
+        let current = users.get(&user.id)?;
+        match created <= current.rights_changed {
+            true => Some(current.token()),
+            false => None,
+        }
+    }
+
+    fn sessions_revoked(&self, user: u32) -> u64 {
+        self.users
+            .read()
+            .ok()
+            .and_then(|users| users.get(&user).map(|user| user.sessions_revoked))
+            .unwrap_or_default()
+    }
+
+    async fn login(&self, login: &str, password: String) -> Result<UserToken> {
+        let user = hand_user::check_password(self, login, password).await?;
+        Ok(user.token())
+    }
+
+    async fn file_put(
+        &self,
+        user: UserToken,
+        target: FileTarget,
+        input: &mut dyn ByteStream,
+    ) -> Result<Vec<u8>> {
+        let file = hand_files::put(self, user, target, input).await?;
+        encode_json(&file)
+    }
+
+    async fn file_get(&self, user: UserToken, id: u64) -> Result<FileOutput> {
+        hand_files::open(self, user, id).await
+    }
+
+    fn dav_locks(&self) -> &DavLocks {
+        &self.dav_locks
+    }
+
+    async fn dav_find(
+        &self,
+        user: UserToken,
+        path: &[String],
+        children: bool,
+    ) -> Result<Vec<DavEntry>> {
+        hand_files::dav::find(self, &user, path, children)
+    }
+
+    async fn dav_get(&self, user: UserToken, path: &[String]) -> Result<FileOutput> {
+        hand_files::dav::get(self, user, path).await
+    }
+
+    async fn dav_put(
+        &self,
+        user: UserToken,
+        path: &[String],
+        input: &mut dyn ByteStream,
+    ) -> Result<bool> {
+        hand_files::dav::put(self, user, path, input).await
+    }
+
+    async fn dav_mkcol(&self, user: UserToken, path: &[String]) -> Result<()> {
+        hand_files::dav::mkcol(self, user, path).await
+    }
+
+    async fn dav_delete(&self, user: UserToken, path: &[String]) -> Result<()> {
+        hand_files::dav::delete(self, user, path).await
+    }
+
+    async fn dav_transfer(
+        &self,
+        user: UserToken,
+        from: &[String],
+        to: &[String],
+        copy: bool,
+        overwrite: bool,
+    ) -> Result<bool> {
+        hand_files::dav::transfer(self, user, from, to, copy, overwrite).await
+    }
+}
+
+#[tokio::test]
+async fn operation_registry() {
+    let server = test_state("operation_registry");
+    let err = server
+        .api_json("home.sett", UserToken::DEV_EDITOR, b"")
+        .await
+        .unwrap_err();
+    assert_eq!(Some(axum::http::StatusCode::NOT_FOUND), err.status_http);
+    let err = server
+        .api_json("admin.gc", UserToken::DEV_EDITOR, b"")
+        .await
+        .unwrap_err();
+    assert_eq!(Some(axum::http::StatusCode::FORBIDDEN), err.status_http);
+    let err = server
+        .api_json("folder.create", UserToken::default(), b"")
+        .await
+        .unwrap_err();
+    assert_eq!(Some(axum::http::StatusCode::FORBIDDEN), err.status_http);
+    let (_, home) = server
+        .api_json("home.get", UserToken::default(), b"")
+        .await
+        .unwrap();
+    assert!(home.starts_with(b"{\"title\":"));
+
+    let (_, output) = server
+        .api_json("_catalog", UserToken::default(), b"")
+        .await
+        .unwrap();
+    let catalog: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let operations = catalog["operations"].as_array().unwrap();
+    assert_eq!(OPERATIONS.len(), operations.len());
+    let login = operations.iter().find(|op| op["name"] == "auth.login");
+    let login = login.unwrap();
+    assert_eq!("None", login["level"]);
+    let create = operations.iter().find(|op| op["name"] == "folder.create");
+    assert_eq!("EditData", create.unwrap()["level"]);
+    assert_eq!("#/$defs/Login", login["input"]["$ref"]);
+    assert_eq!(
+        serde_json::json!(["login", "password"]),
+        catalog["$defs"]["Login"]["required"]
+    );
+    // Every reference has its definition.
+    for operation in operations {
+        for schema in [&operation["input"], &operation["output"]] {
+            let text = schema.to_string();
+            for reference in text.split("\"#/$defs/").skip(1) {
+                let name = &reference[..reference.find('"').unwrap()];
+                assert!(catalog["$defs"].get(name).is_some(), "{name}");
+            }
         }
     }
 }
@@ -0,0 +1,298 @@
+//! Persistent key value storage of the state data.
+//!
+//! A key is a `/` separated path, each segment is not empty and only
+//! contains ASCII alphanumeric characters, `_`, `-` or `.` (not in first
+//! position). Values are raw bytes, the helpers [`Storage::load`] and
+//! [`Storage::save`] encode them in JSON.
+
+use crate::{
+    app_driver::error::{err_storage, err_sync_fail},
+    *,
+};
+use axum::http::StatusCode;
+use serde::{Serialize, de::DeserializeOwned};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub trait Storage: std::fmt::Debug + Send + Sync {
+    /// Get the value, or `None` if the key does not exist.
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+    /// Replace the value. After a crash, the old or the new value is read,
+    /// never a partial value.
+    fn set(&self, key: &str, value: &[u8]) -> Result<()>;
+    /// Remove the key, do nothing if it does not exist.
+    fn remove(&self, key: &str) -> Result<()>;
+    /// List the sorted names of direct children values of the prefix.
+    /// With `"a"` as prefix, the key `"a/b"` give `"b"`.
+    fn list(&self, prefix: &str) -> Result<Vec<String>>;
+    /// List all the keys, sorted.
+    fn keys(&self) -> Result<Vec<String>>;
+}
+
+impl dyn Storage {
+    /// Get and decode a JSON value.
+    pub fn load<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        match self.get(key)? {
+            Some(data) => serde_json::from_slice(&data).map(Some).map_err(|err| {
+                WrapError::http(StatusCode::INTERNAL_SERVER_ERROR, "storage decoding fail")
+                    .add_err(err)
+            }),
+            None => Ok(None),
+        }
+    }
+
+    /// Encode and set a JSON value.
+    pub fn save<T: Serialize>(&self, key: &str, value: &T) -> Result<()> {
+        let data = serde_json::to_vec(value).map_err(|err| {
+            WrapError::http(StatusCode::INTERNAL_SERVER_ERROR, "storage encoding fail").add_err(err)
+        })?;
+        self.set(key, &data)
+    }
+}
+
+fn check_key(key: &str) -> Result<()> {
+    let valid = key.split('/').all(|segment| {
+        !segment.is_empty()
+            && !segment.starts_with('.')
+            && segment
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-' || b == b'.')
+    });
+    match valid {
+        true => Ok(()),
+        false => Err(WrapError::http(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "invalid storage key",
+        )),
+    }
+}
+
+/* MEMORY */
+
+/// A volatile storage, all is lost when the process stop.
+#[derive(Debug, Default)]
+pub struct MemoryStorage(Mutex<BTreeMap<String, Vec<u8>>>);
+
+impl Storage for MemoryStorage {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        check_key(key)?;
+        let map = self.0.lock().map_err(err_sync_fail)?;
+        Ok(map.get(key).cloned())
+    }
+    fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        check_key(key)?;
+        let mut map = self.0.lock().map_err(err_sync_fail)?;
+        map.insert(key.to_string(), value.to_vec());
+        Ok(())
+    }
+    fn remove(&self, key: &str) -> Result<()> {
+        check_key(key)?;
+        let mut map = self.0.lock().map_err(err_sync_fail)?;
+        map.remove(key);
+        Ok(())
+    }
+    fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        check_key(prefix)?;
+        let map = self.0.lock().map_err(err_sync_fail)?;
+        let prefix = format!("{prefix}/");
+        Ok(map
+            .keys()
+            .filter_map(|key| key.strip_prefix(&prefix))
+            .filter(|name| !name.contains('/'))
+            .map(String::from)
+            .collect())
+    }
+    fn keys(&self) -> Result<Vec<String>> {
+        let map = self.0.lock().map_err(err_sync_fail)?;
+        Ok(map.keys().cloned().collect())
+    }
+}
+
+/* DIRECTORY */
+
+/// A storage in a directory, one file by value.
+///
+/// A value is first written in a temporary file, synced, and then renamed
+/// over the old value.
+///
+/// The handlers write under the locks of the state, the writes can not move
+/// to another thread. They block their thread until the disk syncs: on the
+/// multi-thread runtime, the other requests move to the other workers, on a
+/// single thread runtime, they wait.
+#[derive(Debug)]
+pub struct DirStorage {
+    root: PathBuf,
+    /// Serialize writers to use an unique temporary file.
+    write_lock: Mutex<()>,
+}
+
+impl DirStorage {
+    const TMP_NAME: &str = ".tmp";
+
+    pub fn open(root: impl Into<PathBuf>) -> Result<Self> {
+        let root = root.into();
+        std::fs::create_dir_all(&root).map_err(err_storage)?;
+        Ok(Self {
+            root,
+            write_lock: Mutex::new(()),
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+}
+
+impl Storage for DirStorage {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        check_key(key)?;
+        match std::fs::read(self.root.join(key)) {
+            Ok(data) => Ok(Some(data)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err_storage(err)),
+        }
+    }
+
+    fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        check_key(key)?;
+        let path = self.root.join(key);
+        let parent = path.parent().unwrap_or(&self.root);
+        blocking(|| {
+            std::fs::create_dir_all(parent).map_err(err_storage)?;
+
+            let _guard = self.write_lock.lock().map_err(err_sync_fail)?;
+            let tmp = self.root.join(Self::TMP_NAME);
+            let mut file = std::fs::File::create(&tmp).map_err(err_storage)?;
+            file.write_all(value).map_err(err_storage)?;
+            file.sync_all().map_err(err_storage)?;
+            std::fs::rename(&tmp, &path).map_err(err_storage)?;
+            sync_dir(parent)
+        })
+    }
+
+    fn remove(&self, key: &str) -> Result<()> {
+        check_key(key)?;
+        let path = self.root.join(key);
+        blocking(|| match std::fs::remove_file(&path) {
+            Ok(()) => sync_dir(path.parent().unwrap_or(&self.root)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err_storage(err)),
+        })
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        check_key(prefix)?;
+        let entries = match std::fs::read_dir(self.root.join(prefix)) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err_storage(err)),
+        };
+        let mut names = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(err_storage)?;
+            if !entry.file_type().map_err(err_storage)?.is_file() {
+                continue;
+            }
+            if let Some(name) = entry.file_name().to_str()
+                && !name.starts_with('.')
+            {
+                names.push(name.to_string());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn keys(&self) -> Result<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut dirs = vec![String::new()];
+        while let Some(prefix) = dirs.pop() {
+            for entry in std::fs::read_dir(self.root.join(&prefix)).map_err(err_storage)? {
+                let entry = entry.map_err(err_storage)?;
+                let Some(name) = entry.file_name().to_str().map(String::from) else {
+                    continue;
+                };
+                if name.starts_with('.') {
+                    continue;
+                }
+                let key = match prefix.is_empty() {
+                    true => name,
+                    false => format!("{prefix}/{name}"),
+                };
+                match entry.file_type().map_err(err_storage)?.is_dir() {
+                    true => dirs.push(key),
+                    false => keys.push(key),
+                }
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+/// Run a blocking write. A worker of the multi-thread runtime first gives
+/// its other tasks to the other workers.
+fn blocking<T>(write: impl FnOnce() -> T) -> T {
+    use tokio::runtime::{Handle, RuntimeFlavor};
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(write)
+        }
+        _ => write(),
+    }
+}
+
+/// Persist the entries of a directory, so a rename is durable.
+fn sync_dir(dir: &Path) -> Result<()> {
+    std::fs::File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .map_err(err_storage)
+}
+
+/// Create a new empty directory for a test.
+#[cfg(test)]
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("brume-test-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn dir_storage() {
+    let storage: Box<dyn Storage> = Box::new(DirStorage::open(test_dir("dir_storage")).unwrap());
+
+    assert_eq!(None, storage.get("a/b").unwrap());
+    storage.set("a/b", b"value").unwrap();
+    storage.set("a/c", b"other").unwrap();
+    storage.set("a/d/e", b"deep").unwrap();
+    assert_eq!(Some(b"value".to_vec()), storage.get("a/b").unwrap());
+    assert_eq!(vec!["b", "c"], storage.list("a").unwrap());
+
+    storage.remove("a/b").unwrap();
+    assert_eq!(None, storage.get("a/b").unwrap());
+    assert_eq!(vec!["c"], storage.list("a").unwrap());
+    assert_eq!(vec!["a/c", "a/d/e"], storage.keys().unwrap());
+
+    storage.save("json", &(1, "two")).unwrap();
+    assert_eq!(Some((1, "two".to_string())), storage.load("json").unwrap());
+
+    assert!(storage.get("../escape").is_err());
+    assert!(storage.get("a//b").is_err());
+
+    // From a task of both runtimes.
+    let storage: std::sync::Arc<dyn Storage> = storage.into();
+    for mut builder in [
+        tokio::runtime::Builder::new_current_thread(),
+        tokio::runtime::Builder::new_multi_thread(),
+    ] {
+        let runtime = builder.build().unwrap();
+        let task = storage.clone();
+        let task = runtime.spawn(async move { task.set("a/c", b"async") });
+        runtime.block_on(task).unwrap().unwrap();
+        assert_eq!(Some(b"async".to_vec()), storage.get("a/c").unwrap());
+        storage.remove("a/c").unwrap();
+    }
+}
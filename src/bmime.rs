@@ -3,4 +3,98 @@ pub const ICO: &str = "image/vnd.microsoft.icon";
 pub const WEBP: &str = "image/webp";
 pub const HTML: &str = "text/html";
 pub const JSON: &str = "application/json";
+pub const XML: &str = "application/xml; charset=UTF-8";
 pub const TEXT: &str = "text/plain; charset=UTF-8";
+pub const MARKDOWN: &str = "text/markdown; charset=UTF-8";
+pub const JS: &str = "text/javascript";
+pub const PNG: &str = "image/png";
+pub const JPEG: &str = "image/jpeg";
+pub const GIF: &str = "image/gif";
+pub const SVG: &str = "image/svg+xml";
+pub const PDF: &str = "application/pdf";
+pub const ZIP: &str = "application/zip";
+pub const MP4: &str = "video/mp4";
+pub const OCTET_STREAM: &str = "application/octet-stream";
+
+/// Detect the MIME type of a file from the first bytes of its content,
+/// or else from the extension of its name.
+pub fn detect(name: &str, head: &[u8]) -> &'static str {
+    const MAGICS: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1A\n", PNG),
+        (b"\xFF\xD8\xFF", JPEG),
+        (b"GIF87a", GIF),
+        (b"GIF89a", GIF),
+        (b"%PDF-", PDF),
+        (b"PK\x03\x04", ZIP),
+        (b"\x00\x00\x01\x00", ICO),
+    ];
+    for &(magic, mime) in MAGICS {
+        if head.starts_with(magic) {
+            return mime;
+        }
+    }
+    if head.len() >= 12 && head.starts_with(b"RIFF") && &head[8..12] == b"WEBP" {
+        return WEBP;
+    }
+    if head.len() >= 8 && &head[4..8] == b"ftyp" {
+        return MP4;
+    }
+
+    let extension = name.rsplit_once('.').map(|(_, ext)| ext).unwrap_or("");
+    match extension.to_ascii_lowercase().as_str() {
+        "css" => CSS,
+        "ico" => ICO,
+        "webp" => WEBP,
+        "html" | "htm" => HTML,
+        "json" => JSON,
+        "txt" => TEXT,
+        "md" | "markdown" => MARKDOWN,
+        "js" | "mjs" => JS,
+        "png" => PNG,
+        "jpg" | "jpeg" => JPEG,
+        "gif" => GIF,
+        "svg" => SVG,
+        "pdf" => PDF,
+        "zip" => ZIP,
+        "mp4" => MP4,
+        _ if is_text(head) => TEXT,
+        _ => OCTET_STREAM,
+    }
+}
+
+/// A type the browsers run or render with scripts: a page, an image with
+/// scripts, or a script. Served from the site, it would run as the user.
+pub fn is_active(mime: &str) -> bool {
+    let essence = mime.split(';').next().unwrap_or("").trim();
+    matches!(
+        essence.to_ascii_lowercase().as_str(),
+        "text/html"
+            | "application/xhtml+xml"
+            | "image/svg+xml"
+            | "text/xml"
+            | "application/xml"
+            | "text/javascript"
+            | "application/javascript"
+            | "application/ecmascript"
+    )
+}
+
+/// The head is valid UTF-8, except maybe a last cut character.
+fn is_text(head: &[u8]) -> bool {
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(err) => err.error_len().is_none(),
+    }
+}
+
+#[test]
+fn detect_mime() {
+    assert_eq!(PNG, detect("image.txt", b"\x89PNG\r\n\x1A\n...."));
+    assert_eq!(PDF, detect("report.pdf", b""));
+    assert_eq!(MARKDOWN, detect("README.MD", b"# Title"));
+    assert_eq!(TEXT, detect("notes", b"Hello"));
+    assert_eq!(OCTET_STREAM, detect("data", b"\xFF\xFE\x00"));
+    assert!(is_active(detect("page.HTML", b"<p>")));
+    assert!(is_active(XML));
+    assert!(!is_active(TEXT));
+}
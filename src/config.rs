@@ -0,0 +1,413 @@
+//! The options of the server, from a JSON file, the environment variables
+//! and the command line. Each source overrides the previous one.
+
+use crate::{UserLevel, app_driver::State, log::LogLevel};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+pub const HELP: &str = "\
+Usage: brume [OPTIONS] [COMMAND]
+
+Commands:
+  serve                                 Run the server, the default
+  user add <LOGIN> <NAME> [--level <LEVEL>] [--password <PASSWORD>]
+                                        Create a user, the password is generated
+                                        if not given
+  user reset-password <LOGIN> [--password <PASSWORD>]
+                                        Replace the password and revoke the tokens
+  group add <NAME> --owner <LOGIN>      Create a group with a super administrator
+  token issue --user <ID>               Print a token of the user
+  fsck [--repair]                       Check the blobs and their references
+  export <FILE>                         Write all the data in a file
+  import <FILE>                         Load an export in an empty data directory
+
+The commands other than serve must be run while the server is stopped.
+
+Options:
+  -c, --config <FILE>          JSON configuration file [env: BRUME_CONFIG]
+  -l, --listen <ADDR>          Socket address to listen, can be repeated
+                               [env: BRUME_LISTEN, comma separated]
+  -d, --data-dir <DIR>         Directory of the data [env: BRUME_DATA_DIR]
+      --token-key-file <FILE>  File of the token keys [env: BRUME_TOKEN_KEY_FILE]
+      --token-lifetime <SECS>  Validity of a user token [env: BRUME_TOKEN_LIFETIME]
+      --upload-max <SIZE>      Maximum size of an uploaded file, with an optional
+                               suffix K, M, G or T [env: BRUME_UPLOAD_MAX]
+      --log-level <LEVEL>      off, error, warn, info or debug [env: BRUME_LOG_LEVEL]
+      --theme-dir <DIR>        Templates replacing the default theme [env: BRUME_THEME_DIR]
+      --worker-threads <N>     Threads of the runtime, 0 for one by CPU
+                               [env: BRUME_WORKER_THREADS]
+      --check-config           Check the configuration, print it and exit
+  -h, --help                   Print this help
+";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Socket addresses to listen, like `0.0.0.0:8000` or `[::1]:8000`.
+    pub listen: Vec<String>,
+    /// Directory of the database and the file contents.
+    pub data_dir: PathBuf,
+    /// File of the token keys, else the keys are read from the environment
+    /// variable `BRUME_TOKEN_KEY`.
+    pub token_key_file: Option<PathBuf>,
+    /// Validity duration of a user token, in seconds.
+    pub token_lifetime: u64,
+    /// Maximum size of an uploaded file, in bytes.
+    pub upload_max: u64,
+    pub log_level: LogLevel,
+    /// Directory of the templates replacing the ones of the default theme.
+    pub theme_dir: Option<PathBuf>,
+    /// Threads running the requests, `0` for one by CPU.
+    pub worker_threads: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen: vec!["0.0.0.0:8000".to_string()],
+            data_dir: PathBuf::from("data"),
+            token_key_file: None,
+            token_lifetime: State::TOKEN_LIFETIME_DEFAULT,
+            upload_max: State::UPLOAD_MAX_DEFAULT,
+            log_level: LogLevel::default(),
+            theme_dir: None,
+            worker_threads: 0,
+        }
+    }
+}
+
+/// What the binary must do.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Serve,
+    CheckConfig,
+    Help,
+    UserAdd {
+        login: String,
+        name: String,
+        level: UserLevel,
+        password: Option<String>,
+    },
+    UserResetPassword {
+        login: String,
+        password: Option<String>,
+    },
+    GroupAdd {
+        name: String,
+        owner: String,
+    },
+    TokenIssue {
+        user: u32,
+    },
+    Fsck {
+        repair: bool,
+    },
+    Export {
+        file: PathBuf,
+    },
+    Import {
+        file: PathBuf,
+    },
+}
+
+impl Command {
+    /// Parse a command from its words and options.
+    fn parse(args: Vec<String>) -> Result<Self, String> {
+        let mut words = Vec::new();
+        let mut options = std::collections::BTreeMap::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some("repair") => {
+                    options.insert("repair".to_string(), String::new());
+                }
+                Some(name @ ("level" | "password" | "owner" | "user")) => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| format!("The option '{arg}' needs a value"))?;
+                    options.insert(name.to_string(), value);
+                }
+                Some(_) => return Err(format!("Unknown option '{arg}', see --help")),
+                None => words.push(arg),
+            }
+        }
+        let words: Vec<&str> = words.iter().map(String::as_str).collect();
+        let mut option = |name: &str| options.remove(name);
+
+        let command = match words.as_slice() {
+            ["user", "add", login, name] => Command::UserAdd {
+                login: login.to_string(),
+                name: name.to_string(),
+                level: match option("level") {
+                    Some(level) => serde_json::from_value(serde_json::Value::String(level))
+                        .map_err(|_| "Invalid level, expected: None, SeeData, EditData, Admin or SuperAdmin")?,
+                    None => UserLevel::EditData,
+                },
+                password: option("password"),
+            },
+            ["user", "reset-password", login] => Command::UserResetPassword {
+                login: login.to_string(),
+                password: option("password"),
+            },
+            ["group", "add", name] => Command::GroupAdd {
+                name: name.to_string(),
+                owner: option("owner").ok_or("The option '--owner' is needed")?,
+            },
+            ["token", "issue"] => Command::TokenIssue {
+                user: option("user")
+                    .and_then(|user| user.parse().ok())
+                    .ok_or("The option '--user' with a user id is needed")?,
+            },
+            ["fsck"] => Command::Fsck {
+                repair: option("repair").is_some(),
+            },
+            ["export", file] => Command::Export { file: file.into() },
+            ["import", file] => Command::Import { file: file.into() },
+            _ => return Err(format!("Invalid command '{}', see --help", words.join(" "))),
+        };
+        match options.keys().next() {
+            Some(name) => Err(format!("The option '--{name}' is not used by the command")),
+            None => Ok(command),
+        }
+    }
+}
+
+impl Config {
+    /// Build the configuration from the command line arguments (without the
+    /// program name) and the environment.
+    pub fn load(
+        args: impl IntoIterator<Item = String>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<(Self, Command), String> {
+        let mut args = args.into_iter();
+        let mut command = Command::Serve;
+        let mut flags = Vec::new();
+        let mut file = env("BRUME_CONFIG").map(PathBuf::from);
+        while let Some(arg) = args.next() {
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => (name, Some(value.to_string())),
+                _ => (arg.as_str(), None),
+            };
+            match name {
+                "-h" | "--help" => command = Command::Help,
+                "--check-config" => command = Command::CheckConfig,
+                "-c" | "--config" | "-l" | "--listen" | "-d" | "--data-dir"
+                | "--token-key-file" | "--token-lifetime" | "--upload-max" | "--log-level"
+                | "--theme-dir" | "--worker-threads" => {
+                    let value = inline
+                        .or_else(|| args.next())
+                        .ok_or_else(|| format!("The option '{name}' needs a value"))?;
+                    match name {
+                        "-c" | "--config" => file = Some(PathBuf::from(value)),
+                        _ => flags.push((name.to_string(), value)),
+                    }
+                }
+                "serve" => {}
+                _ if !arg.starts_with('-') => {
+                    let words = std::iter::once(arg).chain(args).collect();
+                    command = Command::parse(words)?;
+                    break;
+                }
+                _ => return Err(format!("Unknown option '{arg}', see --help")),
+            }
+        }
+        if command == Command::Help {
+            return Ok((Config::default(), command));
+        }
+
+        let mut config = match file {
+            Some(path) => {
+                let text = std::fs::read_to_string(&path).map_err(|err| {
+                    format!("Can not read the config file '{}': {err}", path.display())
+                })?;
+                serde_json::from_str(&text)
+                    .map_err(|err| format!("Invalid config file '{}': {err}", path.display()))?
+            }
+            None => Config::default(),
+        };
+
+        for (var, name) in [
+            ("BRUME_LISTEN", "--listen"),
+            ("BRUME_DATA_DIR", "--data-dir"),
+            ("BRUME_TOKEN_KEY_FILE", "--token-key-file"),
+            ("BRUME_TOKEN_LIFETIME", "--token-lifetime"),
+            ("BRUME_UPLOAD_MAX", "--upload-max"),
+            ("BRUME_LOG_LEVEL", "--log-level"),
+            ("BRUME_THEME_DIR", "--theme-dir"),
+            ("BRUME_WORKER_THREADS", "--worker-threads"),
+        ] {
+            if let Some(value) = env(var) {
+                match name {
+                    "--listen" => {
+                        config.listen = value.split(',').map(|s| s.trim().to_string()).collect()
+                    }
+                    _ => config
+                        .set(name, value)
+                        .map_err(|err| format!("{var}: {err}"))?,
+                }
+            }
+        }
+
+        // The listen flags replace the addresses of the file and environment.
+        if flags
+            .iter()
+            .any(|(name, _)| name == "-l" || name == "--listen")
+        {
+            config.listen.clear();
+        }
+        for (name, value) in flags {
+            match name.as_str() {
+                "-l" | "--listen" => config.listen.push(value),
+                _ => config
+                    .set(&name, value)
+                    .map_err(|err| format!("{name}: {err}"))?,
+            }
+        }
+
+        config.check()?;
+        Ok((config, command))
+    }
+
+    /// Set an option from its text value.
+    fn set(&mut self, name: &str, value: String) -> Result<(), String> {
+        match name {
+            "-d" | "--data-dir" => self.data_dir = PathBuf::from(value),
+            "--token-key-file" => self.token_key_file = Some(PathBuf::from(value)),
+            "--token-lifetime" => {
+                self.token_lifetime = value
+                    .parse()
+                    .map_err(|_| format!("Invalid duration '{value}'"))?
+            }
+            "--upload-max" => self.upload_max = parse_size(&value)?,
+            "--log-level" => self.log_level = value.parse()?,
+            "--theme-dir" => self.theme_dir = Some(PathBuf::from(value)),
+            "--worker-threads" => {
+                self.worker_threads = value
+                    .parse()
+                    .map_err(|_| format!("Invalid number of threads '{value}'"))?
+            }
+            _ => unreachable!("unknown option {name}"),
+        }
+        Ok(())
+    }
+
+    /// Check the values that can not be checked by their type.
+    pub fn check(&self) -> Result<(), String> {
+        if self.listen.is_empty() {
+            return Err("No address to listen".to_string());
+        }
+        for addr in &self.listen {
+            addr.parse::<std::net::SocketAddr>()
+                .map_err(|_| format!("Invalid listen address '{addr}'"))?;
+        }
+        if self.token_lifetime < 60 {
+            return Err("The token lifetime is too short, need 60 seconds".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Parse a size in bytes, with an optional binary suffix.
+fn parse_size(value: &str) -> Result<u64, String> {
+    let (number, shift) = match value.as_bytes().last() {
+        Some(b'K' | b'k') => (&value[..value.len() - 1], 10),
+        Some(b'M' | b'm') => (&value[..value.len() - 1], 20),
+        Some(b'G' | b'g') => (&value[..value.len() - 1], 30),
+        Some(b'T' | b't') => (&value[..value.len() - 1], 40),
+        _ => (value, 0),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .ok_or_else(|| format!("Invalid size '{value}'"))
+}
+
+#[test]
+fn config_sources() {
+    let args = |args: &[&str]| args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    let no_env = |_: &str| None;
+
+    let (config, command) = Config::load(args(&[]), no_env).unwrap();
+    assert_eq!((Config::default(), Command::Serve), (config, command));
+
+    let env = |var: &str| match var {
+        "BRUME_LISTEN" => Some("127.0.0.1:80, [::1]:80".to_string()),
+        "BRUME_UPLOAD_MAX" => Some("2M".to_string()),
+        "BRUME_LOG_LEVEL" => Some("warn".to_string()),
+        "BRUME_WORKER_THREADS" => Some("4".to_string()),
+        _ => None,
+    };
+    let (config, _) = Config::load(args(&["--theme-dir", "themes/dark"]), env).unwrap();
+    assert_eq!(vec!["127.0.0.1:80", "[::1]:80"], config.listen);
+    assert_eq!(Some(PathBuf::from("themes/dark")), config.theme_dir);
+    assert_eq!(2 << 20, config.upload_max);
+    assert_eq!(LogLevel::Warn, config.log_level);
+    assert_eq!(4, config.worker_threads);
+
+    let flags = args(&[
+        "--check-config",
+        "-l",
+        "127.0.0.1:8080",
+        "--log-level=debug",
+        "--token-lifetime",
+        "3600",
+        "--worker-threads=2",
+    ]);
+    let (config, command) = Config::load(flags, env).unwrap();
+    assert_eq!(Command::CheckConfig, command);
+    assert_eq!(vec!["127.0.0.1:8080"], config.listen);
+    assert_eq!(LogLevel::Debug, config.log_level);
+    assert_eq!(3600, config.token_lifetime);
+    assert_eq!(2, config.worker_threads);
+
+    assert!(Config::load(args(&["--unknown"]), no_env).is_err());
+    assert!(Config::load(args(&["--listen"]), no_env).is_err());
+    assert!(Config::load(args(&["-l", "localhost"]), no_env).is_err());
+    assert!(Config::load(args(&["--upload-max", "1X"]), no_env).is_err());
+    assert!(Config::load(args(&["--worker-threads", "-1"]), no_env).is_err());
+
+    let dir = crate::app_driver::storage::test_dir("config_sources");
+    let file = dir.join("brume.json");
+    std::fs::write(
+        &file,
+        r#"{"data_dir": "/srv/brume", "upload_max": 1024, "worker_threads": 1}"#,
+    )
+    .unwrap();
+    let flags = args(&["-c", file.to_str().unwrap(), "--upload-max", "1K"]);
+    let (config, _) = Config::load(flags, no_env).unwrap();
+    assert_eq!(PathBuf::from("/srv/brume"), config.data_dir);
+    assert_eq!(1024, config.upload_max);
+    assert_eq!(1, config.worker_threads);
+    std::fs::write(&file, r#"{"data_dir": "/srv/brume", "unknown": 1}"#).unwrap();
+    let flags = args(&["-c", file.to_str().unwrap()]);
+    assert!(Config::load(flags, no_env).is_err());
+}
+
+#[test]
+fn commands() {
+    let load = |args: &str| {
+        let args = args.split_whitespace().map(String::from);
+        Config::load(args, |_| None).map(|(_, command)| command)
+    };
+    assert_eq!(Ok(Command::Serve), load("serve -l 127.0.0.1:80"));
+    assert_eq!(
+        Ok(Command::UserAdd {
+            login: "root".to_string(),
+            name: "Root".to_string(),
+            level: UserLevel::SuperAdmin,
+            password: None,
+        }),
+        load("-d /srv/brume user add root Root --level SuperAdmin")
+    );
+    assert_eq!(
+        Ok(Command::TokenIssue { user: 56 }),
+        load("token issue --user 56")
+    );
+    assert_eq!(Ok(Command::Fsck { repair: true }), load("fsck --repair"));
+    assert!(load("user add root").is_err());
+    assert!(load("user add root Root --level Root").is_err());
+    assert!(load("group add Team").is_err());
+    assert!(load("fsck --owner root").is_err());
+    assert!(load("token issue --user bob").is_err());
+}
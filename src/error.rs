@@ -8,9 +8,23 @@ pub struct WrapError {
     /// A simple description string, without new line, returned to the client.
     pub desc: &'static str,
     /// Sub error
-    pub source_error: Option<Box<dyn std::error::Error + 'static>>,
+    pub source_error: Option<Box<dyn std::error::Error + Send + Sync + 'static>>,
     // A status code returned to the HTTP client.
     pub status_http: Option<StatusCode>,
+    /// A stable identifier for the programs, like `empty_values`.
+    pub code: Option<&'static str>,
+    /// The invalid fields of the request.
+    pub fields: Vec<FieldError>,
+}
+
+/// An invalid field of a request.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct FieldError {
+    /// The path of the field, like `target.name` for a nested field.
+    pub field: String,
+    /// The failed rule, like `required`.
+    pub code: &'static str,
+    pub message: String,
 }
 
 impl WrapError {
@@ -19,27 +33,59 @@ impl WrapError {
             desc: description,
             source_error: None,
             status_http: None,
+            code: None,
+            fields: Vec::new(),
         }
     }
 
     pub fn http(status_http: StatusCode, description: &'static str) -> Self {
         Self {
-            desc: description,
-            source_error: None,
             status_http: Some(status_http),
+            ..Self::new(description)
         }
     }
 
-    pub fn add_err<E: std::error::Error + 'static>(self, err: E) -> Self {
+    pub fn add_err<E: std::error::Error + Send + Sync + 'static>(self, err: E) -> Self {
         Self {
             source_error: Some(Box::new(err)),
             ..self
         }
     }
 
+    pub fn with_code(self, code: &'static str) -> Self {
+        Self {
+            code: Some(code),
+            ..self
+        }
+    }
+
+    pub fn add_field(mut self, field: &str, code: &'static str, message: &str) -> Self {
+        self.fields.push(FieldError {
+            field: field.to_string(),
+            code,
+            message: message.to_string(),
+        });
+        self
+    }
+
     pub fn description(&self) -> &'static str {
         self.desc
     }
+
+    /// The code of the error, else the code of the HTTP status, like
+    /// `not_found`.
+    pub fn code(&self) -> String {
+        match self.code {
+            Some(code) => code.to_string(),
+            None => self
+                .status_http
+                .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+                .canonical_reason()
+                .unwrap_or("error")
+                .to_ascii_lowercase()
+                .replace([' ', '-'], "_"),
+        }
+    }
 }
 
 impl fmt::Display for WrapError {
@@ -56,7 +102,9 @@ impl fmt::Display for WrapError {
 
 impl std::error::Error for WrapError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        self.source_error.as_deref()
+        self.source_error
+            .as_deref()
+            .map(|err| err as &(dyn std::error::Error + 'static))
     }
 
     fn description(&self) -> &'static str {
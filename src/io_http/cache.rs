@@ -0,0 +1,368 @@
+//! Conditional and partial responses: the validators `ETag` and
+//! `Last-Modified`, the requests `If-None-Match`, `If-Modified-Since`,
+//! `Range` and `If-Range`.
+//!
+//! Only one range is served, a request of many ranges gets all the content.
+//! The range and the validators apply to the encoded content, each encoding
+//! has its own `ETag`.
+
+use super::compress::{self, Encoding, Level};
+use axum::{
+    body::{Body, Bytes},
+    http::{
+        HeaderMap, HeaderName, HeaderValue, StatusCode,
+        header::{
+            ACCEPT_RANGES, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_RANGE, CONTENT_TYPE, ETAG,
+            IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED, RANGE, VARY,
+        },
+    },
+    response::{IntoResponse, Response},
+};
+use crypto::digest::Digest;
+use std::sync::{Arc, OnceLock};
+
+/// `Cache-Control` of the generated pages, checked at each use.
+pub const PAGE_CACHE: &str = "no-cache";
+/// `Cache-Control` of the assets, they only change with the binary.
+pub const ASSET_CACHE: &str = "public, max-age=86400";
+
+/// A generated page, ready to be sent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedPage {
+    pub mime: &'static str,
+    /// The content, shared by the responses without copy.
+    pub body: Bytes,
+    /// Strong validator of the content, quoted.
+    pub etag: String,
+    /// Time of the generation, in seconds since Epoch.
+    pub modified: u64,
+    /// The effort of the compression.
+    pub level: Level,
+    /// The compressed variants of the content, only when smaller. They are
+    /// computed at the first request, and shared by the clones.
+    encoded: Arc<OnceLock<Vec<(Encoding, Bytes)>>>,
+}
+
+impl CachedPage {
+    /// A generated page, compressed with a fast level: it changes with the
+    /// data.
+    pub fn new(mime: &'static str, body: impl Into<Bytes>) -> Self {
+        Self::with_level(mime, body, Level::Fast)
+    }
+
+    /// An asset, compressed with the best level: it only changes with the
+    /// binary.
+    pub fn asset(mime: &'static str, body: impl Into<Bytes>) -> Self {
+        Self::with_level(mime, body, Level::Best)
+    }
+
+    fn with_level(mime: &'static str, body: impl Into<Bytes>, level: Level) -> Self {
+        let body = body.into();
+        CachedPage {
+            mime,
+            etag: etag(&body),
+            body,
+            modified: std::time::UNIX_EPOCH
+                .elapsed()
+                .unwrap_or_default()
+                .as_secs(),
+            level,
+            encoded: Arc::default(),
+        }
+    }
+
+    /// The compressed variants, computed at the first call.
+    pub fn encoded(&self) -> &[(Encoding, Bytes)] {
+        self.encoded.get_or_init(|| {
+            if !compress::compressible(self.mime, self.body.len()) {
+                return Vec::new();
+            }
+            Encoding::ALL
+                .into_iter()
+                .map(|encoding| (encoding, encoding.compress(&self.body, self.level)))
+                .filter(|(_, data)| data.len() < self.body.len())
+                .map(|(encoding, data)| (encoding, Bytes::from(data)))
+                .collect()
+        })
+    }
+
+    /// Compute the compressed variants in a blocking task, out of the async
+    /// runtime.
+    async fn encode(&self) {
+        if self.encoded.get().is_none() {
+            let page = self.clone();
+            let _ = tokio::task::spawn_blocking(move || {
+                page.encoded();
+            })
+            .await;
+        }
+    }
+}
+
+/// A strong entity tag of the content, quoted.
+pub fn etag(content: &[u8]) -> String {
+    let mut hasher = crypto::sha2::Sha256::new();
+    hasher.input(content);
+    format!("\"{}\"", &hasher.result_str()[..32])
+}
+
+/// What to send to a `GET` request, after its conditional headers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Conditional {
+    /// The client copy is still valid.
+    NotModified,
+    Full,
+    /// The bytes from the start to the end, both included.
+    Partial(u64, u64),
+    /// The range is outside the content.
+    Unsatisfiable,
+}
+
+impl Conditional {
+    /// Evaluate the request headers for a content.
+    pub fn new(header: &HeaderMap, etag: &str, modified: u64, size: u64) -> Self {
+        let text = |name| header.get(name).and_then(|value| value.to_str().ok());
+        if let Some(tags) = text(IF_NONE_MATCH) {
+            if tags
+                .split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == "*" || tag == etag)
+            {
+                return Conditional::NotModified;
+            }
+        } else if let Some(since) = text(IF_MODIFIED_SINCE).and_then(parse_http_date)
+            && modified <= since
+        {
+            return Conditional::NotModified;
+        }
+
+        let Some(range) = text(RANGE) else {
+            return Conditional::Full;
+        };
+        let unchanged = match text(IF_RANGE) {
+            Some(validator) if validator.starts_with('"') => validator == etag,
+            Some(date) => parse_http_date(date) == Some(modified),
+            None => true,
+        };
+        match unchanged {
+            true => parse_range(range, size),
+            false => Conditional::Full,
+        }
+    }
+}
+
+/// Parse a range `bytes=<start>-<end>`, `bytes=<start>-` or
+/// `bytes=-<suffix length>`. An invalid range is ignored.
+fn parse_range(range: &str, size: u64) -> Conditional {
+    let Some((start, end)) = range
+        .strip_prefix("bytes=")
+        .filter(|range| !range.contains(','))
+        .and_then(|range| range.trim().split_once('-'))
+    else {
+        return Conditional::Full;
+    };
+    let (start, end) = match (start.parse::<u64>(), end.parse::<u64>()) {
+        (Ok(start), Ok(end)) if start <= end => (start, end.min(size.saturating_sub(1))),
+        (Ok(start), Err(_)) if end.is_empty() => (start, size.saturating_sub(1)),
+        (Err(_), Ok(suffix)) if start.is_empty() && suffix > 0 => {
+            (size.saturating_sub(suffix), size.saturating_sub(1))
+        }
+        _ => return Conditional::Full,
+    };
+    match start < size {
+        true => Conditional::Partial(start, end),
+        false => Conditional::Unsatisfiable,
+    }
+}
+
+/// The headers of the validators, sent with the content or the `304`.
+pub fn validators(etag: &str, modified: u64) -> [(HeaderName, HeaderValue); 3] {
+    [
+        (
+            ETAG,
+            HeaderValue::from_str(etag).unwrap_or(HeaderValue::from_static("\"\"")),
+        ),
+        (
+            LAST_MODIFIED,
+            HeaderValue::from_str(&http_date(modified)).unwrap(),
+        ),
+        (ACCEPT_RANGES, HeaderValue::from_static("bytes")),
+    ]
+}
+
+/// The `Content-Range` of a partial response, or of an unsatisfiable range
+/// without `start` and `end`.
+pub fn content_range(range: Option<(u64, u64)>, size: u64) -> (HeaderName, HeaderValue) {
+    let value = match range {
+        Some((start, end)) => format!("bytes {start}-{end}/{size}"),
+        None => format!("bytes */{size}"),
+    };
+    (CONTENT_RANGE, HeaderValue::from_str(&value).unwrap())
+}
+
+/// Send a generated page or an asset, or only a part of it, in the best
+/// encoding accepted by the client.
+pub async fn page_response(
+    header: &HeaderMap,
+    page: &CachedPage,
+    cache_control: &'static str,
+) -> Response {
+    page.encode().await;
+    let available: Vec<Encoding> = page
+        .encoded()
+        .iter()
+        .map(|(encoding, _)| *encoding)
+        .collect();
+    let encoded = Encoding::negotiate(header, &available)
+        .and_then(|encoding| page.encoded().iter().find(|(e, _)| *e == encoding));
+    let (body, etag) = match encoded {
+        Some((encoding, body)) => {
+            let tag = page.etag.trim_end_matches('"');
+            (body.clone(), format!("{tag}-{}\"", encoding.name()))
+        }
+        None => (page.body.clone(), page.etag.clone()),
+    };
+
+    let size = body.len() as u64;
+    let mut response = match Conditional::new(header, &etag, page.modified, size) {
+        Conditional::NotModified => StatusCode::NOT_MODIFIED.into_response(),
+        Conditional::Full => ([(CONTENT_TYPE, page.mime)], Body::from(body)).into_response(),
+        Conditional::Partial(start, end) => (
+            StatusCode::PARTIAL_CONTENT,
+            [(CONTENT_TYPE, page.mime)],
+            [content_range(Some((start, end)), size)],
+            Body::from(body.slice(start as usize..=end as usize)),
+        )
+            .into_response(),
+        Conditional::Unsatisfiable => (
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            [content_range(None, size)],
+        )
+            .into_response(),
+    };
+
+    let headers = response.headers_mut();
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static(cache_control));
+    headers.extend(validators(&etag, page.modified));
+    if !available.is_empty() {
+        headers.insert(VARY, HeaderValue::from_static("accept-encoding"));
+    }
+    if let Some((encoding, _)) = encoded {
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.name()));
+    }
+    response
+}
+
+/* DATES */
+
+const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Format a date for HTTP (RFC 7231), like `Thu, 01 Jan 1970 00:00:00 GMT`.
+pub fn http_date(secs: u64) -> String {
+    let days = secs / 86400;
+    let time = secs % 86400;
+
+    // Civil from days, by Howard Hinnant.
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAYS[(days % 7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// Parse an HTTP date in the preferred format, like
+/// `Sun, 06 Nov 1994 08:49:37 GMT`.
+fn parse_http_date(date: &str) -> Option<u64> {
+    let parts: Vec<&str> = date.split_whitespace().collect();
+    let [_, day, month, year, time, "GMT"] = parts[..] else {
+        return None;
+    };
+    let day: i64 = day.parse().ok()?;
+    let month = MONTHS.iter().position(|name| *name == month)? as i64 + 1;
+    let year: i64 = year.parse().ok()?;
+    let mut time = time.split(':').map(|part| part.parse::<i64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+
+    // Days from civil, by Howard Hinnant.
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    u64::try_from(days * 86400 + hour * 3600 + minute * 60 + second).ok()
+}
+
+#[test]
+fn conditional_requests() {
+    assert_eq!("Thu, 01 Jan 1970 00:00:00 GMT", http_date(0));
+    assert_eq!("Sun, 01 Mar 2026 17:15:36 GMT", http_date(1772385336));
+    assert_eq!(Some(1772385336), parse_http_date(&http_date(1772385336)));
+    assert_eq!(
+        Some(951782400),
+        parse_http_date("Tue, 29 Feb 2000 00:00:00 GMT")
+    );
+    assert_eq!(None, parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"));
+
+    let etag = etag(b"Hello");
+    let modified = 1772385336;
+    let request = |headers: &[(HeaderName, &str)]| {
+        let mut header = HeaderMap::new();
+        for (name, value) in headers {
+            header.insert(name, HeaderValue::from_str(value).unwrap());
+        }
+        Conditional::new(&header, &etag, modified, 5)
+    };
+    assert_eq!(Conditional::Full, request(&[]));
+    assert_eq!(
+        Conditional::NotModified,
+        request(&[(IF_NONE_MATCH, &format!("\"other\", W/{etag}"))])
+    );
+    assert_eq!(Conditional::Full, request(&[(IF_NONE_MATCH, "\"other\"")]));
+    let date = http_date(modified);
+    assert_eq!(
+        Conditional::NotModified,
+        request(&[(IF_MODIFIED_SINCE, &date)])
+    );
+    assert_eq!(
+        Conditional::Full,
+        request(&[(IF_MODIFIED_SINCE, &http_date(modified - 1))])
+    );
+
+    assert_eq!(Conditional::Partial(1, 3), request(&[(RANGE, "bytes=1-3")]));
+    assert_eq!(Conditional::Partial(2, 4), request(&[(RANGE, "bytes=2-")]));
+    assert_eq!(Conditional::Partial(3, 4), request(&[(RANGE, "bytes=-2")]));
+    assert_eq!(
+        Conditional::Partial(0, 4),
+        request(&[(RANGE, "bytes=0-99")])
+    );
+    assert_eq!(Conditional::Unsatisfiable, request(&[(RANGE, "bytes=5-")]));
+    assert_eq!(Conditional::Full, request(&[(RANGE, "bytes=0-1,3-4")]));
+    assert_eq!(Conditional::Full, request(&[(RANGE, "lines=1-2")]));
+    assert_eq!(
+        Conditional::Partial(0, 1),
+        request(&[(RANGE, "bytes=0-1"), (IF_RANGE, &etag)])
+    );
+    assert_eq!(
+        Conditional::Full,
+        request(&[(RANGE, "bytes=0-1"), (IF_RANGE, "\"old\"")])
+    );
+}
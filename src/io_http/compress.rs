@@ -0,0 +1,185 @@
+//! Compression of the responses, negotiated with `Accept-Encoding`.
+//!
+//! The cached pages and the assets are compressed once, at their first
+//! request, out of the locks: the assets with the best level, the pages with
+//! a fast level. The JSON responses are compressed at each request with a
+//! fast level.
+
+use crate::bmime;
+use axum::http::{HeaderMap, header::ACCEPT_ENCODING};
+use std::io::Write;
+
+/// Smaller contents are not worth the compression.
+const MIN_SIZE: usize = 512;
+
+/// The types already compressed.
+const COMPRESSED: &[&str] = &[
+    bmime::WEBP,
+    bmime::PNG,
+    bmime::JPEG,
+    bmime::GIF,
+    bmime::ZIP,
+    bmime::MP4,
+];
+
+/// A content coding, in the order of preference of the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Zstd,
+    Gzip,
+}
+
+/// The effort of the compression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    /// For the assets, compressed once and sent many times.
+    Best,
+    /// For the generated pages and the content compressed at each request.
+    Fast,
+}
+
+impl Encoding {
+    pub const ALL: [Encoding; 3] = [Encoding::Brotli, Encoding::Zstd, Encoding::Gzip];
+
+    /// The name in `Content-Encoding`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zstd",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// Choose the available encoding with the best quality value of the
+    /// request, or none to send the content as is.
+    pub fn negotiate(header: &HeaderMap, available: &[Encoding]) -> Option<Encoding> {
+        let accepted: Vec<(&str, f32)> = header
+            .get_all(ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|coding| {
+                let mut params = coding.split(';');
+                let name = params.next()?.trim();
+                let quality = params
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .find_map(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                (!name.is_empty()).then_some((name, quality))
+            })
+            .collect();
+        let quality = |encoding: Encoding| {
+            let find = |name: &str| {
+                accepted
+                    .iter()
+                    .find(|(coding, _)| coding.eq_ignore_ascii_case(name))
+                    .map(|(_, quality)| *quality)
+            };
+            find(encoding.name()).or_else(|| find("*")).unwrap_or(0.0)
+        };
+
+        let mut best: Option<(Encoding, f32)> = None;
+        for encoding in Encoding::ALL {
+            let q = quality(encoding);
+            if available.contains(&encoding) && q > 0.0 && best.is_none_or(|(_, best)| best < q) {
+                best = Some((encoding, q));
+            }
+        }
+        best.map(|(encoding, _)| encoding)
+    }
+
+    /// Compress the content.
+    pub fn compress(self, data: &[u8], level: Level) -> Vec<u8> {
+        let best = level == Level::Best;
+        let mut output = Vec::with_capacity(data.len() / 3);
+        match self {
+            Encoding::Brotli => {
+                let params = brotli::enc::BrotliEncoderParams {
+                    quality: if best { 11 } else { 4 },
+                    ..Default::default()
+                };
+                let mut input = data;
+                brotli::BrotliCompress(&mut input, &mut output, &params)
+                    .expect("compress in memory");
+            }
+            Encoding::Zstd => {
+                let level = if best { 19 } else { 3 };
+                output = zstd::bulk::compress(data, level).expect("compress in memory");
+            }
+            Encoding::Gzip => {
+                let level = match best {
+                    true => flate2::Compression::best(),
+                    false => flate2::Compression::fast(),
+                };
+                let mut encoder = flate2::write::GzEncoder::new(output, level);
+                encoder.write_all(data).expect("compress in memory");
+                output = encoder.finish().expect("compress in memory");
+            }
+        }
+        output
+    }
+}
+
+/// Check if a content of the type and size is worth compressing.
+pub fn compressible(mime: &str, size: usize) -> bool {
+    size >= MIN_SIZE && !COMPRESSED.contains(&mime)
+}
+
+/// Compress a content for one response, if the client accepts it.
+pub fn compress_response(
+    header: &HeaderMap,
+    mime: &str,
+    data: Vec<u8>,
+) -> (Option<Encoding>, Vec<u8>) {
+    if !compressible(mime, data.len()) {
+        return (None, data);
+    }
+    match Encoding::negotiate(header, &Encoding::ALL) {
+        Some(encoding) => (Some(encoding), encoding.compress(&data, Level::Fast)),
+        None => (None, data),
+    }
+}
+
+#[test]
+fn negotiate_encoding() {
+    let request = |accept: &str| {
+        let mut header = HeaderMap::new();
+        header.insert(ACCEPT_ENCODING, accept.parse().unwrap());
+        Encoding::negotiate(&header, &Encoding::ALL)
+    };
+    assert_eq!(None, Encoding::negotiate(&HeaderMap::new(), &Encoding::ALL));
+    assert_eq!(None, request("identity"));
+    assert_eq!(Some(Encoding::Gzip), request("gzip"));
+    assert_eq!(Some(Encoding::Brotli), request("gzip, deflate, br, zstd"));
+    assert_eq!(Some(Encoding::Zstd), request("br;q=0.5, zstd, gzip;q=0.8"));
+    assert_eq!(Some(Encoding::Gzip), request("*, br;q=0, zstd;q=0"));
+    assert_eq!(None, request("gzip;q=0"));
+    let mut header = HeaderMap::new();
+    header.insert(ACCEPT_ENCODING, "br, gzip".parse().unwrap());
+    assert_eq!(
+        Some(Encoding::Gzip),
+        Encoding::negotiate(&header, &[Encoding::Gzip])
+    );
+    assert_eq!(None, Encoding::negotiate(&header, &[Encoding::Zstd]));
+
+    let data = "Brume stores the files. ".repeat(100);
+    for encoding in Encoding::ALL {
+        for level in [Level::Best, Level::Fast] {
+            assert!(encoding.compress(data.as_bytes(), level).len() < 200);
+        }
+    }
+    let mut decoded = String::new();
+    let gzip = Encoding::Gzip.compress(data.as_bytes(), Level::Best);
+    std::io::Read::read_to_string(&mut flate2::read::GzDecoder::new(&gzip[..]), &mut decoded)
+        .unwrap();
+    assert_eq!(data, decoded);
+    assert_eq!(
+        data.as_bytes(),
+        zstd::decode_all(&Encoding::Zstd.compress(data.as_bytes(), Level::Fast)[..]).unwrap()
+    );
+
+    assert!(!compressible(bmime::HTML, 100));
+    assert!(compressible(bmime::HTML, 1000));
+    assert!(!compressible(bmime::WEBP, 1000));
+}
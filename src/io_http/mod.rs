@@ -1,39 +1,75 @@
+mod cache;
+mod compress;
 mod serve_api_data;
+mod serve_dav;
+mod serve_files;
 mod serve_generated;
 mod usertoken;
+mod validate;
 
 use crate::*;
+use axum::extract::DefaultBodyLimit;
 use axum::http::header::{CONTENT_TYPE, SET_COOKIE};
 use axum::routing;
-use axum::{
-    Router,
-    http::{HeaderValue, StatusCode},
-};
+use axum::{Router, http::StatusCode};
+pub use cache::CachedPage;
 pub use serve_api_data::{
-    DTO, DataRequest, DataResponse, DataResponseResult, EmptyDTO, api_data_call, data_response_ok,
+    Catalog, CatalogEntry, DTO, DataRequest, DataResponse, DataResponseResult, EmptyDTO, Operation,
+    OperationFuture, SchemaFn, api_data_call, catalog, data_response_ok, encode_json,
 };
+pub use serve_dav::{DAV_ROOT, DavEntry, DavLocks};
+pub use serve_files::{ByteStream, FileOutput, FileTarget};
 use std::sync::Arc;
-use usertoken::encode_user_token;
+pub use usertoken::{TokenKey, TokenKeys, encode_user_token, issue_time};
+pub use validate::{Text, Validator};
 
 const USER_COOKIE: &str = "user=";
 
 /// Create a router.
 pub fn router<S: HTTPState + Clone + 'static>() -> Router<S> {
-    let mut router = Router::new().route(
-        "/_api.json/{service}",
-        routing::post(serve_api_data::json_handler::<S>).fallback(method_not_allowed),
-    );
+    let mut router = Router::new()
+        .route(
+            "/_api.json/{service}",
+            routing::post(serve_api_data::json_handler::<S>).fallback(method_not_allowed::<S>),
+        )
+        // The file size is limited by the state when the file is received.
+        .route(
+            "/_file",
+            routing::post(serve_files::upload::<S>)
+                .layer(DefaultBodyLimit::disable())
+                .fallback(method_not_allowed::<S>),
+        )
+        .route(
+            "/_file/{id}",
+            routing::get(serve_files::download::<S>)
+                .put(serve_files::replace::<S>)
+                .layer(DefaultBodyLimit::disable())
+                .fallback(method_not_allowed::<S>),
+        )
+        // WebDAV use its own methods, all are handled by one handler.
+        .route(DAV_ROOT, routing::any(serve_dav::serve_dav::<S>))
+        .route("/dav/", routing::any(serve_dav::serve_dav::<S>))
+        .route(
+            "/dav/{*path}",
+            routing::any(serve_dav::serve_dav::<S>).layer(DefaultBodyLimit::disable()),
+        );
 
+    // The validators of the assets are computed once, and their compression
+    // at the first request.
     for (path, mime, data) in S::ASSETS {
+        let page = CachedPage::asset(mime, axum::body::Bytes::from_static(data));
         router = router.route(
             path,
-            routing::get(async || ([(CONTENT_TYPE, HeaderValue::from_static(mime))], *data))
-                .fallback(method_not_allowed),
+            routing::get(async move |header: axum::http::HeaderMap| {
+                cache::page_response(&header, &page, cache::ASSET_CACHE).await
+            })
+            .fallback(method_not_allowed::<S>),
         )
     }
 
-    router = router
-        .fallback(routing::get(serve_generated::serve_generated::<S>).fallback(method_not_allowed));
+    router = router.fallback(
+        routing::get(serve_generated::serve_generated::<S>).fallback(method_not_allowed::<S>),
+    );
 
     if cfg!(debug_assertions) {
         router = router.route(
@@ -41,31 +77,58 @@ pub fn router<S: HTTPState + Clone + 'static>() -> Router<S> {
             routing::get(
                 async |axum::extract::State(state): axum::extract::State<S>| {
                     let now = std::time::UNIX_EPOCH.elapsed().unwrap().as_secs();
-                    let token =
-                        encode_user_token(&UserToken::DEV_EDITOR, state.user_token_key(), now);
+                    let token = encode_user_token(
+                        &UserToken::DEV_EDITOR,
+                        &state.user_token_keys().current,
+                        now,
+                    );
                     ([(SET_COOKIE, token.clone())], token)
                 },
             ),
         )
     }
 
-    router
+    router.layer(axum::middleware::from_fn(log_request))
+}
+
+/// Log the requests at the debug level, and the server errors.
+async fn log_request(
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let response = next.run(request).await;
+    let status = response.status();
+    match status.is_server_error() {
+        true => crate::log!(Error, "{method} {path} {status}"),
+        false => crate::log!(Debug, "{method} {path} {status}"),
+    }
+    response
 }
 
 #[async_trait::async_trait]
 impl<S: HTTPState> HTTPState for Arc<S> {
     const ASSETS: &[(&str, &str, &[u8])] = S::ASSETS;
 
-    fn cached(&self, path: &str) -> Option<(&'static str, Arc<Vec<u8>>)> {
-        let s: &S = &self;
-        s.cached(path)
+    fn cached(&self, path: &str, user: &UserToken) -> Result<Option<CachedPage>> {
+        let s: &S = self;
+        s.cached(path, user)
     }
 
-    const ERROR_404: &[u8] = S::ERROR_404;
+    fn theme(&self) -> &theme::Theme {
+        let s: &S = self;
+        s.theme()
+    }
 
-    fn user_token_key<'a>(&'a self) -> &'a [u8] {
-        let s: &S = &self;
-        s.user_token_key()
+    fn user_token_keys(&self) -> &TokenKeys {
+        let s: &S = self;
+        s.user_token_keys()
+    }
+
+    fn token_lifetime(&self) -> u64 {
+        let s: &S = self;
+        s.token_lifetime()
     }
 
     async fn api_json(
@@ -77,12 +140,97 @@ impl<S: HTTPState> HTTPState for Arc<S> {
         let s: &S = &self;
         s.api_json(operation, user, data).await
     }
+
+    fn current_user(&self, user: &UserToken, created: u64) -> Option<UserToken> {
+        let s: &S = self;
+        s.current_user(user, created)
+    }
+
+    fn sessions_revoked(&self, user: u32) -> u64 {
+        let s: &S = self;
+        s.sessions_revoked(user)
+    }
+
+    async fn login(&self, login: &str, password: String) -> Result<UserToken> {
+        let s: &S = self;
+        s.login(login, password).await
+    }
+
+    async fn file_put(
+        &self,
+        user: UserToken,
+        target: FileTarget,
+        input: &mut dyn ByteStream,
+    ) -> Result<Vec<u8>> {
+        let s: &S = self;
+        s.file_put(user, target, input).await
+    }
+
+    async fn file_get(&self, user: UserToken, id: u64) -> Result<FileOutput> {
+        let s: &S = self;
+        s.file_get(user, id).await
+    }
+
+    fn dav_locks(&self) -> &DavLocks {
+        let s: &S = self;
+        s.dav_locks()
+    }
+
+    async fn dav_find(
+        &self,
+        user: UserToken,
+        path: &[String],
+        children: bool,
+    ) -> Result<Vec<DavEntry>> {
+        let s: &S = self;
+        s.dav_find(user, path, children).await
+    }
+
+    async fn dav_get(&self, user: UserToken, path: &[String]) -> Result<FileOutput> {
+        let s: &S = self;
+        s.dav_get(user, path).await
+    }
+
+    async fn dav_put(
+        &self,
+        user: UserToken,
+        path: &[String],
+        input: &mut dyn ByteStream,
+    ) -> Result<bool> {
+        let s: &S = self;
+        s.dav_put(user, path, input).await
+    }
+
+    async fn dav_mkcol(&self, user: UserToken, path: &[String]) -> Result<()> {
+        let s: &S = self;
+        s.dav_mkcol(user, path).await
+    }
+
+    async fn dav_delete(&self, user: UserToken, path: &[String]) -> Result<()> {
+        let s: &S = self;
+        s.dav_delete(user, path).await
+    }
+
+    async fn dav_transfer(
+        &self,
+        user: UserToken,
+        from: &[String],
+        to: &[String],
+        copy: bool,
+        overwrite: bool,
+    ) -> Result<bool> {
+        let s: &S = self;
+        s.dav_transfer(user, from, to, copy, overwrite).await
+    }
 }
 
-pub async fn method_not_allowed() -> impl axum::response::IntoResponse {
+pub async fn method_not_allowed<S: HTTPState>(
+    axum::extract::State(state): axum::extract::State<S>,
+) -> impl axum::response::IntoResponse {
+    let status = StatusCode::METHOD_NOT_ALLOWED;
     (
-        StatusCode::METHOD_NOT_ALLOWED,
-        [(axum::http::header::CONTENT_TYPE, bmime::TEXT)],
-        "405 Method Not Allowed\r\n",
+        status,
+        [(CONTENT_TYPE, bmime::HTML)],
+        state.theme().error_page(status),
     )
 }
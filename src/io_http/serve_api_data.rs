@@ -1,14 +1,15 @@
-use super::{HTTPState, USER_COOKIE, usertoken};
+use super::{HTTPState, USER_COOKIE, compress, usertoken};
 use crate::*;
 use axum::{
     body::Bytes,
     extract::{Path, State},
     http::{
-        HeaderMap, StatusCode,
-        header::{CONTENT_TYPE, COOKIE},
+        HeaderMap, HeaderValue, StatusCode,
+        header::{ACCEPT, CONTENT_ENCODING, CONTENT_TYPE, COOKIE, SET_COOKIE, VARY},
     },
-    response::IntoResponse,
+    response::{IntoResponse, Response},
 };
+use schemars::{JsonSchema, Schema, SchemaGenerator};
 use serde::{Deserialize, Serialize};
 
 /* HANDLER INPUT / OUTPUT TYPES */
@@ -22,6 +23,8 @@ pub struct DataRequest<T: DTO> {
 
 #[derive(Debug, PartialEq)]
 pub struct DataResponse<T: Serialize> {
+    /// A new token sent to the client in the cookie.
+    /// An anonymous token removes the cookie.
     pub user: Option<UserToken>,
     pub dto: T,
 }
@@ -36,18 +39,19 @@ pub fn data_response_ok<T: Serialize>(data: T) -> DataResponseResult<T> {
 }
 
 /// Check if the data is coerent, exist. It do not have acces to the dara.
-pub trait DTO: std::fmt::Debug + for<'a> Deserialize<'a> + Default {
+/// Its schema is listed in the catalog of the operations.
+pub trait DTO: std::fmt::Debug + for<'a> Deserialize<'a> + Default + JsonSchema {
     const IS_EMPTY: bool = false;
 
-    fn check(self: &Self) -> Result<()> {
+    fn check(&self) -> Result<()> {
         Ok(())
     }
-    fn check_user(self: &Self, _user: &UserToken) -> Result<()> {
+    fn check_user(&self, _user: &UserToken) -> Result<()> {
         Ok(())
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Default)]
+#[derive(Debug, Clone, Deserialize, Default, JsonSchema)]
 pub struct EmptyDTO();
 
 impl DTO for EmptyDTO {
@@ -56,51 +60,259 @@ impl DTO for EmptyDTO {
 
 /* HANDLER WRAPER */
 
+/// The operations called with a rejected token as an anonymous user, to log
+/// in again.
+const ANONYMOUS_OPERATIONS: &[&str] = &["auth.login", "auth.logout"];
+
+/// The operations that check the password, their token is dated after the
+/// revocation of the user.
+const PASSWORD_OPERATIONS: &[&str] = &["auth.login", "auth.password"];
+
 pub async fn json_handler<S: HTTPState>(
     State(state): State<S>,
     Path(handler): Path<String>,
     header: HeaderMap,
     body: Bytes,
-) -> impl IntoResponse {
-    let user = header
-        .get(COOKIE)
-        .map(|cookie| parse_cookie(cookie.as_bytes(), state.user_token_key()))
-        .flatten()
-        .unwrap_or_else(UserToken::default);
+) -> Response {
+    let (user, refreshed) = match request_session(&state, &header) {
+        Ok(session) => session,
+        Err(_) if ANONYMOUS_OPERATIONS.contains(&handler.as_str()) => (UserToken::default(), None),
+        Err(err) => {
+            // The client forgets the rejected token.
+            let mut response = error_response(&header, &err);
+            response
+                .headers_mut()
+                .insert(SET_COOKIE, user_cookie(&state, &UserToken::default(), 0));
+            return response;
+        }
+    };
 
     match state.api_json(handler.as_str(), user, &body).await {
-        Ok((_user, output)) => (StatusCode::OK, [(CONTENT_TYPE, bmime::JSON)], output),
-        Err(err) => {
-            let status = err.status_http.unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
-            let mut output = format!("{} {}\r\n", status, status.canonical_reason().unwrap_or(""));
-            print_err(&mut output, &err);
-            (status, [(CONTENT_TYPE, bmime::TEXT)], output.into_bytes())
+        Ok((user, output)) => {
+            let now = std::time::UNIX_EPOCH
+                .elapsed()
+                .unwrap_or_default()
+                .as_secs();
+            let created = match user {
+                Some(ref user) if PASSWORD_OPERATIONS.contains(&handler.as_str()) => {
+                    usertoken::issue_time(now, state.sessions_revoked(user.id))
+                }
+                _ => now,
+            };
+            let user = user.or(refreshed);
+            let (encoding, output) = compress::compress_response(&header, bmime::JSON, output);
+            let mut response =
+                (StatusCode::OK, [(CONTENT_TYPE, bmime::JSON)], output).into_response();
+            response
+                .headers_mut()
+                .insert(VARY, HeaderValue::from_static("accept-encoding"));
+            if let Some(encoding) = encoding {
+                response
+                    .headers_mut()
+                    .insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.name()));
+            }
+            if let Some(user) = user {
+                response
+                    .headers_mut()
+                    .insert(SET_COOKIE, user_cookie(&state, &user, created));
+            }
+            response
         }
+        Err(err) => error_response(&header, &err),
     }
 }
 
-fn parse_cookie(cookies: &[u8], key: &[u8]) -> Option<UserToken> {
-    if cookies.len() == 0 {
-        return None;
+/// The `Set-Cookie` value to store the user token created at `created` in
+/// the client, or to remove the cookie for an anonymous user.
+fn user_cookie<S: HTTPState>(state: &S, user: &UserToken, created: u64) -> HeaderValue {
+    const ATTRIBUTES: &str = "Path=/; HttpOnly; Secure; SameSite=Lax";
+    let cookie = match user.id {
+        0 => format!("{USER_COOKIE}; Max-Age=0; {ATTRIBUTES}"),
+        _ => {
+            let token =
+                usertoken::encode_user_token(user, &state.user_token_keys().current, created);
+            format!(
+                "{USER_COOKIE}{token}; Max-Age={}; {ATTRIBUTES}",
+                state.token_lifetime()
+            )
+        }
+    };
+    // The token is encoded in base64.
+    HeaderValue::from_str(&cookie).unwrap()
+}
+
+/// Get the user from the request cookie, or an anonymous user without
+/// cookie.
+pub(super) fn request_user<S: HTTPState>(state: &S, header: &HeaderMap) -> Result<UserToken> {
+    request_session(state, header).map(|(user, _)| user)
+}
+
+/// Get the user from the request cookie, and a new token to send to the
+/// client when the rights of the user changed or the token is half expired.
+/// A rejected token fails with `401` and the code of the token error.
+pub(super) fn request_session<S: HTTPState>(
+    state: &S,
+    header: &HeaderMap,
+) -> Result<(UserToken, Option<UserToken>)> {
+    let cookie = header.get(COOKIE).map(|cookie| cookie.as_bytes());
+    match parse_cookie(state, cookie.unwrap_or_default())? {
+        Some((user, created)) => Ok(refresh(state, user, created)),
+        None => Ok((UserToken::default(), None)),
     }
-    let now = std::time::UNIX_EPOCH.elapsed().ok()?;
-    cookies
+}
+
+/// Get the up to date user, and a new token if it must be sent again.
+pub(super) fn refresh<S: HTTPState>(
+    state: &S,
+    user: UserToken,
+    created: u64,
+) -> (UserToken, Option<UserToken>) {
+    let now = std::time::UNIX_EPOCH.elapsed().unwrap_or_default();
+    match state.current_user(&user, created) {
+        Some(current) => (current.clone(), Some(current)),
+        None if state.token_lifetime() / 2 < now.as_secs().saturating_sub(created) => {
+            (user.clone(), Some(user))
+        }
+        None => (user, None),
+    }
+}
+
+/// Decode the first valid user token of the cookies, else the error of the
+/// first rejected one.
+fn parse_cookie<S: HTTPState>(state: &S, cookies: &[u8]) -> Result<Option<(UserToken, u64)>> {
+    let now = std::time::UNIX_EPOCH.elapsed().unwrap_or_default();
+    let mut rejected = None;
+    for token in cookies
         .split(|&b| b == b';')
+        .map(|cookie| cookie.trim_ascii())
         .filter(|&cookie| cookie.starts_with(USER_COOKIE.as_bytes()))
         .filter_map(|cookie| std::str::from_utf8(&cookie[USER_COOKIE.len()..]).ok())
-        .flat_map(|token| usertoken::decode(token, key, now.as_secs()).ok())
-        .next()
+        .filter(|token| !token.is_empty())
+    {
+        let lifetime = state.token_lifetime();
+        match usertoken::decode(
+            token,
+            state.user_token_keys(),
+            now.as_secs(),
+            lifetime,
+            |id| state.sessions_revoked(id),
+        ) {
+            Ok(user) => return Ok(Some(user)),
+            Err(err) => rejected = rejected.or(Some(err)),
+        }
+    }
+    rejected.map_or(Ok(None), Err)
 }
 
-fn print_err(output: &mut String, err: &WrapError) {
-    output.push_str(err.description());
-    output.push_str("\r\n");
+/// Error response in JSON for the clients that accept it, else in plain text
+/// for the humans, like the users of curl.
+pub(super) fn error_response(header: &HeaderMap, err: &WrapError) -> Response {
+    match accepts_json(header) {
+        true => json_error_response(err),
+        false => text_error_response(err),
+    }
+}
 
-    if let Some(ref b) = err.source_error {
-        if let Some(err) = b.downcast_ref::<WrapError>() {
-            print_err(output, err);
+/// Check if the `Accept` header prefers JSON to plain text. The wildcards
+/// do not count.
+fn accepts_json(header: &HeaderMap) -> bool {
+    let (mut json, mut text) = (0.0, 0.0);
+    let ranges = header
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','));
+    for range in ranges {
+        let mut params = range.split(';');
+        let mime = params.next().unwrap_or_default().trim();
+        let quality = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if mime.eq_ignore_ascii_case("application/json") || mime.ends_with("+json") {
+            json = quality.max(json);
+        } else if mime.eq_ignore_ascii_case("text/plain") {
+            text = quality.max(text);
         }
     }
+    json > 0.0 && json >= text
+}
+
+/// Plain text response with the status and the description of the errors.
+fn text_error_response(err: &WrapError) -> Response {
+    let status = err.status_http.unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let mut output = format!("{status}\r\n");
+    for description in descriptions(err) {
+        output.push_str(description);
+        output.push_str("\r\n");
+    }
+    for field in &err.fields {
+        output.push_str(&format!("{}: {}\r\n", field.field, field.message));
+    }
+    (status, [(CONTENT_TYPE, bmime::TEXT)], output).into_response()
+}
+
+/// An error, in JSON.
+#[derive(Debug, Serialize)]
+struct ErrorBody<'a> {
+    status: u16,
+    /// A stable identifier of the error.
+    code: String,
+    message: &'static str,
+    /// The invalid fields, then the descriptions of the causes.
+    details: Vec<ErrorDetail<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorDetail<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    field: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
+    message: &'a str,
+}
+
+/// JSON response with the status, the code and the details of the error.
+fn json_error_response(err: &WrapError) -> Response {
+    let status = err.status_http.unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let fields = err.fields.iter().map(|field| ErrorDetail {
+        field: Some(&field.field),
+        code: Some(field.code),
+        message: &field.message,
+    });
+    let causes = descriptions(err)
+        .into_iter()
+        .skip(1)
+        .map(|message| ErrorDetail {
+            field: None,
+            code: None,
+            message,
+        });
+    let body = ErrorBody {
+        status: status.as_u16(),
+        code: err.code(),
+        message: err.description(),
+        details: fields.chain(causes).collect(),
+    };
+    match encode_json(&body) {
+        Ok(body) => (status, [(CONTENT_TYPE, bmime::JSON)], body).into_response(),
+        Err(err) => text_error_response(&err),
+    }
+}
+
+/// The description of the error, then of the wrapped errors.
+fn descriptions(err: &WrapError) -> Vec<&'static str> {
+    let mut descriptions = vec![err.description()];
+    let mut err = err;
+    while let Some(source) = err
+        .source_error
+        .as_ref()
+        .and_then(|source| source.downcast_ref::<WrapError>())
+    {
+        descriptions.push(source.description());
+        err = source;
+    }
+    descriptions
 }
 
 pub async fn api_data_call<S, F, T, U>(
@@ -117,8 +329,10 @@ where
     let dto: T = if T::IS_EMPTY {
         T::default()
     } else {
-        serde_json::from_slice(&data).map_err(|err| {
-            WrapError::http(StatusCode::BAD_REQUEST, "Decoding request JSON body fail").add_err(err)
+        serde_json::from_slice(data).map_err(|err| {
+            WrapError::http(StatusCode::BAD_REQUEST, "Decoding request JSON body fail")
+                .with_code("invalid_json")
+                .add_err(err)
         })?
     };
 
@@ -127,13 +341,196 @@ where
 
     let response = service(state, DataRequest { user, dto }).await?;
 
-    let output = serde_json::to_vec(&response.dto).map_err(|err| {
+    let output = encode_json(&response.dto)?;
+
+    Ok((response.user, output))
+}
+
+/* OPERATION REGISTRY */
+
+/// The future of an operation: the user to send back and the JSON output.
+pub type OperationFuture<'a> =
+    std::pin::Pin<Box<dyn Future<Output = Result<(Option<UserToken>, Vec<u8>)>> + Send + 'a>>;
+
+/// Add the schema of a type in the generator, and get a reference to it.
+pub type SchemaFn = fn(&mut SchemaGenerator) -> Schema;
+
+/// An operation of the JSON API, dispatched by its name.
+/// Create it with [`api_operation!`](crate::api_operation).
+pub struct Operation<S> {
+    pub name: &'static str,
+    /// The minimal global level of the user.
+    pub level: UserLevel,
+    /// The schema of the input DTO, none when the input is empty.
+    pub input: Option<SchemaFn>,
+    pub output: SchemaFn,
+    pub call: for<'a> fn(&'a S, UserToken, &'a [u8]) -> OperationFuture<'a>,
+}
+
+impl<S> Operation<S> {
+    /// The schemas of the input and the output of the handler.
+    pub const fn schemas<F, T, U>(_: &F) -> (Option<SchemaFn>, SchemaFn)
+    where
+        F: AsyncFn(&S, DataRequest<T>) -> DataResponseResult<U>,
+        T: DTO,
+        U: Serialize + JsonSchema,
+    {
+        let input: Option<SchemaFn> = match T::IS_EMPTY {
+            true => None,
+            false => Some(SchemaGenerator::subschema_for::<T>),
+        };
+        (input, SchemaGenerator::subschema_for::<U>)
+    }
+}
+
+/// Declare an operation of the JSON API with its name, the minimal
+/// [`UserLevel`] and the handler.
+#[macro_export]
+macro_rules! api_operation {
+    ($name:literal, $level:ident, $handler:path) => {{
+        let (input, output) = $crate::io_http::Operation::schemas(&$handler);
+        $crate::io_http::Operation {
+            name: $name,
+            level: $crate::UserLevel::$level,
+            input,
+            output,
+            call: |state, user, data| {
+                Box::pin($crate::io_http::api_data_call(state, user, data, $handler))
+            },
+        }
+    }};
+}
+
+/// The list of the operations, for the clients.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Catalog {
+    pub operations: Vec<CatalogEntry>,
+    /// The schemas of the DTO, referenced by the operations.
+    #[serde(rename = "$defs")]
+    pub definitions: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct CatalogEntry {
+    pub name: &'static str,
+    pub level: UserLevel,
+    pub input: Option<serde_json::Value>,
+    pub output: serde_json::Value,
+}
+
+/// Describe the operations, with a JSON Schema of each DTO.
+pub fn catalog<S>(operations: &[Operation<S>]) -> Catalog {
+    let mut generator = SchemaGenerator::default();
+    let operations = operations
+        .iter()
+        .map(|operation| CatalogEntry {
+            name: operation.name,
+            level: operation.level,
+            input: operation
+                .input
+                .map(|input| input(&mut generator).to_value()),
+            output: (operation.output)(&mut generator).to_value(),
+        })
+        .collect();
+    Catalog {
+        operations,
+        definitions: generator.take_definitions(true),
+    }
+}
+
+/// Encode a response body in JSON.
+pub fn encode_json<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+    serde_json::to_vec(value).map_err(|err| {
         WrapError::http(
             StatusCode::INTERNAL_SERVER_ERROR,
             "Encoding body JSON response fail",
         )
         .add_err(err)
-    })?;
+    })
+}
 
-    Ok((response.user, output))
+#[test]
+fn sliding_session() {
+    let server = crate::app_driver::test_state("sliding_session");
+    let now = std::time::UNIX_EPOCH.elapsed().unwrap().as_secs();
+    let user = UserToken::DEV_EDITOR;
+    assert_eq!((user.clone(), None), refresh(&server, user.clone(), now));
+
+    let old = now - server.token_lifetime() / 2 - 1;
+    assert_eq!(
+        (user.clone(), Some(user.clone())),
+        refresh(&server, user, old)
+    );
+
+    let key = &server.user_token_keys().current;
+    let token = usertoken::encode_user_token(&UserToken::DEV_EDITOR, key, now);
+    let cookies = format!("theme=dark; user={token}");
+    assert_eq!(
+        Some((UserToken::DEV_EDITOR, now)),
+        parse_cookie(&server, cookies.as_bytes()).unwrap()
+    );
+    assert_eq!(None, parse_cookie(&server, b"theme=dark").unwrap());
+
+    // A rejected token is reported, and not taken as an anonymous user.
+    let expired = now - server.token_lifetime() - 1;
+    let token = usertoken::encode_user_token(&UserToken::DEV_EDITOR, key, expired);
+    let err = parse_cookie(&server, format!("user={token}").as_bytes()).unwrap_err();
+    assert_eq!(Some(StatusCode::UNAUTHORIZED), err.status_http);
+    assert_eq!("token_expired", err.code());
+    let err = parse_cookie(&server, b"user=U1.AAAA").unwrap_err();
+    assert_eq!("token_invalid", err.code());
+}
+
+#[tokio::test]
+async fn error_formats() {
+    let err = WrapError::http(StatusCode::BAD_REQUEST, "Some values are invalid")
+        .with_code("invalid_values")
+        .add_field("title", "required", "The value is empty")
+        .add_err(WrapError::new("cause"));
+    let response = |accept: &str| {
+        let mut header = HeaderMap::new();
+        if !accept.is_empty() {
+            header.insert(ACCEPT, HeaderValue::from_str(accept).unwrap());
+        }
+        error_response(&header, &err)
+    };
+    let body = async |response: Response| {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX);
+        String::from_utf8(body.await.unwrap().to_vec()).unwrap()
+    };
+
+    for accept in ["", "*/*", "text/plain, application/json;q=0.5"] {
+        let text = response(accept);
+        assert_eq!(bmime::TEXT, text.headers()[CONTENT_TYPE]);
+        assert_eq!(
+            "400 Bad Request\r\nSome values are invalid\r\ncause\r\ntitle: The value is empty\r\n",
+            body(text).await
+        );
+    }
+    for accept in [
+        "application/json",
+        "text/plain;q=0.5, application/problem+json",
+    ] {
+        let json = response(accept);
+        assert_eq!(StatusCode::BAD_REQUEST, json.status());
+        assert_eq!(bmime::JSON, json.headers()[CONTENT_TYPE]);
+        let json: serde_json::Value = serde_json::from_str(&body(json).await).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "status": 400,
+                "code": "invalid_values",
+                "message": "Some values are invalid",
+                "details": [
+                    {"field": "title", "code": "required", "message": "The value is empty"},
+                    {"message": "cause"},
+                ],
+            }),
+            json
+        );
+    }
+
+    // Without code, the code of the status.
+    let err = WrapError::http(StatusCode::PAYLOAD_TOO_LARGE, "Too large");
+    assert_eq!("payload_too_large", err.code());
+    assert_eq!("internal_server_error", WrapError::new("Fail").code());
 }
@@ -0,0 +1,719 @@
+//! WebDAV (RFC 4918) access to the files, under [`DAV_ROOT`].
+//!
+//! The properties are read only, and only the exclusive write locks are
+//! supported. The locks are kept in memory, and lost at restart.
+//!
+//! The user is authenticated with the `user=` cookie, or with HTTP Basic
+//! where the password is the user password or a user token.
+//!
+//! A `GET` on a folder returns its HTML listing, for the browsers.
+
+use super::HTTPState;
+use super::cache::http_date;
+use super::serve_api_data::{error_response, refresh, request_user};
+use super::serve_files::{file_response, percent_encode};
+use super::usertoken;
+use crate::*;
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{
+        HeaderMap, HeaderName, HeaderValue, Method, StatusCode,
+        header::{ALLOW, AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE},
+    },
+    response::{IntoResponse, Response},
+};
+use crypto::digest::Digest;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The path prefix of the WebDAV subsystem.
+pub const DAV_ROOT: &str = "/dav";
+
+const ALLOWED: &str = "OPTIONS, PROPFIND, GET, HEAD, PUT, MKCOL, MOVE, COPY, DELETE, LOCK, UNLOCK";
+
+/// A file or a folder seen by WebDAV.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DavEntry {
+    /// Empty for the root.
+    pub name: String,
+    pub is_folder: bool,
+    /// Size in bytes.
+    pub size: u64,
+    pub mime: String,
+    /// Last modification, in seconds since Epoch.
+    pub modified: u64,
+    /// Change when the content change.
+    pub etag: String,
+}
+
+/* LOCKS */
+
+/// An exclusive write lock.
+#[derive(Debug, Clone)]
+struct DavLock {
+    token: String,
+    path: Vec<String>,
+    /// The lock cover all the descendants.
+    infinite: bool,
+    user: u32,
+    /// Description of the owner, given by the client.
+    owner: String,
+    timeout: u64,
+    /// Expiration time, in seconds since Epoch.
+    expire: u64,
+}
+
+impl DavLock {
+    /// The lock cover the path.
+    fn cover(&self, path: &[String]) -> bool {
+        path.starts_with(&self.path) && (self.infinite || path.len() == self.path.len())
+    }
+}
+
+/// The active WebDAV locks, indexed by token.
+#[derive(Debug, Default)]
+pub struct DavLocks {
+    locks: std::sync::Mutex<BTreeMap<String, DavLock>>,
+    /// Counter to get unique tokens.
+    counter: AtomicU64,
+}
+
+impl DavLocks {
+    /// Default and maximum lock duration, in seconds.
+    const TIMEOUT: u64 = 3600;
+    const TIMEOUT_MAX: u64 = 24 * 3600;
+
+    fn get(&self) -> Result<std::sync::MutexGuard<'_, BTreeMap<String, DavLock>>> {
+        let mut locks = self.locks.lock().map_err(|_| {
+            WrapError::http(StatusCode::INTERNAL_SERVER_ERROR, "internal sync fail")
+        })?;
+        let now = now();
+        locks.retain(|_, lock| now < lock.expire);
+        Ok(locks)
+    }
+
+    /// Check that the path can be modified by the user with the tokens.
+    /// With `descendants`, the locks inside the path are also checked.
+    fn check(
+        &self,
+        user: &UserToken,
+        path: &[String],
+        tokens: &[&str],
+        descendants: bool,
+    ) -> Result<()> {
+        let locks = self.get()?;
+        let locked = locks.values().any(|lock| {
+            (lock.cover(path) || (descendants && lock.path.starts_with(path)))
+                && !(lock.user == user.id && tokens.contains(&lock.token.as_str()))
+        });
+        match locked {
+            true => Err(err_locked()),
+            false => Ok(()),
+        }
+    }
+
+    fn lock(
+        &self,
+        user: &UserToken,
+        path: &[String],
+        infinite: bool,
+        owner: String,
+        timeout: u64,
+    ) -> Result<DavLock> {
+        let mut locks = self.get()?;
+        if locks
+            .values()
+            .any(|lock| lock.cover(path) || (infinite && lock.path.starts_with(path)))
+        {
+            return Err(err_locked());
+        }
+
+        let mut hasher = crypto::sha2::Sha256::new();
+        hasher.input_str(&format!(
+            "{:?}{}{}",
+            std::time::UNIX_EPOCH.elapsed(),
+            self.counter.fetch_add(1, Ordering::Relaxed),
+            user.id,
+        ));
+        let hash = hasher.result_str();
+        let lock = DavLock {
+            token: format!(
+                "opaquelocktoken:{}-{}-{}-{}-{}",
+                &hash[..8],
+                &hash[8..12],
+                &hash[12..16],
+                &hash[16..20],
+                &hash[20..32]
+            ),
+            path: path.to_vec(),
+            infinite,
+            user: user.id,
+            owner,
+            timeout,
+            expire: now() + timeout,
+        };
+        locks.insert(lock.token.clone(), lock.clone());
+        Ok(lock)
+    }
+
+    /// Extend the duration of a lock of the user.
+    fn refresh(
+        &self,
+        user: &UserToken,
+        path: &[String],
+        tokens: &[&str],
+        timeout: u64,
+    ) -> Result<DavLock> {
+        let mut locks = self.get()?;
+        let lock = locks
+            .values_mut()
+            .find(|lock| {
+                lock.user == user.id && lock.cover(path) && tokens.contains(&lock.token.as_str())
+            })
+            .ok_or_else(|| {
+                WrapError::http(StatusCode::PRECONDITION_FAILED, "No lock to refresh")
+            })?;
+        lock.timeout = timeout;
+        lock.expire = now() + timeout;
+        Ok(lock.clone())
+    }
+
+    fn unlock(&self, user: &UserToken, path: &[String], token: &str) -> Result<()> {
+        let mut locks = self.get()?;
+        match locks.get(token) {
+            Some(lock) if lock.user == user.id && lock.cover(path) => {
+                locks.remove(token);
+                Ok(())
+            }
+            _ => Err(WrapError::http(
+                StatusCode::CONFLICT,
+                "The lock token does not match the resource",
+            )),
+        }
+    }
+
+    /// Remove the locks of a removed path and its descendants.
+    fn remove(&self, path: &[String]) -> Result<()> {
+        self.get()?.retain(|_, lock| !lock.path.starts_with(path));
+        Ok(())
+    }
+
+    /// Get the locks that cover the path.
+    fn covering(&self, path: &[String]) -> Result<Vec<DavLock>> {
+        Ok(self
+            .get()?
+            .values()
+            .filter(|lock| lock.cover(path))
+            .cloned()
+            .collect())
+    }
+}
+
+/* HANDLER */
+
+/// Handle all the WebDAV methods.
+pub async fn serve_dav<S: HTTPState>(State(state): State<S>, request: Request) -> Response {
+    let header = request.headers().clone();
+    let user = match dav_user(&state, &header).await {
+        Ok(user) => user,
+        Err(err) => return unauthorized(&header, &err),
+    };
+    let anonymous = user.id == 0;
+    let result = match dav_path(request.uri().path()) {
+        Ok(path) => dispatch(&state, user, path, request).await,
+        Err(err) => Err(err),
+    };
+
+    match result {
+        Ok(response) => response,
+        Err(err) if anonymous && err.status_http == Some(StatusCode::FORBIDDEN) => {
+            unauthorized(&header, &err)
+        }
+        Err(err) if err.status_http == Some(StatusCode::METHOD_NOT_ALLOWED) => {
+            let mut response = error_response(&header, &err);
+            response
+                .headers_mut()
+                .insert(ALLOW, HeaderValue::from_static(ALLOWED));
+            response
+        }
+        Err(err) => error_response(&header, &err),
+    }
+}
+
+/// Ask the client for the credentials.
+fn unauthorized(header: &HeaderMap, err: &WrapError) -> Response {
+    let mut response = error_response(header, err);
+    *response.status_mut() = StatusCode::UNAUTHORIZED;
+    response.headers_mut().insert(
+        WWW_AUTHENTICATE,
+        HeaderValue::from_static("Basic realm=\"brume\", charset=\"UTF-8\""),
+    );
+    response
+}
+
+async fn dispatch<S: HTTPState>(
+    state: &S,
+    user: UserToken,
+    path: Vec<String>,
+    request: Request,
+) -> Result<Response> {
+    let header = request.headers().clone();
+    let tokens = if_tokens(&header);
+    let locks = state.dav_locks();
+
+    match request.method().as_str() {
+        "OPTIONS" => Ok((
+            [
+                (HeaderName::from_static("dav"), "1, 2"),
+                (ALLOW, ALLOWED),
+                (HeaderName::from_static("ms-author-via"), "DAV"),
+            ],
+            "",
+        )
+            .into_response()),
+        "PROPFIND" => {
+            let children = match header_str(&header, "depth") {
+                Some("0") => false,
+                Some("1") => true,
+                _ => {
+                    return Err(WrapError::http(
+                        StatusCode::FORBIDDEN,
+                        "Only the depth 0 and 1 are supported",
+                    ));
+                }
+            };
+            let entries = state.dav_find(user, &path, children).await?;
+            let mut output = String::from(XML_HEAD);
+            output.push_str("<D:multistatus xmlns:D=\"DAV:\">");
+            for (i, entry) in entries.iter().enumerate() {
+                let mut entry_path = path.clone();
+                if i > 0 {
+                    entry_path.push(entry.name.clone());
+                }
+                write_response(
+                    &mut output,
+                    &entry_path,
+                    entry,
+                    &locks.covering(&entry_path)?,
+                );
+            }
+            output.push_str("</D:multistatus>");
+            Ok(xml_response(StatusCode::MULTI_STATUS, output))
+        }
+        "GET" | "HEAD" => {
+            let entries = state.dav_find(user.clone(), &path, true).await?;
+            let mut response = match entries.first() {
+                Some(first) if first.is_folder => (
+                    [(CONTENT_TYPE, bmime::HTML)],
+                    folder_page(state, &path, &entries[1..])?,
+                )
+                    .into_response(),
+                _ => file_response(state.dav_get(user, &path).await?, &header).await,
+            };
+            if request.method() == Method::HEAD {
+                *response.body_mut() = Body::empty();
+            }
+            Ok(response)
+        }
+        "PUT" => {
+            locks.check(&user, &path, &tokens, false)?;
+            let mut body = request.into_body();
+            match state.dav_put(user, &path, &mut body).await? {
+                true => Ok(StatusCode::CREATED.into_response()),
+                false => Ok(StatusCode::NO_CONTENT.into_response()),
+            }
+        }
+        "MKCOL" => {
+            locks.check(&user, &path, &tokens, false)?;
+            state.dav_mkcol(user, &path).await?;
+            Ok(StatusCode::CREATED.into_response())
+        }
+        "DELETE" => {
+            locks.check(&user, &path, &tokens, true)?;
+            state.dav_delete(user, &path).await?;
+            locks.remove(&path)?;
+            Ok(StatusCode::NO_CONTENT.into_response())
+        }
+        method @ ("MOVE" | "COPY") => {
+            let copy = method == "COPY";
+            let destination = header_str(&header, "destination")
+                .and_then(|url| match url.split_once("://") {
+                    Some((_, url)) => url.find('/').map(|i| &url[i..]),
+                    None => Some(url),
+                })
+                .ok_or_else(|| {
+                    WrapError::http(StatusCode::BAD_REQUEST, "Invalid destination header")
+                })?;
+            let destination = dav_path(destination)?;
+            let overwrite = header_str(&header, "overwrite") != Some("F");
+
+            if !copy {
+                locks.check(&user, &path, &tokens, true)?;
+            }
+            locks.check(&user, &destination, &tokens, true)?;
+            let created = state
+                .dav_transfer(user, &path, &destination, copy, overwrite)
+                .await?;
+            if !copy {
+                locks.remove(&path)?;
+            }
+            match created {
+                true => Ok(StatusCode::CREATED.into_response()),
+                false => Ok(StatusCode::NO_CONTENT.into_response()),
+            }
+        }
+        "LOCK" => lock(state, user, path, request, &tokens).await,
+        "UNLOCK" => {
+            let token = header_str(&header, "lock-token")
+                .map(|token| token.trim_matches(['<', '>', ' ']))
+                .ok_or_else(|| {
+                    WrapError::http(StatusCode::BAD_REQUEST, "Missing Lock-Token header")
+                })?;
+            locks.unlock(&user, &path, token)?;
+            Ok(StatusCode::NO_CONTENT.into_response())
+        }
+        _ => Err(WrapError::http(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "Method not supported by WebDAV",
+        )),
+    }
+}
+
+/// Create or refresh a lock.
+/// A lock on a missing path creates an empty file.
+async fn lock<S: HTTPState>(
+    state: &S,
+    user: UserToken,
+    path: Vec<String>,
+    request: Request,
+    tokens: &[&str],
+) -> Result<Response> {
+    let locks = state.dav_locks();
+    let timeout = match header_str(request.headers(), "timeout") {
+        Some(timeout) => timeout
+            .split(',')
+            .find_map(|t| match t.trim() {
+                "Infinite" => Some(DavLocks::TIMEOUT_MAX),
+                t => t.strip_prefix("Second-")?.parse().ok(),
+            })
+            .unwrap_or(DavLocks::TIMEOUT)
+            .min(DavLocks::TIMEOUT_MAX),
+        None => DavLocks::TIMEOUT,
+    };
+    let infinite = header_str(request.headers(), "depth") != Some("0");
+    let body = axum::body::to_bytes(request.into_body(), 1 << 16)
+        .await
+        .map_err(|err| {
+            WrapError::http(StatusCode::BAD_REQUEST, "Reading request body fail").add_err(err)
+        })?;
+    let body = String::from_utf8_lossy(&body);
+
+    let (status, lock) = if body.trim().is_empty() {
+        (
+            StatusCode::OK,
+            locks.refresh(&user, &path, tokens, timeout)?,
+        )
+    } else {
+        if xml_element(&body, "lockscope").is_some_and(|scope| scope.contains("shared")) {
+            return Err(WrapError::http(
+                StatusCode::PRECONDITION_FAILED,
+                "Only the exclusive locks are supported",
+            ));
+        }
+        let owner = xml_element(&body, "owner").map_or(String::new(), xml_text);
+        let exist = match state.dav_find(user.clone(), &path, false).await {
+            Ok(_) => true,
+            Err(err) if err.status_http == Some(StatusCode::NOT_FOUND) => false,
+            Err(err) => return Err(err),
+        };
+        let lock = locks.lock(&user, &path, infinite, owner, timeout)?;
+        if exist {
+            (StatusCode::OK, lock)
+        } else {
+            let mut body = Body::empty();
+            if let Err(err) = state.dav_put(user, &path, &mut body).await {
+                locks.remove(&path)?;
+                return Err(err);
+            }
+            (StatusCode::CREATED, lock)
+        }
+    };
+
+    let mut output = String::from(XML_HEAD);
+    output.push_str("<D:prop xmlns:D=\"DAV:\"><D:lockdiscovery>");
+    write_active_lock(&mut output, &lock);
+    output.push_str("</D:lockdiscovery></D:prop>");
+    let mut response = xml_response(status, output);
+    if let Ok(value) = HeaderValue::from_str(&format!("<{}>", lock.token)) {
+        response
+            .headers_mut()
+            .insert(HeaderName::from_static("lock-token"), value);
+    }
+    Ok(response)
+}
+
+/// Get the user from HTTP Basic, else from the cookie.
+/// The Basic password is a user token, or the password of the login.
+/// A rejected token fails with `401` and the code of the token error.
+async fn dav_user<S: HTTPState>(state: &S, header: &HeaderMap) -> Result<UserToken> {
+    use base64::Engine;
+    let credentials = header_str(header, AUTHORIZATION.as_str())
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|value| {
+            base64::engine::general_purpose::STANDARD
+                .decode(value.trim())
+                .ok()
+        })
+        .and_then(|credentials| String::from_utf8(credentials).ok());
+    let Some((login, password)) = credentials
+        .as_ref()
+        .and_then(|credentials| credentials.split_once(':'))
+    else {
+        return request_user(state, header);
+    };
+
+    let lifetime = state.token_lifetime();
+    let rejected =
+        match usertoken::decode(password, state.user_token_keys(), now(), lifetime, |id| {
+            state.sessions_revoked(id)
+        }) {
+            Ok((user, created)) => return Ok(refresh(state, user, created).0),
+            Err(err) => err,
+        };
+    match state.login(login, password.to_string()).await {
+        Ok(user) => Ok(user),
+        // A token as password, rejected.
+        Err(_) if password.starts_with(usertoken::TOKEN_PREFIX) => Err(rejected),
+        Err(_) => Ok(UserToken::default()),
+    }
+}
+
+/// Get the decoded segments of a WebDAV path.
+fn dav_path(uri: &str) -> Result<Vec<String>> {
+    let err_path = || WrapError::http(StatusCode::BAD_REQUEST, "Invalid WebDAV path");
+    let path = uri.strip_prefix(DAV_ROOT).ok_or_else(err_path)?;
+    if !path.is_empty() && !path.starts_with('/') {
+        return Err(err_path());
+    }
+    path.split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| match percent_decode(segment) {
+            Some(segment) if segment != "." && segment != ".." => Ok(segment),
+            _ => Err(err_path()),
+        })
+        .collect()
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'%' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                out.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            _ => out.push(b),
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+/// Get the lock tokens of the `If` header.
+fn if_tokens(header: &HeaderMap) -> Vec<&str> {
+    header_str(header, "if")
+        .map(|value| {
+            value
+                .split(['<', '>'])
+                .filter(|token| token.starts_with("opaquelocktoken:"))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn header_str<'a>(header: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    header.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn err_locked() -> WrapError {
+    WrapError::http(StatusCode::LOCKED, "The resource is locked")
+}
+
+/// The HTML listing of a folder, the subfolders first.
+fn folder_page<S: HTTPState>(state: &S, path: &[String], children: &[DavEntry]) -> Result<String> {
+    let mut children: Vec<&DavEntry> = children.iter().collect();
+    children.sort_by(|a, b| (!a.is_folder, &a.name).cmp(&(!b.is_folder, &b.name)));
+    let entries: Vec<_> = children
+        .into_iter()
+        .map(|entry| {
+            let entry_path = [path, std::slice::from_ref(&entry.name)].concat();
+            minijinja::context! {
+                href => href(&entry_path, entry.is_folder),
+                name => entry.name,
+                is_folder => entry.is_folder,
+                size => entry.size,
+                mime => entry.mime,
+                modified => entry.modified,
+            }
+        })
+        .collect();
+    let parent = path.split_last().map(|(_, parent)| href(parent, true));
+    state.theme().render(
+        "folder.html",
+        minijinja::context! {
+            title => format!("/{}", path.join("/")),
+            description => "Folder",
+            parent,
+            entries,
+        },
+    )
+}
+
+/* XML */
+
+const XML_HEAD: &str = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n";
+
+fn xml_response(status: StatusCode, output: String) -> Response {
+    (status, [(CONTENT_TYPE, bmime::XML)], output).into_response()
+}
+
+fn write_response(output: &mut String, path: &[String], entry: &DavEntry, locks: &[DavLock]) {
+    output.push_str("<D:response><D:href>");
+    output.push_str(&href(path, entry.is_folder));
+    output.push_str("</D:href><D:propstat><D:prop>");
+    let _ = write!(
+        output,
+        "<D:displayname>{}</D:displayname>",
+        xml_escape(&entry.name)
+    );
+    if entry.is_folder {
+        output.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+    } else {
+        let _ = write!(
+            output,
+            "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength>\
+            <D:getcontenttype>{}</D:getcontenttype>",
+            entry.size,
+            xml_escape(&entry.mime)
+        );
+    }
+    let _ = write!(
+        output,
+        "<D:getlastmodified>{}</D:getlastmodified><D:getetag>\"{}\"</D:getetag>",
+        http_date(entry.modified),
+        xml_escape(&entry.etag)
+    );
+    output.push_str(
+        "<D:supportedlock><D:lockentry><D:lockscope><D:exclusive/></D:lockscope>\
+        <D:locktype><D:write/></D:locktype></D:lockentry></D:supportedlock>",
+    );
+    output.push_str("<D:lockdiscovery>");
+    for lock in locks {
+        write_active_lock(output, lock);
+    }
+    output.push_str("</D:lockdiscovery>");
+    output.push_str("</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>");
+}
+
+fn write_active_lock(output: &mut String, lock: &DavLock) {
+    let _ = write!(
+        output,
+        "<D:activelock><D:locktype><D:write/></D:locktype>\
+        <D:lockscope><D:exclusive/></D:lockscope>\
+        <D:depth>{}</D:depth><D:owner>{}</D:owner><D:timeout>Second-{}</D:timeout>\
+        <D:locktoken><D:href>{}</D:href></D:locktoken>\
+        <D:lockroot><D:href>{}</D:href></D:lockroot></D:activelock>",
+        match lock.infinite {
+            true => "infinity",
+            false => "0",
+        },
+        xml_escape(&lock.owner),
+        lock.timeout,
+        lock.token,
+        href(&lock.path, false),
+    );
+}
+
+/// The encoded absolute path, a folder ends with a slash.
+fn href(path: &[String], is_folder: bool) -> String {
+    let mut href = String::from(DAV_ROOT);
+    for segment in path {
+        href.push('/');
+        href.push_str(&percent_encode(segment));
+    }
+    if is_folder || path.is_empty() {
+        href.push('/');
+    }
+    href
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Get the content of the first element with the local name, whatever its
+/// namespace prefix.
+fn xml_element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let local = |tag: &'a str| tag.rsplit(':').next().unwrap_or(tag);
+    let mut start = None;
+    let mut rest = xml;
+    while let Some(i) = rest.find('<') {
+        let tag_start = xml.len() - rest.len() + i;
+        let tag = &rest[i + 1..];
+        let end = tag.find('>')?;
+        let (closing, tag_name) = match tag.strip_prefix('/') {
+            Some(tag) => (true, tag),
+            None => (false, tag),
+        };
+        let tag_name = tag_name[..tag_name.find([' ', '\t', '\r', '\n', '/', '>'])?].trim();
+        if local(tag_name) == name {
+            match (closing, start) {
+                (false, None) if !tag[..end].ends_with('/') => {
+                    start = Some(tag_start + end + 2);
+                }
+                (false, None) => return Some(""),
+                (true, Some(start)) => return Some(&xml[start..tag_start]),
+                _ => (),
+            }
+        }
+        rest = &tag[end + 1..];
+    }
+    None
+}
+
+/// Remove the tags of a XML fragment.
+fn xml_text(xml: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in xml.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => (),
+        }
+    }
+    text.trim().to_string()
+}
+
+#[test]
+fn test_dav_helpers() {
+    assert_eq!(
+        vec!["a b".to_string(), "é".to_string()],
+        dav_path("/dav/a%20b/%C3%A9/").unwrap()
+    );
+    assert!(dav_path("/dav/..").is_err());
+    assert!(dav_path("/davx").is_err());
+    assert_eq!("/dav/a%20b/", href(&["a b".to_string()], true));
+
+    let body = "<?xml version=\"1.0\"?><a:lockinfo xmlns:a=\"DAV:\">\
+        <a:lockscope><a:exclusive/></a:lockscope>\
+        <a:owner><a:href>mailto:me</a:href></a:owner></a:lockinfo>";
+    assert_eq!(Some("<a:exclusive/>"), xml_element(body, "lockscope"));
+    assert_eq!("mailto:me", xml_text(xml_element(body, "owner").unwrap()));
+}
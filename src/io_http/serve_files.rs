@@ -0,0 +1,321 @@
+use super::HTTPState;
+use super::cache::{self, Conditional};
+use super::serve_api_data::{error_response, request_user};
+use crate::*;
+use axum::{
+    body::{Body, Bytes},
+    extract::{FromRequest, Multipart, Path, Query, Request, State},
+    http::{
+        HeaderMap, HeaderValue, StatusCode,
+        header::{
+            CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_SECURITY_POLICY, CONTENT_TYPE,
+            X_CONTENT_TYPE_OPTIONS,
+        },
+    },
+    response::{IntoResponse, Response},
+};
+use http_body_util::BodyExt;
+use serde::Deserialize;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+/// A source of bytes, received by chunks.
+#[async_trait::async_trait]
+pub trait ByteStream: Send {
+    /// Get the next chunk, `None` at the end of the stream.
+    async fn chunk(&mut self) -> Result<Option<Bytes>>;
+}
+
+#[async_trait::async_trait]
+impl ByteStream for Body {
+    async fn chunk(&mut self) -> Result<Option<Bytes>> {
+        while let Some(frame) = self.frame().await {
+            let frame = frame.map_err(|err| {
+                WrapError::http(StatusCode::BAD_REQUEST, "Reading request body fail").add_err(err)
+            })?;
+            if let Ok(data) = frame.into_data() {
+                return Ok(Some(data));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[async_trait::async_trait]
+impl ByteStream for axum::extract::multipart::Field<'_> {
+    async fn chunk(&mut self) -> Result<Option<Bytes>> {
+        axum::extract::multipart::Field::chunk(self)
+            .await
+            .map_err(err_multipart)
+    }
+}
+
+/// Where an uploaded file is stored.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileTarget {
+    /// Create a new file in the folder `parent`.
+    /// The group is needed for a root file.
+    New {
+        parent: u64,
+        group: Option<u32>,
+        name: String,
+    },
+    /// Replace the content of an existing file.
+    Replace(u64),
+}
+
+/// A stored file, ready to be sent.
+#[derive(Debug)]
+pub struct FileOutput {
+    pub name: String,
+    pub mime: String,
+    pub size: u64,
+    /// Strong validator of the content, quoted.
+    pub etag: String,
+    /// Last modification, in seconds since Epoch.
+    pub modified: u64,
+    pub file: tokio::fs::File,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UploadQuery {
+    #[serde(default)]
+    parent: u64,
+    group: Option<u32>,
+    name: Option<String>,
+}
+
+/// Upload new files, from a raw body with the name in the query,
+/// or from a `multipart/form-data` body with one or many files.
+pub async fn upload<S: HTTPState>(
+    State(state): State<S>,
+    Query(query): Query<UploadQuery>,
+    request: Request,
+) -> Response {
+    let header = request.headers().clone();
+    let user = match request_user(&state, &header) {
+        Ok(user) => user,
+        Err(err) => return error_response(&header, &err),
+    };
+    let is_multipart = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("multipart/form-data"));
+
+    let result = if is_multipart {
+        upload_multipart(&state, user, query.parent, query.group, request).await
+    } else {
+        match query.name {
+            Some(name) => {
+                let target = FileTarget::New {
+                    parent: query.parent,
+                    group: query.group,
+                    name,
+                };
+                let mut body = request.into_body();
+                state.file_put(user, target, &mut body).await
+            }
+            None => Err(WrapError::http(
+                StatusCode::BAD_REQUEST,
+                "The query need a file name",
+            )),
+        }
+    };
+
+    json_response(&header, result)
+}
+
+async fn upload_multipart<S: HTTPState>(
+    state: &S,
+    user: UserToken,
+    parent: u64,
+    group: Option<u32>,
+    request: Request,
+) -> Result<Vec<u8>> {
+    let mut multipart = Multipart::from_request(request, &()).await.map_err(|err| {
+        WrapError::http(StatusCode::BAD_REQUEST, "Invalid multipart body").add_err(err)
+    })?;
+
+    let mut output = b"[".to_vec();
+    while let Some(mut field) = multipart.next_field().await.map_err(err_multipart)? {
+        let Some(name) = field.file_name().map(String::from) else {
+            continue;
+        };
+        if output.len() > 1 {
+            output.push(b',');
+        }
+        let target = FileTarget::New {
+            parent,
+            group,
+            name,
+        };
+        output.extend(state.file_put(user.clone(), target, &mut field).await?);
+    }
+    output.push(b']');
+
+    Ok(output)
+}
+
+/// Replace the content of a file with the raw body.
+pub async fn replace<S: HTTPState>(
+    State(state): State<S>,
+    Path(id): Path<u64>,
+    request: Request,
+) -> Response {
+    let header = request.headers().clone();
+    let user = match request_user(&state, &header) {
+        Ok(user) => user,
+        Err(err) => return error_response(&header, &err),
+    };
+    let mut body = request.into_body();
+    json_response(
+        &header,
+        state
+            .file_put(user, FileTarget::Replace(id), &mut body)
+            .await,
+    )
+}
+
+/// Send the file content.
+pub async fn download<S: HTTPState>(
+    State(state): State<S>,
+    Path(id): Path<u64>,
+    header: axum::http::HeaderMap,
+) -> Response {
+    let user = match request_user(&state, &header) {
+        Ok(user) => user,
+        Err(err) => return error_response(&header, &err),
+    };
+    match state.file_get(user, id).await {
+        Ok(output) => file_response(output, &header).await,
+        Err(err) => error_response(&header, &err),
+    }
+}
+
+/// Stream the file content, with its type, size and name, or only the
+/// requested range.
+///
+/// The files come from the users, on the origin of the application. The
+/// browser must not guess their type nor run their scripts, and the active
+/// types are only downloaded.
+pub(super) async fn file_response(mut output: FileOutput, header: &HeaderMap) -> Response {
+    let mime = HeaderValue::from_str(&output.mime)
+        .unwrap_or(HeaderValue::from_static(bmime::OCTET_STREAM));
+    let disposition = match bmime::is_active(&output.mime) {
+        true => "attachment",
+        false => "inline",
+    };
+    let disposition = format!(
+        "{disposition}; filename*=UTF-8''{}",
+        percent_encode(&output.name)
+    );
+    let validators = cache::validators(&output.etag, output.modified);
+    let (status, start, len) =
+        match Conditional::new(header, &output.etag, output.modified, output.size) {
+            Conditional::NotModified => {
+                return (StatusCode::NOT_MODIFIED, validators).into_response();
+            }
+            Conditional::Unsatisfiable => {
+                let range = cache::content_range(None, output.size);
+                return (StatusCode::RANGE_NOT_SATISFIABLE, [range]).into_response();
+            }
+            Conditional::Full => (StatusCode::OK, 0, output.size),
+            Conditional::Partial(start, end) => {
+                (StatusCode::PARTIAL_CONTENT, start, end - start + 1)
+            }
+        };
+    if start > 0
+        && let Err(err) = output.file.seek(std::io::SeekFrom::Start(start)).await
+    {
+        let err = WrapError::http(StatusCode::INTERNAL_SERVER_ERROR, "File seek fail").add_err(err);
+        return error_response(header, &err);
+    }
+    let stream = tokio_util::io::ReaderStream::new(output.file.take(len));
+    let mut response = (
+        status,
+        validators,
+        [
+            (CONTENT_TYPE, mime),
+            (CONTENT_LENGTH, HeaderValue::from(len)),
+            (
+                CONTENT_DISPOSITION,
+                HeaderValue::from_str(&disposition).unwrap(),
+            ),
+            (X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff")),
+            (CONTENT_SECURITY_POLICY, HeaderValue::from_static("sandbox")),
+        ],
+        Body::from_stream(stream),
+    )
+        .into_response();
+    if status == StatusCode::PARTIAL_CONTENT {
+        let (name, value) = cache::content_range(Some((start, start + len - 1)), output.size);
+        response.headers_mut().insert(name, value);
+    }
+    response
+}
+
+fn json_response(header: &HeaderMap, result: Result<Vec<u8>>) -> Response {
+    match result {
+        Ok(output) => (StatusCode::OK, [(CONTENT_TYPE, bmime::JSON)], output).into_response(),
+        Err(err) => error_response(header, &err),
+    }
+}
+
+fn err_multipart(err: axum::extract::multipart::MultipartError) -> WrapError {
+    WrapError::http(err.status(), "Reading multipart body fail").add_err(err)
+}
+
+/// Encode a string for a header parameter (RFC 5987).
+pub(super) fn percent_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(b as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+#[test]
+fn test_percent_encode() {
+    assert_eq!("r%C3%A9sum%C3%A9%20v2.pdf", percent_encode("résumé v2.pdf"));
+}
+
+#[tokio::test]
+async fn active_file_headers() {
+    let server = std::sync::Arc::new(crate::app_driver::test_state("active_file_headers"));
+    let get = async |name: &str| {
+        let target = FileTarget::New {
+            parent: 0,
+            group: Some(42),
+            name: name.to_string(),
+        };
+        let mut body = Body::from("<script>alert(1)</script>");
+        let file = server
+            .file_put(UserToken::DEV_EDITOR, target, &mut body)
+            .await
+            .unwrap();
+        let file: serde_json::Value = serde_json::from_slice(&file).unwrap();
+        let id = file["id"].as_u64().unwrap();
+        let output = server.file_get(UserToken::DEV_EDITOR, id).await.unwrap();
+        file_response(output, &HeaderMap::new()).await
+    };
+
+    let page = get("page.html").await;
+    assert_eq!(bmime::HTML, page.headers()[CONTENT_TYPE]);
+    assert_eq!(
+        "attachment; filename*=UTF-8''page.html",
+        page.headers()[CONTENT_DISPOSITION]
+    );
+    assert_eq!("nosniff", page.headers()[X_CONTENT_TYPE_OPTIONS]);
+    assert_eq!("sandbox", page.headers()[CONTENT_SECURITY_POLICY]);
+    let text = get("notes.txt").await;
+    assert_eq!(
+        "inline; filename*=UTF-8''notes.txt",
+        text.headers()[CONTENT_DISPOSITION]
+    );
+    assert_eq!("nosniff", text.headers()[X_CONTENT_TYPE_OPTIONS]);
+}
@@ -1,27 +1,107 @@
 use super::HTTPState;
+use super::cache::{self, PAGE_CACHE};
 use crate::*;
 use axum::{
     extract::State,
-    http::{HeaderName, StatusCode},
+    http::{HeaderMap, StatusCode, header::CONTENT_TYPE},
+    response::{IntoResponse, Response},
 };
 
 pub async fn serve_generated<S: HTTPState + Clone + Send + Sync + 'static>(
     State(state): State<S>,
     uri: axum::http::Uri,
-) -> (StatusCode, [(HeaderName, &'static str); 1], Vec<u8>) {
-    match state.cached(uri.path()) {
-        Some(arc) => {
-            let (mime, body) = arc;
-            (
-                StatusCode::OK,
-                [(axum::http::header::CONTENT_TYPE, mime)],
-                body.to_vec(),
-            )
-        }
-        None => (
-            StatusCode::NOT_FOUND,
-            [(axum::http::header::CONTENT_TYPE, bmime::HTML)],
-            S::ERROR_404.to_vec(),
-        ),
-    }
+    header: HeaderMap,
+) -> Response {
+    // A page is shown as to an anonymous user when the token is rejected.
+    let user = super::serve_api_data::request_user(&state, &header).unwrap_or_default();
+    let status = match state.cached(uri.path(), &user) {
+        Ok(Some(page)) => return cache::page_response(&header, &page, PAGE_CACHE).await,
+        Ok(None) => StatusCode::NOT_FOUND,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (
+        status,
+        [(CONTENT_TYPE, bmime::HTML)],
+        state.theme().error_page(status),
+    )
+        .into_response()
+}
+
+#[tokio::test]
+async fn home_page() {
+    let server = std::sync::Arc::new(crate::app_driver::test_state("home_page"));
+    let get = async |header: HeaderMap| {
+        let uri = axum::http::Uri::from_static("/");
+        let response = serve_generated(State(server.clone()), uri, header).await;
+        let (parts, body) = response.into_parts();
+        let body = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        (
+            parts.status,
+            parts.headers,
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    };
+    let (status, header, _) = get(HeaderMap::new()).await;
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(bmime::HTML, header[CONTENT_TYPE]);
+
+    let page = br#"{"title": "A <b>title</b>", "description": "News", "body": "Some **news**"}"#;
+    let admin = UserToken {
+        level: crate::UserLevel::Admin,
+        ..Default::default()
+    };
+    server
+        .api_json("home.set", admin.clone(), page)
+        .await
+        .unwrap();
+    let (_, changed, body) = get(HeaderMap::new()).await;
+    assert!(body.contains("<title>A &lt;b&gt;title&lt;/b&gt;</title>"));
+    assert!(body.contains("<p>Some <strong>news</strong></p>"));
+
+    // The old ETag does not match the new content.
+    let mut conditional = HeaderMap::new();
+    conditional.insert("if-none-match", header["etag"].clone());
+    assert_eq!(StatusCode::OK, get(conditional.clone()).await.0);
+    conditional.insert("if-none-match", changed["etag"].clone());
+    let (status, _, body) = get(conditional).await;
+    assert_eq!((StatusCode::NOT_MODIFIED, ""), (status, body.as_str()));
+
+    let mut range = HeaderMap::new();
+    range.insert("range", "bytes=0-14".parse().unwrap());
+    let (status, header, body) = get(range).await;
+    assert_eq!(StatusCode::PARTIAL_CONTENT, status);
+    assert_eq!("<!DOCTYPE html>", body);
+    assert!(
+        header["content-range"]
+            .to_str()
+            .unwrap()
+            .starts_with("bytes 0-14/")
+    );
+
+    // A page large enough is sent compressed, with its own ETag.
+    let body = "Some **news** of the day. ".repeat(50);
+    let page = serde_json::json!({"title": "News", "description": "Daily", "body": body});
+    server
+        .api_json("home.set", admin.clone(), page.to_string().as_bytes())
+        .await
+        .unwrap();
+    let (_, plain, body) = get(HeaderMap::new()).await;
+    assert_eq!("accept-encoding", plain["vary"]);
+    assert!(!plain.contains_key("content-encoding"));
+    let mut accept = HeaderMap::new();
+    accept.insert("accept-encoding", "gzip, deflate".parse().unwrap());
+    let uri = axum::http::Uri::from_static("/");
+    let response = serve_generated(State(server.clone()), uri, accept.clone()).await;
+    let (parts, gzip) = response.into_parts();
+    let gzip = axum::body::to_bytes(gzip, usize::MAX).await.unwrap();
+    assert_eq!("gzip", parts.headers["content-encoding"]);
+    assert_ne!(plain["etag"], parts.headers["etag"]);
+    assert!(gzip.len() < body.len());
+    let mut decoded = String::new();
+    std::io::Read::read_to_string(&mut flate2::read::GzDecoder::new(&gzip[..]), &mut decoded)
+        .unwrap();
+    assert_eq!(body, decoded);
+
+    accept.insert("if-none-match", parts.headers["etag"].clone());
+    assert_eq!(StatusCode::NOT_MODIFIED, get(accept).await.0);
 }
@@ -2,14 +2,15 @@
 //! The token created by the server, and send to the client.
 //!
 //! Format:
-//! - token: `"U0." + base64(creatation_time:u64 right right* hmac)`
+//! - token: `"U1." + base64(key_id:u8 creatation_time:u64 right right* hmac)`
 //! - right: `id_len:u4 level:u4 id:(id_len)u8`
+//!
 //! Always in big endian.
-//! Hmac is the signature of decoded
+//! Hmac is the signature of decoded, with the key `key_id`.
 //!
 //! ```txt
-//! token = U0.AAAAAGmkdDgSOBMqJBI0YvIvXJ0Zy9vqDWaolQ71F5Qi38N4U7mgnWe0fH06lQM
-//! key = b"Very Secret /// Very Secret /// "
+//! token = U1.AQAAAABppHQ4EjgTKiQSNBheqsmaesdpWTsSKy-YjfuvG9PUvpIRjMrig1DiW6n8
+//! key = TokenKey { id: 1, secret: b"Very Secret /// Very Secret /// " }
 //! now = 1772385336 (seconds since Epoch)
 //! UserToken {
 //!     level: UserLevel::EditData,
@@ -39,19 +40,135 @@ use axum::http::StatusCode;
 use crypto::mac::Mac;
 
 /// The base64 decoded token length
-pub const MAX_TOKEN_LEN: usize = 8 + 8 + UserToken::GROUP_MAX * 4 + 32;
+pub const MAX_TOKEN_LEN: usize = 1 + 8 + 8 + UserToken::GROUP_MAX * 4 + 32;
+
+/// Minimal length of a key secret, in bytes.
+pub const KEY_SECRET_MIN_LEN: usize = 32;
+
+/// The start of the tokens of this format.
+pub const TOKEN_PREFIX: &str = "U1.";
+
+/// A key to sign the user tokens.
+#[derive(Clone, PartialEq)]
+pub struct TokenKey {
+    /// Identifier written in the tokens, to find the key that check them.
+    pub id: u8,
+    pub secret: Vec<u8>,
+    /// Time in seconds since Epoch after which the tokens signed by this key
+    /// are rejected, `0` for never.
+    pub until: u64,
+}
+
+impl std::fmt::Debug for TokenKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenKey")
+            .field("id", &self.id)
+            .field("until", &self.until)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The keys of the server. The current key signs the new tokens, the
+/// previous keys only check the tokens during a grace period after a
+/// rotation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenKeys {
+    pub current: TokenKey,
+    pub previous: Vec<TokenKey>,
+}
+
+impl TokenKeys {
+    /// Parse the keys, one by line with the format `id secret [until]`, the
+    /// current key in first. Empty lines and lines beginning by `#` are
+    /// ignored.
+    ///
+    /// ```txt
+    /// # Current key
+    /// 2 a-new-secret-of-at-least-32-bytes-long
+    /// # Previous key, accepted until 2026-03-08
+    /// 1 the-old-secret-of-at-least-32-bytes-long 1772928000
+    /// ```
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut keys = Vec::new();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_ascii_whitespace();
+            let id = fields.next().and_then(|id| id.parse::<u8>().ok());
+            let secret = fields.next().map(|secret| secret.as_bytes().to_vec());
+            let until = match fields.next() {
+                Some(until) => until.parse::<u64>().ok(),
+                None => Some(0),
+            };
+            let (Some(id), Some(secret), Some(until), None) = (id, secret, until, fields.next())
+            else {
+                return Err(WrapError::http(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Invalid token key line, expected: 'id secret [until]'",
+                ));
+            };
+            if secret.len() < KEY_SECRET_MIN_LEN {
+                return Err(WrapError::http(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "The token key secret is too short, need 32 bytes",
+                ));
+            } else if keys.iter().any(|key: &TokenKey| key.id == id) {
+                return Err(WrapError::http(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Duplicated token key id",
+                ));
+            }
+            keys.push(TokenKey { id, secret, until });
+        }
+
+        if keys.is_empty() {
+            return Err(WrapError::http(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "No token key",
+            ));
+        }
+        let current = keys.remove(0);
+        Ok(TokenKeys {
+            current,
+            previous: keys,
+        })
+    }
+
+    /// A random key, the tokens are lost when the server restart.
+    pub fn random() -> Result<Self> {
+        let mut secret = vec![0u8; KEY_SECRET_MIN_LEN];
+        getrandom::fill(&mut secret).map_err(|_| {
+            WrapError::http(StatusCode::INTERNAL_SERVER_ERROR, "Random generation fail")
+        })?;
+        Ok(TokenKeys {
+            current: TokenKey {
+                id: 0,
+                secret,
+                until: 0,
+            },
+            previous: Vec::new(),
+        })
+    }
 
-const EXPIRED_DURATION: u64 = 7 * 12 * 3600;
+    /// Get the key that can check a token now.
+    fn get(&self, id: u8, now: u64) -> Option<&TokenKey> {
+        std::iter::once(&self.current)
+            .chain(self.previous.iter())
+            .find(|key| key.id == id && (key.until == 0 || now <= key.until))
+    }
+}
 
-pub fn encode_user_token(user: &UserToken, key: &[u8], now: u64) -> String {
+pub fn encode_user_token(user: &UserToken, key: &TokenKey, now: u64) -> String {
     let mut buff = [0u8; MAX_TOKEN_LEN];
 
-    // Add creation date
-    buff[..8].copy_from_slice(&(now.to_be_bytes()));
+    // Add key id and creation date
+    buff[0] = key.id;
+    buff[1..9].copy_from_slice(&(now.to_be_bytes()));
 
-    // Encode level and id.
-    let mut w = 8;
-    for (level, id) in user.iter() {
+    // Encode level and id, of the user then of its groups.
+    let mut w = 9;
+    for (level, id) in std::iter::once((user.level, user.id)).chain(user.iter()) {
         let len = match id {
             _ if id <= 0xFF => {
                 buff[w + 1] = id as u8;
@@ -81,70 +198,95 @@ pub fn encode_user_token(user: &UserToken, key: &[u8], now: u64) -> String {
     }
 
     // Sign token
-    let mut hasher = crypto::hmac::Hmac::new(crypto::sha2::Sha256::new(), key);
+    let mut hasher = crypto::hmac::Hmac::new(crypto::sha2::Sha256::new(), &key.secret);
     hasher.input(&buff[..w]);
     hasher.raw_result(&mut buff[w..w + 32]);
 
     // Prefix and encode token body
     use base64::Engine;
     use base64::engine::general_purpose::URL_SAFE_NO_PAD;
-    let mut out = String::from("U0.");
+    let mut out = String::from(TOKEN_PREFIX);
     URL_SAFE_NO_PAD.encode_string(&buff[..w + 32], &mut out);
 
     out
 }
 
-pub fn decode(token: &str, key: &[u8], now: u64) -> super::Result<UserToken> {
+/// The creation time of a token issued after a check of the password: after
+/// the `revoked` time of the user, even in the same second. The other tokens
+/// of this second are revoked.
+pub fn issue_time(now: u64, revoked: u64) -> u64 {
+    now.max(revoked.saturating_add(1))
+}
+
+/// Decode and check the token, return the user and the token creation time.
+/// The tokens older than `lifetime` seconds, or created until the `revoked`
+/// time of the user are rejected.
+pub fn decode(
+    token: &str,
+    keys: &TokenKeys,
+    now: u64,
+    lifetime: u64,
+    revoked: impl Fn(u32) -> u64,
+) -> super::Result<(UserToken, u64)> {
     use base64::Engine;
     // Check and remove prefix
-    if !token.starts_with("U0.") {
+    let Some(token) = token.strip_prefix(TOKEN_PREFIX) else {
         return Err(WrapError::http(
-            StatusCode::BAD_REQUEST,
-            "Invalid token prefix, expected prefix 'U0.'",
-        ));
-    }
-    let token = &token[3..];
+            StatusCode::UNAUTHORIZED,
+            "Invalid token prefix, expected prefix 'U1.'",
+        )
+        .with_code("token_invalid"));
+    };
 
     // Decode base64
     let mut data = [0u8; MAX_TOKEN_LEN];
     let len = base64::engine::general_purpose::URL_SAFE_NO_PAD
         .decode_slice(token, &mut data)
         .map_err(|err| {
-            WrapError::http(StatusCode::BAD_REQUEST, "base64 token decoding fail").add_err(err)
+            WrapError::http(StatusCode::UNAUTHORIZED, "base64 token decoding fail")
+                .with_code("token_invalid")
+                .add_err(err)
         })?;
     let data = &data[..len];
-    if data.len() < 8 + 2 + 32 {
-        return Err(WrapError::http(
-            StatusCode::BAD_REQUEST,
-            "The token is too short",
-        ));
+    if data.len() < 1 + 8 + 2 + 32 {
+        return Err(
+            WrapError::http(StatusCode::UNAUTHORIZED, "The token is too short")
+                .with_code("token_invalid"),
+        );
     }
 
     // Check expiration
-    let creation = u64::from_be_bytes(data[0..8].try_into().unwrap());
-    if EXPIRED_DURATION < now - creation {
-        return Err(WrapError::http(
-            StatusCode::BAD_REQUEST,
-            "The token is expired",
-        ));
+    let creation = u64::from_be_bytes(data[1..9].try_into().unwrap());
+    if lifetime < now.saturating_sub(creation) {
+        return Err(
+            WrapError::http(StatusCode::UNAUTHORIZED, "The token is expired")
+                .with_code("token_expired"),
+        );
     }
 
     // Check signature
+    let key = keys.get(data[0], now).ok_or_else(|| {
+        WrapError::http(
+            StatusCode::UNAUTHORIZED,
+            "The token key is unknown or expired",
+        )
+        .with_code("token_invalid")
+    })?;
     let signature_begin = data.len() - 32;
-    let mut hasher = crypto::hmac::Hmac::new(crypto::sha2::Sha256::new(), key);
+    let mut hasher = crypto::hmac::Hmac::new(crypto::sha2::Sha256::new(), &key.secret);
     hasher.input(&data[..signature_begin]);
     let mut processed_signature: [u8; 32] = [0u8; 32];
     hasher.raw_result(&mut processed_signature);
     let token_signature: &[u8] = &data[signature_begin..];
     if token_signature != processed_signature {
-        return Err(WrapError::http(
-            StatusCode::BAD_REQUEST,
-            "The token signature is invalid",
-        ));
+        return Err(
+            WrapError::http(StatusCode::UNAUTHORIZED, "The token signature is invalid")
+                .with_code("token_invalid"),
+        );
     }
 
     // Decode user data
-    let data = &data[8..signature_begin];
+    let data = &data[9..signature_begin];
     let (user_level, user_id, mut data) = decode_one(data)?;
 
     let mut user = UserToken {
@@ -155,14 +297,22 @@ pub fn decode(token: &str, key: &[u8], now: u64) -> super::Result<UserToken> {
 
     // Decode group data
     let mut i = 0;
-    while data.len() > 0 && i < UserToken::GROUP_MAX {
+    while !data.is_empty() && i < UserToken::GROUP_MAX {
         let (level, id, rest) = decode_one(data)?;
         user.groups[i] = (level, id);
         data = rest;
         i += 1;
     }
 
-    Ok(user)
+    // Check revocation
+    if creation <= revoked(user.id) {
+        return Err(
+            WrapError::http(StatusCode::UNAUTHORIZED, "The token is revoked")
+                .with_code("token_revoked"),
+        );
+    }
+
+    Ok((user, creation))
 }
 
 /// Parse on tuple of level and id.
@@ -176,30 +326,32 @@ fn decode_one(data: &[u8]) -> Result<(UserLevel, u32, &[u8])> {
         4 => UserLevel::SuperAdmin,
         _ => {
             return Err(WrapError::http(
-                StatusCode::BAD_REQUEST,
+                StatusCode::UNAUTHORIZED,
                 "The token contain value unknown or wrong syntax",
-            ));
+            )
+            .with_code("token_invalid"));
         }
     };
 
     let len = first as usize >> 4;
     if 4 < len {
         return Err(WrapError::http(
-            StatusCode::BAD_REQUEST,
+            StatusCode::UNAUTHORIZED,
             "The token contain value unknown or wrong syntax",
-        ));
+        )
+        .with_code("token_invalid"));
     } else if data.len() < len + 1 {
-        return Err(WrapError::http(
-            StatusCode::BAD_REQUEST,
-            "The token is too short",
-        ));
+        return Err(
+            WrapError::http(StatusCode::UNAUTHORIZED, "The token is too short")
+                .with_code("token_invalid"),
+        );
     }
 
     let data = &data[1..];
     let mut id = 0u32;
-    for i in 0..len {
+    for &b in &data[..len] {
         id <<= 8;
-        id += data[i] as u32;
+        id += b as u32;
     }
 
     Ok((level, id, &data[len..]))
@@ -207,10 +359,60 @@ fn decode_one(data: &[u8]) -> Result<(UserLevel, u32, &[u8])> {
 
 #[test]
 fn test_encoding() {
-    let token = "U0.AAAAAGmkdDgSOBMqJBI0YvIvXJ0Zy9vqDWaolQ71F5Qi38N4U7mgnWe0fH06lQM";
-    let key = b"Very Secret /// Very Secret /// ";
+    let token = "U1.AQAAAABppHQ4EjgTKiQSNBheqsmaesdpWTsSKy-YjfuvG9PUvpIRjMrig1DiW6n8";
+    let key = TokenKey {
+        id: 1,
+        secret: b"Very Secret /// Very Secret /// ".to_vec(),
+        until: 0,
+    };
+    let keys = TokenKeys {
+        current: key.clone(),
+        previous: Vec::new(),
+    };
     let user = UserToken::DEV_EDITOR;
 
-    assert_eq!(user, decode(token, key, 1772385340).unwrap());
-    assert_eq!(token, encode_user_token(&user, key, 1772385336));
+    assert_eq!(
+        (user.clone(), 1772385336),
+        decode(token, &keys, 1772385340, 3600, |_| 0).unwrap()
+    );
+    assert!(
+        decode(token, &keys, 1772385340, 3600, |id| (id == 56) as u64
+            * 1772385337)
+        .is_err()
+    );
+    // A token of the same second as the revocation is revoked, a new login
+    // is dated after it.
+    assert!(decode(token, &keys, 1772385340, 3600, |_| 1772385336).is_err());
+    let created = issue_time(1772385336, 1772385336);
+    let login = encode_user_token(&user, &key, created);
+    assert!(decode(&login, &keys, 1772385340, 3600, |_| 1772385336).is_ok());
+    assert_eq!(1772385340, issue_time(1772385340, 0));
+    assert_eq!(token, encode_user_token(&user, &key, 1772385336));
+
+    // Rotation: the previous key is accepted until its end.
+    let rotated = TokenKeys::parse(
+        "# Current key\n\
+         2 Another-secret-///-Another-secret\n\
+         \n\
+         1 Very-Secret-///-Very-Secret-///- 1772385339\n",
+    )
+    .unwrap();
+    assert_eq!((2, 0), (rotated.current.id, rotated.current.until));
+    assert_eq!(
+        (1, 1772385339),
+        (rotated.previous[0].id, rotated.previous[0].until)
+    );
+    let rotated = TokenKeys {
+        previous: vec![TokenKey {
+            until: 1772385339,
+            ..key
+        }],
+        ..rotated
+    };
+    assert!(decode(token, &rotated, 1772385338, 3600, |_| 0).is_ok());
+    assert!(decode(token, &rotated, 1772385340, 3600, |_| 0).is_err());
+
+    assert!(TokenKeys::parse("").is_err());
+    assert!(TokenKeys::parse("1 short").is_err());
+    assert!(TokenKeys::parse("1 Very-Secret-///-Very-Secret-///- soon").is_err());
 }
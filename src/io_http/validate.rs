@@ -0,0 +1,306 @@
+//! Declarative validation of the DTO, reporting all the invalid fields.
+//!
+//! The code of the error is `empty_values` when a value is missing, else the
+//! code given to the first invalid text, like `invalid_slug`, else
+//! `invalid_values`. Each field has the code of its failing rule.
+//!
+//! ```
+//! use brume::io_http::Validator;
+//!
+//! let (title, body) = ("", "Some text");
+//! let result = Validator::new()
+//!     .text("title", title, |title| title.required())
+//!     .text("body", body, |body| body.required().max_bytes(1 << 20))
+//!     .finish();
+//! let err = result.unwrap_err();
+//! assert_eq!(("empty_values", "title"), (err.code().as_str(), err.fields[0].field.as_str()));
+//! ```
+
+use super::DTO;
+use crate::*;
+use axum::http::StatusCode;
+use regex_lite::Regex;
+
+/// Collect the invalid fields of a request.
+#[derive(Debug, Default)]
+pub struct Validator {
+    fields: Vec<FieldError>,
+    /// The code and the message of the error given by the invalid fields.
+    codes: Vec<ErrorCode>,
+}
+
+/// The code and the message of an error.
+type ErrorCode = (&'static str, &'static str);
+
+/// The code of the error when a value is missing.
+const EMPTY: ErrorCode = ("empty_values", "Some values are empty in the data body");
+
+impl Validator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check a text with the rules, only the first failing rule is reported.
+    pub fn text<'a>(
+        mut self,
+        field: &'static str,
+        value: &'a str,
+        rules: impl FnOnce(Text<'a>) -> Text<'a>,
+    ) -> Self {
+        let text = rules(Text {
+            value,
+            code: None,
+            error: None,
+        });
+        if let Some((code, message, error_code)) = text.error {
+            match error_code {
+                Some(error_code) => self.codes.push(error_code),
+                None if code == "required" => self.codes.push(EMPTY),
+                None => (),
+            }
+            self.fields.push(FieldError {
+                field: field.to_string(),
+                code,
+                message,
+            });
+        }
+        self
+    }
+
+    /// Check a condition on any value.
+    pub fn check(
+        mut self,
+        field: &'static str,
+        valid: bool,
+        code: &'static str,
+        message: &'static str,
+    ) -> Self {
+        if !valid {
+            self.fields.push(FieldError {
+                field: field.to_string(),
+                code,
+                message: message.to_string(),
+            });
+        }
+        self
+    }
+
+    /// Check a nested DTO, its invalid fields are prefixed by the field name.
+    pub fn nested<T: DTO>(mut self, field: &'static str, value: &T) -> Self {
+        let Err(err) = value.check() else {
+            return self;
+        };
+        if let Some(code) = err.code {
+            self.codes.push((code, err.description()));
+        }
+        match err.fields.is_empty() {
+            true => self.fields.push(FieldError {
+                field: field.to_string(),
+                code: "invalid",
+                message: err.description().to_string(),
+            }),
+            false => self
+                .fields
+                .extend(err.fields.into_iter().map(|nested| FieldError {
+                    field: format!("{field}.{}", nested.field),
+                    ..nested
+                })),
+        }
+        self
+    }
+
+    /// Fail with all the invalid fields, if any.
+    pub fn finish(self) -> Result<()> {
+        if self.fields.is_empty() {
+            return Ok(());
+        }
+        let (code, message) = match self.codes.contains(&EMPTY) {
+            true => EMPTY,
+            false => self
+                .codes
+                .first()
+                .copied()
+                .unwrap_or(("invalid_values", "Some values are invalid")),
+        };
+        Err(WrapError {
+            fields: self.fields,
+            ..WrapError::http(StatusCode::BAD_REQUEST, message).with_code(code)
+        })
+    }
+}
+
+/// The rules of a text, checked in order until one fails.
+#[derive(Debug)]
+pub struct Text<'a> {
+    value: &'a str,
+    /// The code of the error for the next rules.
+    code: Option<ErrorCode>,
+    /// The code of the failed rule, its message, and the code of the error.
+    error: Option<(&'static str, String, Option<ErrorCode>)>,
+}
+
+impl Text<'_> {
+    fn rule(
+        mut self,
+        valid: impl FnOnce(&str) -> bool,
+        code: &'static str,
+        message: impl FnOnce() -> String,
+    ) -> Self {
+        if self.error.is_none() && !valid(self.value) {
+            self.error = Some((code, message(), self.code));
+        }
+        self
+    }
+
+    /// The code and the message of the error when one of the next rules
+    /// fails, like `invalid_slug`.
+    pub fn code(mut self, code: &'static str, message: &'static str) -> Self {
+        self.code = Some((code, message));
+        self
+    }
+
+    pub fn required(self) -> Self {
+        self.rule(
+            |value| !value.is_empty(),
+            "required",
+            || "The value is empty".to_string(),
+        )
+    }
+
+    pub fn min_chars(self, min: usize) -> Self {
+        self.rule(
+            |value| value.chars().count() >= min,
+            "too_short",
+            || format!("Need at least {min} characters"),
+        )
+    }
+
+    pub fn max_chars(self, max: usize) -> Self {
+        self.rule(
+            |value| value.chars().count() <= max,
+            "too_long",
+            || format!("Need at most {max} characters"),
+        )
+    }
+
+    /// Limit the size of the text encoded in UTF-8.
+    pub fn max_bytes(self, max: usize) -> Self {
+        self.rule(
+            |value| value.len() <= max,
+            "too_large",
+            || format!("Need at most {max} bytes"),
+        )
+    }
+
+    /// The text must match the regular expression, anchor it to match all
+    /// the text.
+    pub fn pattern(self, regex: &Regex, message: &'static str) -> Self {
+        self.rule(
+            |value| regex.is_match(value),
+            "pattern",
+            || message.to_string(),
+        )
+    }
+
+    /// The text is one of the values.
+    pub fn one_of(self, values: &[&str]) -> Self {
+        self.rule(
+            |value| values.contains(&value),
+            "not_allowed",
+            || format!("Need one of: {}", values.join(", ")),
+        )
+    }
+
+    /// Any other rule.
+    pub fn valid(self, valid: impl FnOnce(&str) -> bool, message: &'static str) -> Self {
+        self.rule(valid, "invalid", || message.to_string())
+    }
+}
+
+#[test]
+fn validate_fields() {
+    #[derive(Debug, Default, serde::Deserialize, schemars::JsonSchema)]
+    struct Target {
+        name: String,
+        kind: String,
+    }
+    impl DTO for Target {
+        fn check(&self) -> Result<()> {
+            Validator::new()
+                .text("name", &self.name, |name| name.required().max_chars(4))
+                .text("kind", &self.kind, |kind| kind.one_of(&["file", "folder"]))
+                .finish()
+        }
+    }
+
+    let slug = Regex::new("^[a-z]+$").unwrap();
+    let target = Target {
+        name: "é".repeat(5),
+        kind: "link".to_string(),
+    };
+    let err = Validator::new()
+        .text("title", "", |title| title.required().min_chars(2))
+        .text("slug", "Slug", |slug_value| {
+            slug_value
+                .required()
+                .pattern(&slug, "Need lowercase letters")
+        })
+        .text("body", "éé", |body| body.max_chars(2).max_bytes(3))
+        .text("ok", "value", |ok| {
+            ok.required().valid(|v| v != "x", "Not x")
+        })
+        .check("level", false, "not_allowed", "The level can not be None")
+        .nested("target", &target)
+        .finish()
+        .unwrap_err();
+    assert_eq!(Some(StatusCode::BAD_REQUEST), err.status_http);
+    assert_eq!("empty_values", err.code());
+    let fields: Vec<(&str, &str, &str)> = err
+        .fields
+        .iter()
+        .map(|field| (field.field.as_str(), field.code, field.message.as_str()))
+        .collect();
+    assert_eq!(
+        vec![
+            ("title", "required", "The value is empty"),
+            ("slug", "pattern", "Need lowercase letters"),
+            ("body", "too_large", "Need at most 3 bytes"),
+            ("level", "not_allowed", "The level can not be None"),
+            ("target.name", "too_long", "Need at most 4 characters"),
+            ("target.kind", "not_allowed", "Need one of: file, folder"),
+        ],
+        fields
+    );
+
+    // The code of the first invalid text, else the generic one.
+    let code = |value| {
+        Validator::new()
+            .text("title", "x", |title| title.min_chars(2))
+            .text("slug", value, |slug_value| {
+                slug_value
+                    .code("invalid_slug", "Invalid slug")
+                    .required()
+                    .pattern(&slug, "Need lowercase letters")
+            })
+            .finish()
+            .unwrap_err()
+            .code()
+    };
+    assert_eq!("invalid_slug", code("Slug"));
+    assert_eq!("invalid_slug", code(""));
+    assert_eq!("invalid_values", code("slug"));
+
+    assert!(
+        Validator::new()
+            .text("title", "A title", |title| title.required().min_chars(2))
+            .nested(
+                "target",
+                &Target {
+                    name: "name".to_string(),
+                    kind: "file".to_string(),
+                }
+            )
+            .finish()
+            .is_ok()
+    );
+}
@@ -1,26 +1,43 @@
 pub mod app_driver;
 pub mod bmime;
+pub mod config;
 mod error;
 pub mod io_http;
+pub mod log;
+pub mod markdown;
+pub mod theme;
 mod usertoken;
 
 pub use error::*;
-use std::sync::Arc;
+use io_http::{ByteStream, CachedPage, DavEntry, DavLocks, FileOutput, FileTarget, TokenKeys};
+use theme::Theme;
 pub use usertoken::*;
 
+/// Current time in seconds since Epoch.
+pub fn now() -> u64 {
+    std::time::UNIX_EPOCH
+        .elapsed()
+        .unwrap_or_default()
+        .as_secs()
+}
+
 #[async_trait::async_trait]
 pub trait HTTPState: Send + Sync {
     /// Constant static assets.
     /// `(absolute_path, mime_type, content)`
     const ASSETS: &[(&str, &str, &[u8])];
 
-    /// Already generated page
-    fn cached(&self, path: &str) -> Option<(&'static str, Arc<Vec<u8>>)>;
-    /// Page returned when not found ressource.
-    const ERROR_404: &[u8];
+    /// Already generated page, if the user can see it.
+    fn cached(&self, path: &str, user: &UserToken) -> Result<Option<CachedPage>>;
+
+    /// Templates of the HTML pages, and the error pages.
+    fn theme(&self) -> &Theme;
 
-    /// Key to sign user token.
-    fn user_token_key<'a>(&'a self) -> &'a [u8];
+    /// Keys to sign and check the user tokens.
+    fn user_token_keys(&self) -> &TokenKeys;
+
+    /// Validity duration of a user token, in seconds.
+    fn token_lifetime(&self) -> u64;
 
     async fn api_json(
         &self,
@@ -28,4 +45,64 @@ pub trait HTTPState: Send + Sync {
         user: UserToken,
         data: &[u8],
     ) -> Result<(Option<UserToken>, Vec<u8>)>;
+
+    /// Get a new token of the user, if its rights changed after the creation
+    /// of its token.
+    fn current_user(&self, user: &UserToken, created: u64) -> Option<UserToken>;
+
+    /// Time in seconds since Epoch, the tokens of the user created before
+    /// this time are revoked.
+    fn sessions_revoked(&self, user: u32) -> u64;
+
+    /// Get the token of the user if the password match.
+    async fn login(&self, login: &str, password: String) -> Result<UserToken>;
+
+    /// Store an uploaded file, return its JSON description.
+    async fn file_put(
+        &self,
+        user: UserToken,
+        target: FileTarget,
+        input: &mut dyn ByteStream,
+    ) -> Result<Vec<u8>>;
+
+    /// Open a stored file to send it.
+    async fn file_get(&self, user: UserToken, id: u64) -> Result<FileOutput>;
+
+    /// The WebDAV locks.
+    fn dav_locks(&self) -> &DavLocks;
+
+    /// Get the WebDAV entry at the path, followed by its children if asked.
+    async fn dav_find(
+        &self,
+        user: UserToken,
+        path: &[String],
+        children: bool,
+    ) -> Result<Vec<DavEntry>>;
+
+    /// Open the file at the WebDAV path.
+    async fn dav_get(&self, user: UserToken, path: &[String]) -> Result<FileOutput>;
+
+    /// Store a file at the WebDAV path, return `true` if it is created.
+    async fn dav_put(
+        &self,
+        user: UserToken,
+        path: &[String],
+        input: &mut dyn ByteStream,
+    ) -> Result<bool>;
+
+    /// Create a folder at the WebDAV path.
+    async fn dav_mkcol(&self, user: UserToken, path: &[String]) -> Result<()>;
+
+    /// Delete the node at the WebDAV path.
+    async fn dav_delete(&self, user: UserToken, path: &[String]) -> Result<()>;
+
+    /// Move or copy a node, return `true` if the destination is created.
+    async fn dav_transfer(
+        &self,
+        user: UserToken,
+        from: &[String],
+        to: &[String],
+        copy: bool,
+        overwrite: bool,
+    ) -> Result<bool>;
 }
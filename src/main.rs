@@ -1,12 +1,158 @@
 use std::sync::Arc;
 
-use brume::app_driver::State;
-use brume::io_http;
+use brume::app_driver::{
+    State,
+    blob::{self, BlobStore},
+    maintenance,
+    storage::DirStorage,
+};
+use brume::config::{self, Command, Config};
+use brume::io_http::{self, TokenKeys};
+use brume::log;
+use brume::theme::Theme;
+
+fn main() {
+    let args = std::env::args().skip(1);
+    let (config, command) =
+        Config::load(args, |var| std::env::var(var).ok()).unwrap_or_else(|err| exit(err));
+    if command == Command::Help {
+        print!("{}", config::HELP);
+        return;
+    }
+    log::set_level(config.log_level);
+
+    // The storage writes and the file system calls block their thread.
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    if config.worker_threads > 0 {
+        runtime.worker_threads(config.worker_threads);
+    }
+    runtime
+        .enable_all()
+        .build()
+        .unwrap_or_else(|err| exit(format!("Can not start the runtime: {err}")))
+        .block_on(start(config, command));
+}
+
+async fn start(config: Config, command: Command) {
+    let token_keys = match (token_keys(&config), &command) {
+        (Ok(Some(keys)), _) => keys,
+        (Err(err), _) => exit(err),
+        (Ok(None), Command::TokenIssue { .. }) => exit(NO_KEY.to_string()),
+        (Ok(None), Command::Serve | Command::CheckConfig) if !cfg!(debug_assertions) => {
+            exit(NO_KEY.to_string())
+        }
+        (Ok(None), Command::Serve) => {
+            brume::log!(Warn, "No token key, the sessions are lost at restart");
+            TokenKeys::random().unwrap()
+        }
+        (Ok(None), _) => TokenKeys::random().unwrap(),
+    };
+    let theme = Theme::load(config.theme_dir.as_deref())
+        .unwrap_or_else(|err| exit(format!("Invalid theme: {err}")));
+    if command == Command::CheckConfig {
+        println!("{}", serde_json::to_string_pretty(&config).unwrap());
+        return;
+    }
+
+    let storage = DirStorage::open(config.data_dir.join("db")).unwrap();
+    let blobs = BlobStore::open(config.data_dir.join("blob")).unwrap();
+    let mut state = State::new(Box::new(storage), blobs, token_keys, theme).unwrap();
+    state.upload_max = config.upload_max;
+    state.token_lifetime = config.token_lifetime;
+    if command != Command::Serve {
+        run(&state, command)
+            .await
+            .unwrap_or_else(|err| exit(err.to_string()));
+        return;
+    }
+
+    let state = Arc::new(state);
+    let password = std::env::var("BRUME_ADMIN_PASSWORD").ok();
+    let generated = password.is_none();
+    if let Some(password) = state.first_admin(password).await.unwrap() {
+        brume::log!(Info, "Created the user 'admin'");
+        // On the standard output of the first run, never in the log stream.
+        if generated {
+            println!("The password of the user 'admin': {password}");
+        }
+    }
+    state.spawn_tasks();
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() {
-    let state = Arc::new(State::new().unwrap());
     let app = io_http::router().with_state(state);
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    let mut servers = tokio::task::JoinSet::new();
+    for addr in &config.listen {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .unwrap_or_else(|err| exit(format!("Can not listen on {addr}: {err}")));
+        brume::log!(Info, "Listening on {addr}");
+        servers.spawn(axum::serve(listener, app.clone()).into_future());
+    }
+    while let Some(result) = servers.join_next().await {
+        result.unwrap().unwrap();
+    }
+}
+
+/// Run a maintenance command.
+async fn run(state: &State, command: Command) -> brume::Result<()> {
+    match command {
+        Command::UserAdd {
+            login,
+            name,
+            level,
+            password,
+        } => {
+            let password = maintenance::user_add(state, login, name, level, password).await?;
+            println!("{password}");
+        }
+        Command::UserResetPassword { login, password } => {
+            let password = maintenance::reset_password(state, &login, password).await?;
+            println!("{password}");
+        }
+        Command::GroupAdd { name, owner } => {
+            let id = maintenance::group_add(state, name, &owner).await?;
+            println!("{id}");
+        }
+        Command::TokenIssue { user } => println!("{}", maintenance::token_issue(state, user)?),
+        Command::Fsck { repair } => {
+            let checked = blob::check(state, repair).await?;
+            println!("{}", serde_json::to_string_pretty(&checked).unwrap());
+            if !checked.is_ok() && !repair {
+                exit("The blobs are inconsistent, run with --repair".to_string());
+            }
+        }
+        Command::Export { file } => {
+            let exported = maintenance::export(state, &file).await?;
+            println!("{}", serde_json::to_string(&exported).unwrap());
+        }
+        Command::Import { file } => {
+            let imported = maintenance::import(state, &file).await?;
+            println!("{}", serde_json::to_string(&imported).unwrap());
+        }
+        Command::Serve | Command::CheckConfig | Command::Help => {}
+    }
+    Ok(())
+}
+
+const NO_KEY: &str = "No token key: set BRUME_TOKEN_KEY_FILE or BRUME_TOKEN_KEY, see the README";
+
+/// Load the token keys from the configured file, or from the variable
+/// `BRUME_TOKEN_KEY`. Without keys, a release build refuses to serve.
+fn token_keys(config: &Config) -> Result<Option<TokenKeys>, String> {
+    let text = match &config.token_key_file {
+        Some(path) => Some(std::fs::read_to_string(path).map_err(|err| {
+            format!(
+                "Can not read the token key file '{}': {err}",
+                path.display()
+            )
+        })?),
+        None => std::env::var("BRUME_TOKEN_KEY").ok(),
+    };
+    text.map(|text| TokenKeys::parse(&text))
+        .transpose()
+        .map_err(|err| format!("Invalid token keys: {err}"))
+}
+
+fn exit(err: String) -> ! {
+    eprintln!("{err}");
+    std::process::exit(1);
 }
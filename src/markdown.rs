@@ -0,0 +1,91 @@
+//! Markdown rendering to HTML, safe for the content of the users.
+//!
+//! The raw HTML of the source is escaped, and the links only keep the
+//! schemes `http`, `https` and `mailto`, or a relative URL.
+
+use pulldown_cmark::{Event, Options, Parser, Tag};
+
+/// Render the Markdown text to a HTML fragment.
+pub fn to_html(text: &str) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+    let events = Parser::new_ext(text, options).map(|event| match event {
+        Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        event => event,
+    });
+    let mut html = String::with_capacity(text.len() * 3 / 2);
+    pulldown_cmark::html::push_html(&mut html, events);
+    html
+}
+
+/// Keep only the URL with a safe scheme, or without scheme.
+fn safe_url(url: pulldown_cmark::CowStr<'_>) -> pulldown_cmark::CowStr<'_> {
+    let scheme = url
+        .split_once(':')
+        .map(|(scheme, _)| scheme)
+        .filter(|scheme| !scheme.contains(['/', '?', '#']));
+    match scheme.map(str::to_ascii_lowercase).as_deref() {
+        None | Some("http" | "https" | "mailto") => url,
+        Some(_) => "".into(),
+    }
+}
+
+/// Escape the text to write it in HTML, also in an attribute value.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[test]
+fn safe_markdown() {
+    assert_eq!(
+        "<h1>Title</h1>\n<p><em>Hello</em> <a href=\"https://example.com\">world</a></p>\n",
+        to_html("# Title\n\n*Hello* [world](https://example.com)")
+    );
+    assert_eq!(
+        "&lt;script&gt;alert(1)&lt;/script&gt;",
+        to_html("<script>alert(1)</script>")
+    );
+    assert_eq!(
+        "<p><a href=\"\">click</a> <a href=\"/wiki/page#top\">page</a></p>\n",
+        to_html("[click](javascript:alert(1)) [page](/wiki/page#top)")
+    );
+    assert_eq!(
+        "&lt;a href=&quot;x&quot;&gt;&amp;",
+        escape("<a href=\"x\">&")
+    );
+}
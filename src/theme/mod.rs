@@ -0,0 +1,176 @@
+//! The HTML templates of the generated pages, in the Jinja syntax.
+//!
+//! The default theme is built in the binary. A theme directory replaces its
+//! templates by the files of the same name, and can add other partials.
+//! The values are escaped, except the HTML given with [`safe`], like the
+//! rendered Markdown.
+//!
+//! | Template          | Values                                           |
+//! | ----------------- | ------------------------------------------------ |
+//! | `layout.html`     | `title`, `description`, blocks `head` and `main` |
+//! | `nav.html`        | included by the layout                           |
+//! | `home.html`       | `body`                                           |
+//! | `wiki_page.html`  | `slug`, `body`, `version`, `modified`            |
+//! | `wiki_index.html` | `pages` with `path`, `slug`, `title`, `modified` |
+//! | `folder.html`     | `parent`, `entries` with `href` and the WebDAV entry |
+//! | `error.html`      | `status`, the code                               |
+
+use crate::*;
+use axum::{body::Bytes, http::StatusCode};
+use minijinja::{AutoEscape, Environment, Value};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The default templates.
+const TEMPLATES: &[(&str, &str)] = &[
+    ("layout.html", include_str!("layout.html")),
+    ("nav.html", include_str!("nav.html")),
+    ("home.html", include_str!("home.html")),
+    ("wiki_page.html", include_str!("wiki_page.html")),
+    ("wiki_index.html", include_str!("wiki_index.html")),
+    ("folder.html", include_str!("folder.html")),
+    ("error.html", include_str!("error.html")),
+];
+
+/// The error pages rendered at the load.
+const ERRORS: &[StatusCode] = &[
+    StatusCode::NOT_FOUND,
+    StatusCode::METHOD_NOT_ALLOWED,
+    StatusCode::INTERNAL_SERVER_ERROR,
+];
+
+#[derive(Debug)]
+pub struct Theme {
+    env: Environment<'static>,
+    /// The rendered error pages, indexed by status code.
+    errors: BTreeMap<u16, Bytes>,
+}
+
+impl Theme {
+    /// Load the default theme, with the `*.html` files of the directory in
+    /// place of the default templates. Fail if a template is invalid.
+    pub fn load(dir: Option<&Path>) -> Result<Self> {
+        let mut templates: BTreeMap<String, String> = TEMPLATES
+            .iter()
+            .map(|(name, source)| (name.to_string(), source.to_string()))
+            .collect();
+        if let Some(dir) = dir {
+            for entry in std::fs::read_dir(dir).map_err(err_theme)? {
+                let path = entry.map_err(err_theme)?.path();
+                let name = path.file_name().and_then(|name| name.to_str());
+                if let Some(name) = name.filter(|name| name.ends_with(".html")) {
+                    let source = std::fs::read_to_string(&path).map_err(err_theme)?;
+                    templates.insert(name.to_string(), source);
+                }
+            }
+        }
+
+        let mut env = Environment::new();
+        env.set_trim_blocks(true);
+        env.set_keep_trailing_newline(true);
+        env.set_formatter(|output, state, value| match state.auto_escape() {
+            AutoEscape::Html if !value.is_safe() && value.as_str().is_some() => {
+                output.write_str(&markdown::escape(value.as_str().unwrap_or_default()))?;
+                Ok(())
+            }
+            _ => minijinja::escape_formatter(output, state, value),
+        });
+        for (name, source) in templates {
+            env.add_template_owned(name, source).map_err(err_template)?;
+        }
+
+        let mut theme = Theme {
+            env,
+            errors: BTreeMap::new(),
+        };
+        for status in ERRORS {
+            let page = theme.render_error(*status)?;
+            theme.errors.insert(status.as_u16(), Bytes::from(page));
+        }
+        Ok(theme)
+    }
+
+    /// Render the template with the values, any serializable value.
+    pub fn render(&self, name: &str, values: impl Serialize) -> Result<String> {
+        self.env
+            .get_template(name)
+            .and_then(|template| template.render(values))
+            .map_err(err_template)
+    }
+
+    /// The page of the error status.
+    pub fn error_page(&self, status: StatusCode) -> Bytes {
+        match self.errors.get(&status.as_u16()) {
+            Some(page) => page.clone(),
+            None => match self.render_error(status) {
+                Ok(page) => Bytes::from(page),
+                Err(_) => Bytes::from(format!("{status}\r\n")),
+            },
+        }
+    }
+
+    fn render_error(&self, status: StatusCode) -> Result<String> {
+        let description = match status {
+            StatusCode::NOT_FOUND => "The page does not exist.",
+            StatusCode::METHOD_NOT_ALLOWED => "The method is not allowed on this page.",
+            status if status.is_server_error() => "The server failed, retry later.",
+            _ => "The request failed.",
+        };
+        self.render(
+            "error.html",
+            minijinja::context! {
+                title => status.to_string(),
+                description,
+                status => status.as_u16(),
+            },
+        )
+    }
+}
+
+impl Default for Theme {
+    /// The built in theme.
+    fn default() -> Self {
+        Theme::load(None).expect("valid default theme")
+    }
+}
+
+/// Mark the HTML as safe, to insert it without escaping.
+pub fn safe(html: String) -> Value {
+    Value::from_safe_string(html)
+}
+
+fn err_theme(err: std::io::Error) -> WrapError {
+    WrapError::new("Can not read the theme directory").add_err(err)
+}
+
+fn err_template(err: minijinja::Error) -> WrapError {
+    WrapError::http(StatusCode::INTERNAL_SERVER_ERROR, "Template fail").add_err(err)
+}
+
+#[test]
+fn theme_templates() {
+    let theme = Theme::default();
+    let values = minijinja::context! {
+        title => "A <b>title</b>",
+        description => "It's",
+        body => safe("<p>Body</p>\n".to_string()),
+    };
+    let page = theme.render("home.html", &values).unwrap();
+    assert!(page.contains("<title>A &lt;b&gt;title&lt;/b&gt;</title>"));
+    assert!(page.contains("content=\"It&#39;s\""));
+    assert!(page.contains("<main>\n<p>Body</p>\n</main>"));
+    let error = theme.error_page(StatusCode::NOT_FOUND);
+    assert!(String::from_utf8_lossy(&error).contains("<title>404 Not Found</title>"));
+
+    let dir = crate::app_driver::storage::test_dir("theme_templates");
+    std::fs::write(dir.join("nav.html"), "<nav>Custom {{ title }}</nav>\n").unwrap();
+    std::fs::write(dir.join("notes.txt"), "{% broken").unwrap();
+    let theme = Theme::load(Some(&dir)).unwrap();
+    let page = theme.render("home.html", &values).unwrap();
+    assert!(page.contains("<nav>Custom A &lt;b&gt;title&lt;/b&gt;</nav>"));
+
+    std::fs::write(dir.join("error.html"), "{% broken").unwrap();
+    assert!(Theme::load(Some(&dir)).is_err());
+    assert!(Theme::load(Some(&dir.join("missing"))).is_err());
+}
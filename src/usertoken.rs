@@ -1,5 +1,15 @@
 /// User level to allow or deny service acess.
-#[derive(Debug, Copy, Clone, Default, PartialEq, PartialOrd)]
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    Default,
+    PartialEq,
+    PartialOrd,
+    serde::Serialize,
+    serde::Deserialize,
+    schemars::JsonSchema,
+)]
 pub enum UserLevel {
     /// No right.
     #[default]
@@ -34,18 +44,25 @@ pub struct UserToken {
 impl UserToken {
     pub const GROUP_MAX: usize = 15;
 
-    pub fn allow(&self, target_id: u32, target_level: UserLevel) -> bool {
-        for (level, id) in self.iter() {
-            if id == target_id {
-                return target_level <= level;
-            }
+    /// Check the user is a member of the group, with at least the level.
+    /// The user identifier is not a group.
+    pub fn allow(&self, group: u32, target_level: UserLevel) -> bool {
+        match self.group_level(group) {
+            UserLevel::None => false,
+            level => target_level <= level,
         }
-        false
     }
 
+    /// The level of the user in the group, `None` if it is not a member.
+    pub fn group_level(&self, group: u32) -> UserLevel {
+        self.iter()
+            .find(|&(_, id)| id == group)
+            .map_or(UserLevel::None, |(level, _)| level)
+    }
+
+    /// The groups of the user with its level in each, without the user.
     pub fn iter(&self) -> impl Iterator<Item = (UserLevel, u32)> {
-        std::iter::once((self.level, self.id))
-            .chain(self.groups.into_iter().take_while(|&(_, id)| id != 0))
+        self.groups.into_iter().take_while(|&(_, id)| id != 0)
     }
 
     pub const DEV_EDITOR: UserToken = UserToken {
@@ -80,4 +97,11 @@ fn user_token_allow() {
     assert!(user.allow(36, UserLevel::EditData));
     assert!(!user.allow(36, UserLevel::Admin));
     assert!(user.allow(42, UserLevel::EditData));
+    assert!(!user.allow(7, UserLevel::None));
+    assert_eq!(UserLevel::Admin, user.group_level(42));
+
+    // The user and the groups are distinct.
+    user.id = 7;
+    user.level = UserLevel::Admin;
+    assert!(!user.allow(7, UserLevel::SeeData));
 }